@@ -81,6 +81,7 @@ async fn main(spawner: Spawner) {
         },
     );
     info!("Second Core Interrupt Executor started!");
+    trace_marker!("SecondCoreStarted");
 
     // Spawn tasks on core 0
     spawner.spawn(hello_world_task()).unwrap();
@@ -0,0 +1,9 @@
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    // Provide the rustmeter.x linker script (used with `-C link-arg=-Trustmeter.x`)
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("rustmeter.x"), include_str!("rustmeter.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    println!("cargo:rerun-if-changed=rustmeter.x");
+}
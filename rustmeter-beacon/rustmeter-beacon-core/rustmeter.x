@@ -0,0 +1,9 @@
+/* Monitor registry of rustmeter-beacon (`monitor-registry` feature).
+   Kept in the ELF for the host but never loaded onto the target (like the .defmt section). */
+SECTIONS
+{
+  .rustmeter_monitors 0 (INFO) :
+  {
+    *(.rustmeter_monitors .rustmeter_monitors.*);
+  }
+}
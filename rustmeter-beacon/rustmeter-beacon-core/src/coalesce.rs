@@ -0,0 +1,136 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use critical_section::Mutex;
+
+/// Coalescers that recorded at least one call (newest first), reported by [`rustmeter_flush`]
+///
+/// [`rustmeter_flush`]: crate::rustmeter_flush
+static COALESCERS: Mutex<Cell<Option<&'static MonitorCoalescer>>> = Mutex::new(Cell::new(None));
+
+/// Call statistics of a coalesced monitor (`#[monitor_fn(coalesce)]`) within one window
+pub struct CoalescedCalls {
+    pub count: u32,
+    pub total_us: u32,
+    pub window_us: u32,
+}
+
+/// Accumulates calls of a hot function and reports them once per window instead of
+/// emitting start / end events for every call.
+///
+/// Uses plain loads / stores (no atomic RMW) like the sampling counter, so concurrent calls
+/// of the same function (e.g. from two cores) may be lost from the statistics.
+pub struct MonitorCoalescer {
+    name: &'static str,
+    count: AtomicU32,
+    total_us: AtomicU32,
+    window_start_us: AtomicU32,
+    last_end_us: AtomicU32,
+    /// `None` until the coalescer is added to [`COALESCERS`], then the coalescer after it
+    next: Mutex<Cell<Option<Option<&'static MonitorCoalescer>>>>,
+}
+
+impl MonitorCoalescer {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            count: AtomicU32::new(0),
+            total_us: AtomicU32::new(0),
+            window_start_us: AtomicU32::new(0),
+            last_end_us: AtomicU32::new(0),
+            next: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Record a call (timestamps in µs, all arithmetic is wrapping). Returns the accumulated
+    /// statistics once `window_us` has passed since the window started and resets them for the
+    /// next window
+    pub fn record(
+        &'static self,
+        start_us: u32,
+        end_us: u32,
+        window_us: u32,
+    ) -> Option<CoalescedCalls> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            self.register();
+            self.window_start_us.store(start_us, Ordering::Relaxed);
+        }
+
+        let count = count.wrapping_add(1);
+        let total_us = self
+            .total_us
+            .load(Ordering::Relaxed)
+            .wrapping_add(end_us.wrapping_sub(start_us));
+
+        let elapsed_us = end_us.wrapping_sub(self.window_start_us.load(Ordering::Relaxed));
+        if elapsed_us < window_us {
+            self.count.store(count, Ordering::Relaxed);
+            self.total_us.store(total_us, Ordering::Relaxed);
+            self.last_end_us.store(end_us, Ordering::Relaxed);
+            return None;
+        }
+
+        // Window is over ==> report and start a new one with the next call
+        self.count.store(0, Ordering::Relaxed);
+        self.total_us.store(0, Ordering::Relaxed);
+        Some(CoalescedCalls {
+            count,
+            total_us,
+            window_us: elapsed_us,
+        })
+    }
+
+    /// Take the calls of the window that is still open (up to the end of its last call)
+    fn take_partial_window(&self) -> Option<CoalescedCalls> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+
+        self.count.store(0, Ordering::Relaxed);
+        let total_us = self.total_us.load(Ordering::Relaxed);
+        self.total_us.store(0, Ordering::Relaxed);
+        Some(CoalescedCalls {
+            count,
+            total_us,
+            window_us: self
+                .last_end_us
+                .load(Ordering::Relaxed)
+                .wrapping_sub(self.window_start_us.load(Ordering::Relaxed)),
+        })
+    }
+
+    /// Add the coalescer to [`COALESCERS`] (once)
+    fn register(&'static self) {
+        critical_section::with(|cs| {
+            let next = self.next.borrow(cs);
+            if next.get().is_none() {
+                let head = COALESCERS.borrow(cs);
+                next.set(Some(head.get()));
+                head.set(Some(self));
+            }
+        });
+    }
+}
+
+/// Report the open windows of all coalesced monitors, so calls since their last full window are
+/// not lost when the trace ends. Called by [`rustmeter_flush`]
+///
+/// [`rustmeter_flush`]: crate::rustmeter_flush
+pub(crate) fn flush_coalesced_monitors() {
+    let mut coalescer = critical_section::with(|cs| COALESCERS.borrow(cs).get());
+    while let Some(current) = coalescer {
+        if let Some(calls) = current.take_partial_window() {
+            defmt::info!(
+                "@EVENT_MONITOR_AGGREGATE(function_name={=str},count={=u32},total_us={=u32},window_us={=u32},core_id={})",
+                current.name,
+                calls.count,
+                calls.total_us,
+                calls.window_us,
+                crate::get_current_core_id()
+            );
+        }
+        coalescer = critical_section::with(|cs| current.next.borrow(cs).get().flatten());
+    }
+}
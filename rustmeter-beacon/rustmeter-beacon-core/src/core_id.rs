@@ -1,8 +1,17 @@
 #[allow(unreachable_code)]
 #[inline(always)]
 /// Returns the core ID of the currently executing core based on the target architecture.
-/// Supports various architectures including ESP32 (Xtensa and RISC-V), RP2040, and STM32 (single or H7 dual-core).
+/// Supports various architectures including ESP32 (Xtensa and RISC-V), RP2040 / RP235x (with the
+/// `rp2040`, `rp235xa` or `rp235xb` feature), and STM32 (single or H7 dual-core).
 pub fn get_current_core_id() -> u8 {
+    //
+    // 0. Firmware only ever runs on the first core (skips the runtime core id read)
+    //
+    #[cfg(feature = "single-core")]
+    {
+        return 0;
+    }
+
     //
     // 1. ESP32 via esp-hal (xtensa or riscv32) [can be dual-core]
     //
@@ -16,7 +25,21 @@ pub fn get_current_core_id() -> u8 {
         return esp_hal::system::Cpu::current() as u8;
     }
 
-    // TODO: Handle RP2040 dual-core case
+    //
+    // 2. RP2040 / RP235x (A and B variant) [dual-core]
+    //    Both read the core number from the SIO CPUID register. The RP2350B only differs in
+    //    package and GPIO count, SIO and memory map are the same as on the RP2350A. Only on Arm,
+    //    so host builds with all features (clippy, tests) never read the address
+    //
+    #[cfg(all(
+        target_arch = "arm",
+        any(feature = "rp2040", feature = "rp235xa", feature = "rp235xb")
+    ))]
+    {
+        const SIO_CPUID: *const u32 = 0xd000_0000 as *const u32;
+        // SAFETY: SIO_CPUID is a read-only, always accessible register of the single-cycle IO block
+        return unsafe { core::ptr::read_volatile(SIO_CPUID) } as u8;
+    }
 
     //
     // Fallback: Unknown target, probably single-core
@@ -0,0 +1,50 @@
+//! CPU cycle timestamps from the DWT cycle counter (Cortex-M3 and newer)
+
+const DEMCR: *mut u32 = 0xE000_EDFC as *mut u32;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL: *mut u32 = 0xE000_1000 as *mut u32;
+const DWT_CTRL_CYCCNTENA: u32 = 1;
+const DWT_CYCCNT: *const u32 = 0xE000_1004 as *const u32;
+
+/// Start the DWT cycle counter. Call this once at startup before the first log when using
+/// [`cycle_timestamp!`].
+///
+/// # Safety
+///
+/// Only call this on a Cortex-M with DWT (M3 and newer, not M0/M0+). It writes to the debug
+/// registers and must not race with a debugger or other code configuring them.
+pub unsafe fn enable_cycle_counter() {
+    // SAFETY: both registers exist on Cortex-M3 and newer (guaranteed by the caller)
+    unsafe {
+        core::ptr::write_volatile(DEMCR, core::ptr::read_volatile(DEMCR) | DEMCR_TRCENA);
+        core::ptr::write_volatile(
+            DWT_CTRL,
+            core::ptr::read_volatile(DWT_CTRL) | DWT_CTRL_CYCCNTENA,
+        );
+    }
+}
+
+/// Current value of the DWT cycle counter (wraps after 2^32 cycles)
+#[inline(always)]
+pub fn get_tracing_time_cycles() -> u32 {
+    // SAFETY: CYCCNT is a read-only access to an always mapped register on Cortex-M3 and newer
+    unsafe { core::ptr::read_volatile(DWT_CYCCNT) }
+}
+
+#[macro_export]
+/// Use CPU cycles as defmt timestamp instead of `embassy_time::Instant` microseconds.
+///
+/// Replaces your own `defmt::timestamp!` (there can only be one). Start the counter with
+/// [`enable_cycle_counter`] and run the host with `rustmeter --cpu-hz <HZ>` so it converts the
+/// cycles to time.
+///
+/// ```rust,ignore
+/// rustmeter_beacon::cycle_timestamp!();
+///
+/// unsafe { rustmeter_beacon::enable_cycle_counter() };
+/// ```
+macro_rules! cycle_timestamp {
+    () => {
+        defmt::timestamp!("{=u32}", rustmeter_beacon::get_tracing_time_cycles());
+    };
+}
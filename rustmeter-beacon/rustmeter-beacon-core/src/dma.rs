@@ -0,0 +1,74 @@
+use crate::get_current_core_id;
+
+#[macro_export]
+/// Traces a DMA transfer on a channel until the returned [`DmaTransferGuard`] is dropped.
+///
+/// DMA runs next to the CPU, so the host shows the transfer as `DMA transfer` slice on a track
+/// per channel (process `DMA`), where it can be compared with the CPU work of the same time.
+/// Wire it into your DMA setup and completion code; rustmeter does not hook into any HAL. The
+/// channel is any `u8` that identifies the channel to you.
+///
+/// If the transfer completes in an interrupt handler, call [`dma_transfer_begin`] when starting
+/// it and [`dma_transfer_end`] in the handler instead.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let _transfer = trace_dma!(2);
+/// spi.transfer_dma(&mut buffer).await;
+/// // ends when `_transfer` goes out of scope
+/// ```
+macro_rules! trace_dma {
+    ($channel:expr) => {
+        rustmeter_beacon::DmaTransferGuard::begin($channel)
+    };
+}
+
+/// Guard of a DMA transfer traced via [`trace_dma!`]. Reports the end of the transfer when
+/// dropped
+#[must_use = "the transfer ends when the guard is dropped"]
+pub struct DmaTransferGuard {
+    channel: u8,
+}
+
+impl DmaTransferGuard {
+    /// Report the start of a transfer on the channel
+    pub fn begin(channel: u8) -> Self {
+        dma_transfer_begin(channel);
+        Self { channel }
+    }
+
+    /// Channel of the transfer
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+}
+
+impl Drop for DmaTransferGuard {
+    fn drop(&mut self) {
+        dma_transfer_end(self.channel);
+    }
+}
+
+/// Report the start of a DMA transfer on the channel (see [`trace_dma!`])
+pub fn dma_transfer_begin(channel: u8) {
+    if !cfg!(feature = "disabled") {
+        defmt::info!(
+            "@EVENT_DMA_BEGIN(channel={=u8},core_id={})",
+            channel,
+            get_current_core_id()
+        );
+    }
+}
+
+/// Report the end of the DMA transfer on the channel, e.g. from its completion interrupt (see
+/// [`trace_dma!`])
+pub fn dma_transfer_end(channel: u8) {
+    if !cfg!(feature = "disabled") {
+        defmt::info!(
+            "@EVENT_DMA_END(channel={=u8},core_id={})",
+            channel,
+            get_current_core_id()
+        );
+    }
+}
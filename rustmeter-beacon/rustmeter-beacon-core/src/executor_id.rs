@@ -0,0 +1,70 @@
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Whether monitor start events carry the id of the executor polling on this core
+/// (`monitor-executor-id` feature)
+#[doc(hidden)]
+pub const MONITOR_EXECUTOR_ID: bool = cfg!(feature = "monitor-executor-id");
+
+const MAX_CORES: usize = 2;
+const MAX_NESTED_EXECUTORS: usize = 4;
+
+/// Executors currently polling on one core. Interrupt executors preempt in LIFO order, so a
+/// small stack per core is enough. Only touched from its own core (plain load / store to support
+/// targets without atomic RMW)
+struct ExecutorStack {
+    depth: AtomicUsize,
+    executor_ids: [AtomicU32; MAX_NESTED_EXECUTORS],
+}
+
+static EXECUTOR_STACKS: [ExecutorStack; MAX_CORES] = [const {
+    ExecutorStack {
+        depth: AtomicUsize::new(0),
+        executor_ids: [const { AtomicU32::new(0) }; MAX_NESTED_EXECUTORS],
+    }
+}; MAX_CORES];
+
+fn current_executor_stack() -> Option<&'static ExecutorStack> {
+    EXECUTOR_STACKS.get(crate::get_current_core_id() as usize)
+}
+
+/// An executor started polling on this core (called from the embassy trace hooks)
+#[doc(hidden)]
+pub fn __executor_poll_start(executor_id: u32) {
+    if !MONITOR_EXECUTOR_ID {
+        return;
+    }
+    if let Some(stack) = current_executor_stack() {
+        let depth = stack.depth.load(Ordering::Relaxed);
+        if let Some(slot) = stack.executor_ids.get(depth) {
+            slot.store(executor_id, Ordering::Relaxed);
+        }
+        stack.depth.store(depth + 1, Ordering::Relaxed);
+    }
+}
+
+/// An executor finished polling on this core (called from the embassy trace hooks)
+#[doc(hidden)]
+pub fn __executor_poll_end() {
+    if !MONITOR_EXECUTOR_ID {
+        return;
+    }
+    if let Some(stack) = current_executor_stack() {
+        let depth = stack.depth.load(Ordering::Relaxed);
+        stack
+            .depth
+            .store(depth.saturating_sub(1), Ordering::Relaxed);
+    }
+}
+
+/// Id of the executor currently polling on this core (0 outside of any executor or when
+/// executors are nested deeper than tracked)
+#[doc(hidden)]
+pub fn __current_executor_id() -> u32 {
+    current_executor_stack()
+        .and_then(|stack| {
+            let depth = stack.depth.load(Ordering::Relaxed);
+            stack.executor_ids.get(depth.checked_sub(1)?)
+        })
+        .map(|slot| slot.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
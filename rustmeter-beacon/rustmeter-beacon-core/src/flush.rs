@@ -0,0 +1,40 @@
+/// Marks the end of the trace and forces defmt to drain its transport buffer.
+///
+/// Coalesced monitors (`#[monitor_fn(coalesce)]`) report the calls of their open window first.
+///
+/// Call this before a deliberate reset or halt (or from a panic handler) so the last events are not
+/// lost in the RTT / serial buffer. The host uses the emitted event to mark the trace end as clean.
+///
+/// Flushing is best-effort: it blocks until the logger has handed out its buffered bytes, which
+/// requires the host to keep reading. It cannot recover events from a logger that is itself broken
+/// (e.g. a panic while the logger was being acquired).
+///
+/// With the `disabled` feature no event is emitted, the transport buffer is still drained.
+pub fn rustmeter_flush() {
+    // Nothing registers with `disabled` (coalesced functions stay uninstrumented)
+    #[cfg(feature = "coalesce")]
+    crate::coalesce::flush_coalesced_monitors();
+
+    crate::__instrument! {
+        defmt::info!(
+            "@EVENT_TRACE_FLUSH(core_id={})",
+            crate::get_current_core_id()
+        );
+    }
+    defmt::flush();
+}
+
+/// Panic handler that logs the panic message, flushes the trace and halts the core.
+///
+/// Enabled by the `panic-handler` feature as an alternative to e.g. `panic-probe`. Only compiled
+/// for bare-metal targets, so host builds and tests keep the panic handler of std.
+#[cfg(all(feature = "panic-handler", not(test), target_os = "none"))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+    rustmeter_flush();
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1,21 +1,116 @@
 #![no_std]
 
+#[cfg(feature = "coalesce")]
+mod coalesce;
 mod core_id;
+#[cfg(feature = "cycle-timestamps")]
+mod cycle_counter;
+mod dma;
+mod executor_id;
+mod flush;
+mod marker;
+mod metric_value;
+mod monitor;
 mod monitor_scoped;
+mod registry;
+mod resource;
+mod stack;
+mod text;
+mod yield_reason;
+#[cfg(feature = "coalesce")]
+pub use crate::coalesce::*;
 pub use crate::core_id::*;
+#[cfg(feature = "cycle-timestamps")]
+pub use crate::cycle_counter::*;
+pub use crate::dma::*;
+pub use crate::executor_id::*;
+pub use crate::flush::*;
+pub use crate::marker::*;
+pub use crate::metric_value::*;
+pub use crate::registry::*;
+pub use crate::resource::*;
+pub use crate::stack::*;
+pub use crate::text::*;
+pub use crate::yield_reason::*;
 
 #[macro_export]
 /// Logs an event metric with a name and value via defmt.
+///
+/// With the `disabled` feature nothing is emitted and the value expression is not evaluated.
+///
+/// An optional display unit (at most [`MAX_METRIC_UNIT_LEN`] bytes, checked at compile time) is
+/// appended to the counter track name on the host, e.g. `current [mA]`:
+///
+/// ```rust,ignore
+/// event_metric!("current", current_ma, unit = "mA");
+/// ```
 macro_rules! event_metric {
-    ($name:literal, $val:expr) => {
-        // TODO: Check that val is numeric
+    ($name:literal, $val:expr) => {{
         // TODO: Check that name is a string literal without any special characters
 
-        defmt::info!(
-            "@EVENT_METRIC(name={=istr},value={},core_id={})",
-            defmt::intern!($name),
-            $val,
-            rustmeter_beacon::get_current_core_id()
-        );
+        rustmeter_beacon::__instrument! {
+            defmt::info!(
+                "@EVENT_METRIC(name={=istr},value={},core_id={})",
+                defmt::intern!($name),
+                rustmeter_beacon::metric_value($val),
+                rustmeter_beacon::get_current_core_id()
+            );
+        }
+        rustmeter_beacon::__instrument_unused!($val);
+    }};
+    ($name:literal, $val:expr, unit = $unit:literal) => {{
+        rustmeter_beacon::__instrument! {
+            const _: () = assert!(
+                rustmeter_beacon::is_valid_metric_unit($unit),
+                "event_metric! unit must be 1 to 8 bytes without '(', ')', ',' or '='"
+            );
+
+            defmt::info!(
+                "@EVENT_METRIC(name={=istr},value={},unit={=istr},core_id={})",
+                defmt::intern!($name),
+                rustmeter_beacon::metric_value($val),
+                defmt::intern!($unit),
+                rustmeter_beacon::get_current_core_id()
+            );
+        }
+        rustmeter_beacon::__instrument_unused!($val);
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "disabled"))]
+/// Instrumentation enabled ==> emit the wrapped statements as they are
+macro_rules! __instrument {
+    ($($statements:tt)*) => {
+        $($statements)*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "disabled")]
+/// Instrumentation disabled (`disabled` feature) ==> drop the wrapped statements entirely
+macro_rules! __instrument {
+    ($($statements:tt)*) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "disabled"))]
+/// Instrumentation enabled ==> the value is used by the emitted event
+macro_rules! __instrument_unused {
+    ($val:expr) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "disabled")]
+/// Instrumentation disabled ==> reference the value without evaluating it (no unused warnings)
+macro_rules! __instrument_unused {
+    ($val:expr) => {
+        let _ = || {
+            let _ = &$val;
+        };
     };
 }
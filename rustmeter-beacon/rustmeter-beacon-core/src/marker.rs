@@ -0,0 +1,48 @@
+#[macro_export]
+/// Drops a labeled marker into the trace timeline (e.g. button press, test step).
+///
+/// The host renders it as a global instant event across all tracks. Unlike
+/// [`monitor_scoped!`] a marker has no duration.
+///
+/// The label is interned by `defmt`, so its length does not matter. It must not contain
+/// `(`, `)`, `,` or `=` because those delimit the event parameters (checked at compile time).
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// trace_marker!("ButtonPressed");
+/// ```
+macro_rules! trace_marker {
+    ($name:literal) => {{
+        rustmeter_beacon::__instrument! {
+            const _: () = assert!(
+                rustmeter_beacon::is_valid_event_label($name),
+                "trace_marker! label must not contain '(', ')', ',' or '='"
+            );
+
+            defmt::info!(
+                "@EVENT_MARKER(name={=istr},core_id={})",
+                defmt::intern!($name),
+                rustmeter_beacon::get_current_core_id()
+            );
+        }
+    }};
+}
+
+/// Check that a label can be embedded into an event message without breaking the host parser
+pub const fn is_valid_event_label(label: &str) -> bool {
+    let bytes = label.as_bytes();
+    if bytes.is_empty() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if matches!(bytes[i], b'(' | b')' | b',' | b'=') {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
@@ -0,0 +1,33 @@
+/// Types that can be recorded as metric value via [`event_metric!`](crate::event_metric).
+///
+/// Implemented for all primitive integer and float types. The host plots the value as a
+/// counter track, so anything non-numeric is rejected at compile time.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be recorded as metric value",
+    label = "expected a numeric value",
+    note = "event_metric! only accepts integer and float primitives (e.g. `u32`, `i16`, `f32`)"
+)]
+pub trait MetricValue: defmt::Format {}
+
+macro_rules! impl_metric_value {
+    ($($ty:ty),*) => {
+        $(impl MetricValue for $ty {})*
+    };
+}
+
+impl_metric_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+/// Pass a metric value through unchanged. Used by [`event_metric!`](crate::event_metric) so that
+/// non-numeric values fail with a readable error at the call site.
+#[inline(always)]
+pub fn metric_value<V: MetricValue>(value: V) -> V {
+    value
+}
+
+/// Maximum length of a metric unit in bytes (see [`event_metric!`](crate::event_metric))
+pub const MAX_METRIC_UNIT_LEN: usize = 8;
+
+/// Check that a metric unit is short and can be embedded into an event message
+pub const fn is_valid_metric_unit(unit: &str) -> bool {
+    unit.len() <= MAX_METRIC_UNIT_LEN && crate::is_valid_event_label(unit)
+}
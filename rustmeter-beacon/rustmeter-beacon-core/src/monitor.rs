@@ -0,0 +1,67 @@
+#[macro_export]
+/// Monitors an ad-hoc closure or async block without moving it into a named function.
+///
+/// Every call of a monitored closure and every poll of a monitored async block becomes a scope
+/// monitor slice (registered like [`monitor_scoped!`]). The inner value is passed through.
+///
+/// # Async semantics
+///
+/// An async block is only timed while it is polled (on-CPU time), not while it waits for a
+/// wakeup, so one `.await` of it can show up as several short slices on the task's track. A poll
+/// that gets preempted by a higher priority executor still includes the preemption.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// // Closure: every call is monitored
+/// let parse = monitor!("parse", |raw: &[u8]| decode(raw));
+/// let packet = parse(&buffer);
+///
+/// // Async block: awaited in place
+/// let reply = monitor!("request", async { client.send(packet).await }.await);
+///
+/// // Async block: monitored future to await later
+/// let request = monitor!("request", async move { client.send(packet).await });
+/// ```
+macro_rules! monitor {
+    ($name:literal, async $body:block .await) => {
+        rustmeter_beacon::monitor!($name, async $body).await
+    };
+    ($name:literal, async move $body:block .await) => {
+        rustmeter_beacon::monitor!($name, async move $body).await
+    };
+    ($name:literal, async $body:block) => {
+        async {
+            let mut future = core::pin::pin!(async $body);
+            core::future::poll_fn(|cx| {
+                rustmeter_beacon::monitor_scoped!($name, {
+                    core::future::Future::poll(future.as_mut(), cx)
+                })
+            })
+            .await
+        }
+    };
+    ($name:literal, async move $body:block) => {
+        async move {
+            let mut future = core::pin::pin!(async move $body);
+            core::future::poll_fn(|cx| {
+                rustmeter_beacon::monitor_scoped!($name, {
+                    core::future::Future::poll(future.as_mut(), cx)
+                })
+            })
+            .await
+        }
+    };
+    ($name:literal, || $body:expr) => {
+        || rustmeter_beacon::monitor_scoped!($name, { $body })
+    };
+    ($name:literal, move || $body:expr) => {
+        move || rustmeter_beacon::monitor_scoped!($name, { $body })
+    };
+    ($name:literal, |$($arg:ident $(: $ty:ty)?),*| $body:expr) => {
+        |$($arg $(: $ty)?),*| rustmeter_beacon::monitor_scoped!($name, { $body })
+    };
+    ($name:literal, move |$($arg:ident $(: $ty:ty)?),*| $body:expr) => {
+        move |$($arg $(: $ty)?),*| rustmeter_beacon::monitor_scoped!($name, { $body })
+    };
+}
@@ -4,9 +4,14 @@
 /// The return value of the code block is passed through, allowing the macro to be
 /// used in assignments (see Example 2).
 ///
+/// Each start event carries the call site (`file:line`) as interned string, so scopes with the
+/// same name in different places can be told apart on the host. This costs 2 bytes per start.
+///
 /// # Arguments
 ///
 /// * `$name`: A string literal describing the scope name (interned by `defmt`).
+/// * `cat = $cat` (optional): A string literal category used for filtering in Perfetto
+///   (defaults to `function_monitor`).
 /// * `$body`: The code block enclosed in curly braces `{ ... }`.
 ///
 /// # Warning
@@ -16,7 +21,7 @@
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 ///// Example 1: Simple block without a return value (Type `()`)
 ///monitor_scoped!("SensorInit", {
 ///    // Your code goes here
@@ -31,22 +36,79 @@
 ///    let b = 20;
 ///    a + b
 ///});
+///
+///// Example 3: Block with a custom category
+///monitor_scoped!("ParsePacket", cat = "io", {
+///    parse_packet(&buffer);
+///});
 /// ```
 macro_rules! monitor_scoped {
     ($name:literal, $body:block) => {{
-        let core_id = rustmeter_beacon::get_current_core_id();
-        defmt::info!(
-            "@EVENT_MONITOR_START(function_name={=istr},core_id={})",
-            defmt::intern!($name),
-            core_id
-        );
+        rustmeter_beacon::__instrument! {
+            rustmeter_beacon::__register_monitor!(rustmeter_beacon::MONITOR_KIND_SCOPE, $name);
+            let core_id = rustmeter_beacon::get_current_core_id();
+            if rustmeter_beacon::MONITOR_EXECUTOR_ID {
+                defmt::info!(
+                    "@EVENT_MONITOR_START(function_name={=istr},location={=istr},executor_id={=u32},core_id={})",
+                    defmt::intern!($name),
+                    rustmeter_beacon::__intern_location!($name),
+                    rustmeter_beacon::__current_executor_id(),
+                    core_id
+                );
+            } else {
+                defmt::info!(
+                    "@EVENT_MONITOR_START(function_name={=istr},location={=istr},core_id={})",
+                    defmt::intern!($name),
+                    rustmeter_beacon::__intern_location!($name),
+                    core_id
+                );
+            }
+        }
+
+        let result = { $body };
+        rustmeter_beacon::__instrument! {
+            defmt::info!(
+                "@EVENT_MONITOR_END(function_name={=istr},core_id={})",
+                defmt::intern!($name),
+                core_id
+            );
+        }
+
+        result
+    }};
+    ($name:literal, cat = $cat:literal, $body:block) => {{
+        rustmeter_beacon::__instrument! {
+            rustmeter_beacon::__register_monitor!(rustmeter_beacon::MONITOR_KIND_SCOPE, $name);
+            let core_id = rustmeter_beacon::get_current_core_id();
+            if rustmeter_beacon::MONITOR_EXECUTOR_ID {
+                defmt::info!(
+                    "@EVENT_MONITOR_START(function_name={=istr},location={=istr},category={=istr},executor_id={=u32},core_id={})",
+                    defmt::intern!($name),
+                    rustmeter_beacon::__intern_location!($name),
+                    defmt::intern!($cat),
+                    rustmeter_beacon::__current_executor_id(),
+                    core_id
+                );
+            } else {
+                defmt::info!(
+                    "@EVENT_MONITOR_START(function_name={=istr},location={=istr},category={=istr},core_id={})",
+                    defmt::intern!($name),
+                    rustmeter_beacon::__intern_location!($name),
+                    defmt::intern!($cat),
+                    core_id
+                );
+            }
+        }
 
         let result = { $body };
-        defmt::info!(
-            "@EVENT_MONITOR_END(function_name={=istr},core_id={})",
-            defmt::intern!($name),
-            core_id
-        );
+        rustmeter_beacon::__instrument! {
+            defmt::info!(
+                "@EVENT_MONITOR_END(function_name={=istr},category={=istr},core_id={})",
+                defmt::intern!($name),
+                defmt::intern!($cat),
+                core_id
+            );
+        }
 
         result
     }};
@@ -0,0 +1,64 @@
+/// Monitor kind of a [`MonitorRecord`]: `#[monitor_fn]`
+pub const MONITOR_KIND_FUNCTION: u8 = 0;
+/// Monitor kind of a [`MonitorRecord`]: `monitor_scoped!`
+pub const MONITOR_KIND_SCOPE: u8 = 1;
+
+/// Maximum stored name length of a [`MonitorRecord`] (longer names are truncated)
+pub const MONITOR_RECORD_NAME_LEN: usize = 62;
+
+/// Entry of the monitor registry in the `.rustmeter_monitors` linker section.
+///
+/// With the `monitor-registry` feature every monitor places one record into that section, so the
+/// host can list all compiled-in monitors from the ELF without running the firmware. The section
+/// is not loaded onto the target (see `rustmeter.x`) and records of monitors removed by dead-code
+/// elimination are removed with them.
+#[repr(C)]
+pub struct MonitorRecord {
+    pub kind: u8,
+    pub name_len: u8,
+    pub name: [u8; MONITOR_RECORD_NAME_LEN],
+}
+
+impl MonitorRecord {
+    pub const fn new(kind: u8, name: &str) -> Self {
+        let bytes = name.as_bytes();
+        let mut len = bytes.len();
+        if len > MONITOR_RECORD_NAME_LEN {
+            len = MONITOR_RECORD_NAME_LEN;
+        }
+
+        let mut record = Self {
+            kind,
+            name_len: len as u8,
+            name: [0; MONITOR_RECORD_NAME_LEN],
+        };
+        let mut i = 0;
+        while i < len {
+            record.name[i] = bytes[i];
+            i += 1;
+        }
+        record
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "monitor-registry")]
+/// Place a [`MonitorRecord`] for this monitor into the `.rustmeter_monitors` section
+macro_rules! __register_monitor {
+    ($kind:expr, $name:expr) => {{
+        #[unsafe(link_section = ".rustmeter_monitors")]
+        static MONITOR_RECORD: rustmeter_beacon::MonitorRecord =
+            rustmeter_beacon::MonitorRecord::new($kind, $name);
+        // Reference the record from the monitored code so it is only stripped together with it
+        core::hint::black_box(&MONITOR_RECORD);
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "monitor-registry"))]
+/// Monitor registry disabled ==> nothing to register
+macro_rules! __register_monitor {
+    ($kind:expr, $name:expr) => {{}};
+}
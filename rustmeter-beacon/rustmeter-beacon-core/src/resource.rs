@@ -0,0 +1,123 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::get_current_core_id;
+
+#[macro_export]
+/// Traces how long the calling task waits for a lock (e.g. an embassy-sync `Mutex` or
+/// `Semaphore`) and how long it holds it.
+///
+/// Wraps the future that acquires the lock and returns a future of a [`TracedGuard`]. The host
+/// shows a `Blocked on <name>` slice from the call until the lock is acquired, followed by a
+/// `Holding <name>` slice until the guard is dropped. Both are attached to the task running on
+/// the core.
+///
+/// The name is interned by `defmt`. It must not contain `(`, `)`, `,` or `=` (checked at compile
+/// time). Every acquisition costs three events of a few bytes each, also when the lock was free.
+/// If the future is dropped before it acquired the lock (e.g. it lost a `select` or timed out),
+/// the wait is reported as abandoned instead.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// static SPI_BUS: Mutex<CriticalSectionRawMutex, Spi> = ...;
+///
+/// let mut spi = traced_acquire!("spi_bus", SPI_BUS.lock()).await;
+/// spi.transfer(&mut buffer);
+/// // released when `spi` goes out of scope
+///
+/// let _permit = traced_acquire!("dma_channels", DMA_CHANNELS.acquire(1)).await;
+/// ```
+macro_rules! traced_acquire {
+    ($name:literal, $acquire:expr) => {{
+        rustmeter_beacon::__instrument! {
+            const _: () = assert!(
+                rustmeter_beacon::is_valid_event_label($name),
+                "traced_acquire! name must not contain '(', ')', ',' or '='"
+            );
+        }
+        rustmeter_beacon::__traced_acquire(defmt::intern!($name), $acquire)
+    }};
+}
+
+/// Guard of a lock acquired via [`traced_acquire!`]. Dereferences to the wrapped guard's target
+/// and reports the release when dropped
+pub struct TracedGuard<G> {
+    guard: G,
+    name: defmt::Str,
+}
+
+impl<G> TracedGuard<G> {
+    /// The wrapped guard (e.g. a semaphore permit without `Deref`)
+    pub fn inner(&self) -> &G {
+        &self.guard
+    }
+}
+
+impl<G: Deref> Deref for TracedGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for TracedGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for TracedGuard<G> {
+    fn drop(&mut self) {
+        // Emitted just before the wrapped guard is dropped
+        if !cfg!(feature = "disabled") {
+            defmt::info!(
+                "@EVENT_RESOURCE_RELEASED(name={=istr},core_id={})",
+                self.name,
+                get_current_core_id()
+            );
+        }
+    }
+}
+
+/// Reports the wait as abandoned if the acquiring future is dropped before the lock was acquired
+struct WaitGuard {
+    name: defmt::Str,
+}
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        if !cfg!(feature = "disabled") {
+            defmt::info!(
+                "@EVENT_RESOURCE_ABANDONED(name={=istr},core_id={})",
+                self.name,
+                get_current_core_id()
+            );
+        }
+    }
+}
+
+#[doc(hidden)]
+/// Implementation of [`traced_acquire!`]
+pub async fn __traced_acquire<F: Future>(name: defmt::Str, acquire: F) -> TracedGuard<F::Output> {
+    if !cfg!(feature = "disabled") {
+        defmt::info!(
+            "@EVENT_RESOURCE_WAIT(name={=istr},core_id={})",
+            name,
+            get_current_core_id()
+        );
+    }
+
+    let wait_guard = WaitGuard { name };
+    let guard = acquire.await;
+    core::mem::forget(wait_guard);
+
+    if !cfg!(feature = "disabled") {
+        defmt::info!(
+            "@EVENT_RESOURCE_ACQUIRED(name={=istr},core_id={})",
+            name,
+            get_current_core_id()
+        );
+    }
+    TracedGuard { guard, name }
+}
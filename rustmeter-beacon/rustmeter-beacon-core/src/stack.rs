@@ -0,0 +1,113 @@
+#[macro_export]
+/// Reports the high-water mark (deepest usage in bytes) of a [`StackRegion`] as metric.
+///
+/// The host renders it as a counter track `name [B]`, so stacks approaching their size show up
+/// over time. Every report scans the untouched part of the stack word by word, which can take a
+/// while for large stacks. Pass `every = N` to only scan on every N-th call (the first call always
+/// scans).
+///
+/// The stack must have been filled with [`StackRegion::fill_unused`] before, otherwise the whole
+/// stack counts as used.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// monitor_stack_usage!("main_stack", &MAIN_STACK);
+/// monitor_stack_usage!("main_stack", &MAIN_STACK, every = 100);
+/// ```
+macro_rules! monitor_stack_usage {
+    ($name:literal, $region:expr) => {{
+        rustmeter_beacon::__instrument! {
+            let region: &rustmeter_beacon::StackRegion = $region;
+            rustmeter_beacon::event_metric!($name, region.high_water_mark(), unit = "B");
+        }
+        rustmeter_beacon::__instrument_unused!($region);
+    }};
+    ($name:literal, $region:expr, every = $every:literal) => {{
+        rustmeter_beacon::__instrument! {
+            const _: () = assert!($every > 0, "monitor_stack_usage! every must be at least 1");
+
+            static SCAN_COUNTER: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+            // Plain load / store instead of fetch_add to support targets without atomic RMW (races only skew the interval)
+            let call_index = SCAN_COUNTER.load(core::sync::atomic::Ordering::Relaxed);
+            SCAN_COUNTER.store(call_index.wrapping_add(1), core::sync::atomic::Ordering::Relaxed);
+            if call_index % $every == 0 {
+                rustmeter_beacon::monitor_stack_usage!($name, $region);
+            }
+        }
+        rustmeter_beacon::__instrument_unused!($region);
+    }};
+}
+
+/// Word written into unused stack memory by [`StackRegion::fill_unused`]
+pub const STACK_FILL_PATTERN: u32 = 0xDEAD_BEEF;
+
+/// Bytes right below the current stack pointer that [`StackRegion::fill_unused`] leaves alone,
+/// so it does not overwrite its own frame
+pub const STACK_FILL_MARGIN: usize = 256;
+
+/// Memory range of a stack, used by [`monitor_stack_usage!`](crate::monitor_stack_usage).
+///
+/// Assumes a full descending stack (it grows from `top` down to `bottom`) of word aligned
+/// bounds, as on Arm Cortex-M, RISC-V and Xtensa. Embassy tasks do not own a stack: all tasks of
+/// an executor run on the stack of the context it runs in (usually the main stack of its core,
+/// or the interrupt stack for an `InterruptExecutor`).
+pub struct StackRegion {
+    bottom: *const u32,
+    top: *const u32,
+}
+
+// The region only holds addresses and reads them volatile
+unsafe impl Sync for StackRegion {}
+
+impl StackRegion {
+    /// Create a stack region from its lowest (`bottom`) and highest (`top`, exclusive) address,
+    /// e.g. from the linker symbols of your memory layout.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, word aligned memory that is only used as stack
+    pub const unsafe fn new(bottom: *const u32, top: *const u32) -> Self {
+        Self { bottom, top }
+    }
+
+    /// Size of the stack in bytes
+    pub fn size(&self) -> u32 {
+        (self.top as usize).saturating_sub(self.bottom as usize) as u32
+    }
+
+    /// Fill the unused part of the stack (from `bottom` up to [`STACK_FILL_MARGIN`] below the
+    /// current stack pointer) with [`STACK_FILL_PATTERN`]. Call it once early at startup, from
+    /// code running on this stack.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the filled memory at the same time, e.g. an interrupt handler on the
+    /// same stack. Call it before enabling interrupts.
+    pub unsafe fn fill_unused(&self) {
+        let stack_marker = 0u8;
+        let stack_pointer = core::ptr::addr_of!(stack_marker) as usize;
+        let fill_end = stack_pointer
+            .saturating_sub(STACK_FILL_MARGIN)
+            .min(self.top as usize);
+
+        let mut word = self.bottom as *mut u32;
+        while (word as usize) < fill_end {
+            // SAFETY: the caller guarantees the range is unused stack memory
+            unsafe {
+                word.write_volatile(STACK_FILL_PATTERN);
+                word = word.add(1);
+            }
+        }
+    }
+
+    /// Deepest stack usage in bytes so far: everything above the first overwritten fill word
+    pub fn high_water_mark(&self) -> u32 {
+        let mut word = self.bottom;
+        // SAFETY: the range was declared valid stack memory in `new`
+        while word < self.top && unsafe { word.read_volatile() } == STACK_FILL_PATTERN {
+            word = unsafe { word.add(1) };
+        }
+        (self.top as usize - word as usize) as u32
+    }
+}
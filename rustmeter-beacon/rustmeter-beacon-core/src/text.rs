@@ -0,0 +1,49 @@
+/// Maximum number of bytes of a text value sent via [`event_text!`](crate::event_text)
+pub const MAX_EVENT_TEXT_LEN: usize = 64;
+
+#[macro_export]
+/// Logs a short text value (e.g. a state name or a 128-bit id) with a name via defmt.
+///
+/// Perfetto counters are numeric, so the host shows text values as instant markers on the
+/// metrics track of the running task instead of a counter line.
+///
+/// The text is cut to [`MAX_EVENT_TEXT_LEN`] bytes (on a UTF-8 character boundary) and at the
+/// first character that would break the event format (`(`, `)`, `,`, `=` or a line break).
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// event_text!("wifi_state", "Connecting");
+/// ```
+macro_rules! event_text {
+    ($name:literal, $text:expr) => {{
+        rustmeter_beacon::__instrument! {
+            const _: () = assert!(
+                rustmeter_beacon::is_valid_event_label($name),
+                "event_text! name must not contain '(', ')', ',' or '='"
+            );
+
+            defmt::info!(
+                "@EVENT_TEXT(name={=istr},text={=str},core_id={})",
+                defmt::intern!($name),
+                rustmeter_beacon::event_text_value($text),
+                rustmeter_beacon::get_current_core_id()
+            );
+        }
+        rustmeter_beacon::__instrument_unused!($text);
+    }};
+}
+
+/// Cut a text value to the part that can be embedded into an event message (see [`event_text!`](crate::event_text))
+pub fn event_text_value(text: &str) -> &str {
+    let mut end = 0;
+    for (idx, c) in text.char_indices() {
+        if idx + c.len_utf8() > MAX_EVENT_TEXT_LEN
+            || matches!(c, '(' | ')' | ',' | '=' | '\n' | '\r')
+        {
+            break;
+        }
+        end = idx + c.len_utf8();
+    }
+    &text[..end]
+}
@@ -0,0 +1,55 @@
+#[macro_export]
+/// Records why the running task is about to return `Poll::Pending` (e.g. waiting on a timer or
+/// on I/O). Call it right before the await point or `Poll::Pending` it explains.
+///
+/// The host shows the reason as `yield_reason` argument of the task's `Running` slice. The code
+/// is either a [`YieldReason`] or an application defined `u8` from [`FIRST_CUSTOM_YIELD_REASON`]
+/// on, named on the host with `--yield-reason CODE=LABEL`. Only the last reason before the task
+/// stops running is kept.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// yield_reason!(YieldReason::Timer);
+/// Timer::after_millis(10).await;
+///
+/// const WAIT_FOR_MODEM: u8 = FIRST_CUSTOM_YIELD_REASON;
+/// yield_reason!(WAIT_FOR_MODEM);
+/// ```
+macro_rules! yield_reason {
+    ($code:expr) => {{
+        rustmeter_beacon::__instrument! {
+            defmt::info!(
+                "@EVENT_YIELD_REASON(code={=u8},core_id={})",
+                ::core::convert::Into::<u8>::into($code),
+                rustmeter_beacon::get_current_core_id()
+            );
+        }
+        rustmeter_beacon::__instrument_unused!($code);
+    }};
+}
+
+/// Well-known reasons for [`yield_reason!`](crate::yield_reason). The host knows their names
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YieldReason {
+    /// Waiting for a timer (e.g. `Timer::after`)
+    Timer = 1,
+    /// Waiting for a peripheral (UART, SPI, DMA, ...)
+    Io = 2,
+    /// Waiting for a message of a channel
+    Channel = 3,
+    /// Waiting for a lock (mutex, semaphore)
+    Lock = 4,
+    /// Waiting for a signal or event
+    Signal = 5,
+}
+
+/// First code free for application defined yield reasons (lower codes are reserved)
+pub const FIRST_CUSTOM_YIELD_REASON: u8 = 16;
+
+impl From<YieldReason> for u8 {
+    fn from(reason: YieldReason) -> Self {
+        reason as u8
+    }
+}
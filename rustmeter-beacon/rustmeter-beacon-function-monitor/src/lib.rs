@@ -1,49 +1,146 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
-    Ident, ItemFn, LitStr, Result, Token,
+    Ident, ItemFn, Lit, LitStr, Result, Token,
     parse::{Parse, ParseStream},
     parse_macro_input,
+    punctuated::Punctuated,
 };
 
 extern crate proc_macro;
 
-/// Helper struct to parse arguments for the `monitor_fn` attribute macro
+/// A single argument of the `monitor_fn` attribute as written
+enum MonitorArg {
+    /// Positional name: `"Name"`
+    Positional(LitStr),
+    /// Key without a value: `coalesce`
+    Flag(Ident),
+    /// Key-value pair: `sample = 100`
+    KeyValue(Ident, Lit),
+}
+
+impl Parse for MonitorArg {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(LitStr) {
+            return Ok(MonitorArg::Positional(input.parse()?));
+        }
+
+        let key: Ident = input.parse()?;
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Ok(MonitorArg::KeyValue(key, input.parse()?))
+        } else {
+            Ok(MonitorArg::Flag(key))
+        }
+    }
+}
+
+/// Arguments of the `monitor_fn` attribute macro (consulted by the code generation)
+#[derive(Debug, Default, PartialEq)]
 struct MonitorArgs {
     name: Option<String>,
+    /// Only emit events for 1 in `sample` invocations
+    sample: Option<u32>,
+    /// Emit one aggregated event per window (in ms) instead of start / end per call
+    coalesce: Option<u32>,
+}
+
+/// Default window of `#[monitor_fn(coalesce)]` in ms
+const DEFAULT_COALESCE_WINDOW_MS: u32 = 100;
+
+/// Keys accepted by `monitor_fn`
+const KNOWN_KEYS: &[&str] = &["name", "sample", "coalesce"];
+
+/// Error for a key that `monitor_fn` does not know
+fn unknown_key(key: &Ident) -> syn::Error {
+    syn::Error::new(
+        key.span(),
+        format!(
+            "Unknown argument '{key}' (expected one of: {})",
+            KNOWN_KEYS.join(", ")
+        ),
+    )
+}
+
+/// Parse an integer value within `min..=max`
+fn bounded_u32(key: &Ident, value: &Lit, min: u32, max: u32) -> Result<u32> {
+    let Lit::Int(lit) = value else {
+        return Err(syn::Error::new(
+            value.span(),
+            format!("'{key}' expects an integer"),
+        ));
+    };
+    let value: u32 = lit.base10_parse()?;
+    if value < min || value > max {
+        return Err(syn::Error::new(
+            lit.span(),
+            format!("'{key}' must be between {min} and {max}"),
+        ));
+    }
+    Ok(value)
 }
 
 impl Parse for MonitorArgs {
+    /// Accepts an optional positional name and comma separated `key = value` pairs or flags in
+    /// any order, e.g. `#[monitor_fn("name", sample = 100)]`
     fn parse(input: ParseStream) -> Result<Self> {
-        let mut name = None;
-        if input.is_empty() {
-            return Ok(MonitorArgs { name });
-        }
+        let raw_args = Punctuated::<MonitorArg, Token![,]>::parse_terminated(input)?;
 
-        // Case 1: #[monitor_fn("Name")]
-        // `lookahead` checks if the next token is a string literal
-        if input.peek(syn::LitStr) {
-            let lit: LitStr = input.parse()?;
-            name = Some(lit.value());
-        }
-        // Case 2: Key-Value Pair: #[monitor_fn(name = "Name")]
-        else if input.peek(syn::Ident) {
-            let key: Ident = input.parse()?;
-            if key == "name" {
-                input.parse::<Token![=]>()?; // Consume the '='
-                let lit: LitStr = input.parse()?;
-                name = Some(lit.value());
-            } else {
+        let mut args = MonitorArgs::default();
+        let mut seen_keys: Vec<String> = Vec::new();
+        for raw_arg in raw_args {
+            // Positional name ==> same as `name = "..."`
+            let (key, value) = match raw_arg {
+                MonitorArg::Positional(lit) => {
+                    (Ident::new("name", lit.span()), Some(Lit::Str(lit)))
+                }
+                MonitorArg::Flag(key) => (key, None),
+                MonitorArg::KeyValue(key, value) => (key, Some(value)),
+            };
+
+            let key_name = key.to_string();
+            if !KNOWN_KEYS.contains(&key_name.as_str()) {
+                return Err(unknown_key(&key));
+            }
+            if seen_keys.contains(&key_name) {
                 return Err(syn::Error::new(
                     key.span(),
-                    "Unknown argument (expected 'name')",
+                    format!("'{key}' is given more than once"),
                 ));
             }
-        }
+            seen_keys.push(key_name);
 
-        // More arguments could be parsed here in the future
+            match (key.to_string().as_str(), value) {
+                ("name", Some(Lit::Str(lit))) => args.name = Some(lit.value()),
+                ("name", Some(value)) => {
+                    return Err(syn::Error::new(value.span(), "'name' expects a string"));
+                }
+                ("sample", Some(value)) => {
+                    args.sample = Some(bounded_u32(&key, &value, 1, u32::MAX)?);
+                }
+                ("coalesce", Some(value)) => {
+                    args.coalesce = Some(bounded_u32(&key, &value, 1, 60_000)?);
+                }
+                // Flag without value: #[monitor_fn(coalesce)]
+                ("coalesce", None) => args.coalesce = Some(DEFAULT_COALESCE_WINDOW_MS),
+                (_, None) => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("'{key}' expects a value ({key} = ...)"),
+                    ));
+                }
+                _ => return Err(unknown_key(&key)),
+            }
 
-        Ok(MonitorArgs { name })
+            if args.sample.is_some() && args.coalesce.is_some() {
+                return Err(syn::Error::new(
+                    key.span(),
+                    "'sample' and 'coalesce' cannot be combined",
+                ));
+            }
+        }
+
+        Ok(args)
     }
 }
 
@@ -62,12 +159,37 @@ impl Parse for MonitorArgs {
 /// * `#[monitor_fn]` - Uses the name of the function.
 /// * `#[monitor_fn("custom_name")]` - Uses the provided string literal.
 /// * `#[monitor_fn(name = "custom_name")]` - Explicit key-value syntax.
+/// * `#[monitor_fn(sample = 100)]` - Only trace 1 in 100 invocations (can be combined with a name).
+/// * `#[monitor_fn(coalesce)]` / `#[monitor_fn(coalesce = 250)]` - Report call count and total
+///   duration once per window (default 100 ms) instead of every call.
+///
+/// # Sampling
+///
+/// Functions called at a very high frequency flood the defmt channel and skew the timing of
+/// everything around them. With `sample = N` only every N-th invocation emits its start / end
+/// events (counted per function with a relaxed atomic, so concurrent calls may be counted twice).
+/// The resulting slices carry a `sampled: N` arg in Perfetto: they are a statistical selection of
+/// the calls and the gaps between them do not mean the function was not running.
+///
+/// # Coalescing
+///
+/// `coalesce` goes one step further for hot paths: the calls are timed on the target with
+/// `embassy-time` (requires the `embassy-time` feature of `rustmeter-beacon`) and a single
+/// aggregated event with the call count and total duration is emitted per window. The host shows
+/// it as `calls/s` and `avg us` counters instead of individual slices, so the timeline loses the
+/// position of every single call in exchange for an almost constant overhead. The calls of the
+/// window that is still open are reported by `rustmeter_flush()`.
+///
+/// # Disabled Instrumentation
+///
+/// With the `disabled` feature of `rustmeter-beacon` the attribute returns the function unchanged
+/// (arguments are still validated).
 ///
 /// # Examples
 ///
 /// Basic usage using the function's name:
 ///
-/// ```rust
+/// ```rust,ignore
 /// #[monitor_fn]
 /// fn process_data(data: u8) {
 ///     // Function implementation
@@ -78,6 +200,42 @@ pub fn monitor_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemFn);
     let args = parse_macro_input!(attr as MonitorArgs);
 
+    expand_monitor_fn(args, input, cfg!(feature = "disabled")).into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+/// Intern the source location (`file:line`) of the first given token as defmt string, e.g.
+/// `defmt::intern!("src/main.rs:42")`. `monitor_scoped!` passes its name literal, which keeps the
+/// span of the call site
+pub fn __intern_location(input: TokenStream) -> TokenStream {
+    let Some(mut token) = input.into_iter().next() else {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "expected a token to take the location from",
+        )
+        .to_compile_error()
+        .into();
+    };
+    // A fragment forwarded by `macro_rules!` arrives in an invisible group spanning the macro body
+    while let proc_macro::TokenTree::Group(group) = &token
+        && group.delimiter() == proc_macro::Delimiter::None
+        && let Some(inner) = group.stream().into_iter().next()
+    {
+        token = inner;
+    }
+    let span = token.span();
+    let location = format!("{}:{}", span.file(), span.line());
+    quote! { defmt::intern!(#location) }.into()
+}
+
+/// Generate the instrumented function (or the untouched one if instrumentation is `disabled`)
+fn expand_monitor_fn(args: MonitorArgs, input: ItemFn, disabled: bool) -> proc_macro2::TokenStream {
+    // Instrumentation compiled out ==> keep the function exactly as written
+    if disabled {
+        return quote! { #input };
+    }
+
     let fn_name = &input.sig.ident;
     let vis = &input.vis;
     let sig = &input.sig;
@@ -95,28 +253,183 @@ pub fn monitor_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
     //       function is running for a long time and we exit while it is still running
     //          - which timestamp method to use for that?
 
-    if input.sig.asyncness.is_some() {
-        // ASYNC FUNCTION
-        quote! {
-            let core_id = rustmeter_beacon::get_current_core_id();
-            async move {
-                    defmt::info!("@EVENT_MONITOR_START(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
-                    let result = { #block };
+    let (sample_check, start_event, end_event) = match (args.sample, args.coalesce) {
+        (_, Some(window_ms)) => {
+            let window_us = window_ms * 1000;
+            (
+                quote! {
+                    let (coalescer, now_us) = rustmeter_beacon::__coalescer!(#output_name);
+                    let start_us = now_us();
+                },
+                quote! {},
+                quote! {
+                    if let Some(calls) = coalescer.record(start_us, now_us(), #window_us) {
+                        defmt::info!(
+                            "@EVENT_MONITOR_AGGREGATE(function_name={=istr},count={=u32},total_us={=u32},window_us={=u32},core_id={})",
+                            defmt::intern!(#output_name), calls.count, calls.total_us, calls.window_us, core_id
+                        );
+                    }
+                },
+            )
+        }
+        (Some(sample), None) => (
+            quote! {
+                static SAMPLE_COUNTER: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+                // Plain load / store instead of fetch_add to support targets without atomic RMW (races only skew the sampling)
+                let call_index = SAMPLE_COUNTER.load(core::sync::atomic::Ordering::Relaxed);
+                SAMPLE_COUNTER.store(call_index.wrapping_add(1), core::sync::atomic::Ordering::Relaxed);
+                let sampled = call_index % #sample == 0;
+            },
+            quote! {
+                if sampled {
+                    if rustmeter_beacon::MONITOR_EXECUTOR_ID {
+                        defmt::info!("@EVENT_MONITOR_START(function_name={=istr},sampled={=u32},executor_id={=u32},core_id={})", defmt::intern!(#output_name), #sample, rustmeter_beacon::__current_executor_id(), core_id);
+                    } else {
+                        defmt::info!("@EVENT_MONITOR_START(function_name={=istr},sampled={=u32},core_id={})", defmt::intern!(#output_name), #sample, core_id);
+                    }
+                }
+            },
+            quote! {
+                if sampled {
                     defmt::info!("@EVENT_MONITOR_END(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
-                    result
                 }
-            }.into()
+            },
+        ),
+        (None, None) => (
+            quote! {},
+            quote! {
+                if rustmeter_beacon::MONITOR_EXECUTOR_ID {
+                    defmt::info!("@EVENT_MONITOR_START(function_name={=istr},executor_id={=u32},core_id={})", defmt::intern!(#output_name), rustmeter_beacon::__current_executor_id(), core_id);
+                } else {
+                    defmt::info!("@EVENT_MONITOR_START(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
+                }
+            },
+            quote! {
+                defmt::info!("@EVENT_MONITOR_END(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
+            },
+        ),
+    };
+
+    let body = if input.sig.asyncness.is_some() {
+        // ASYNC FUNCTION
+        quote! { async move #block.await }
     } else {
         // SYNC FUNCTION
-        quote! {
-            #(#attrs)*
-            #vis #sig {
-                let core_id = rustmeter_beacon::get_current_core_id();
-                defmt::info!("@EVENT_MONITOR_START(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
-                let result = (move || { #block })();
-                defmt::info!("@EVENT_MONITOR_END(function_name={=istr},core_id={})", defmt::intern!(#output_name), core_id);
-                result
+        quote! { (move || #block)() }
+    };
+
+    quote! {
+        #(#attrs)*
+        #vis #sig {
+            rustmeter_beacon::__register_monitor!(rustmeter_beacon::MONITOR_KIND_FUNCTION, #output_name);
+            #sample_check
+            let core_id = rustmeter_beacon::get_current_core_id();
+            #start_event
+            let result = #body;
+            #end_event
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_keeps_function_untouched() {
+        let input: ItemFn = syn::parse_quote! {
+            fn process_data(data: u8) -> u8 {
+                data + 1
             }
-        }.into()
+        };
+        let args: MonitorArgs = syn::parse2(quote! { sample = 10 }).unwrap();
+
+        let disabled = expand_monitor_fn(args, input.clone(), true);
+        assert_eq!(disabled.to_string(), quote! { #input }.to_string());
+        assert!(!disabled.to_string().contains("rustmeter_beacon"));
+
+        let args: MonitorArgs = syn::parse2(quote! { sample = 10 }).unwrap();
+        let enabled = expand_monitor_fn(args, input, false).to_string();
+        assert!(enabled.contains("EVENT_MONITOR_START"));
+        assert!(enabled.contains("__register_monitor"));
+    }
+
+    fn parse_args(tokens: proc_macro2::TokenStream) -> Result<MonitorArgs> {
+        syn::parse2(tokens)
+    }
+
+    fn parse_error(tokens: proc_macro2::TokenStream) -> String {
+        match parse_args(tokens) {
+            Ok(args) => panic!("expected an error, got {args:?}"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_argument_combinations() {
+        let name = |name: &str| Some(name.to_string());
+
+        assert_eq!(parse_args(quote! {}).unwrap(), MonitorArgs::default());
+        assert_eq!(parse_args(quote! { "a" }).unwrap().name, name("a"));
+        assert_eq!(parse_args(quote! { name = "a" }).unwrap().name, name("a"));
+        assert_eq!(
+            parse_args(quote! { "a", sample = 100 }).unwrap(),
+            MonitorArgs {
+                name: name("a"),
+                sample: Some(100),
+                coalesce: None,
+            }
+        );
+        // any order and a trailing comma
+        assert_eq!(
+            parse_args(quote! { coalesce, "a", }).unwrap(),
+            MonitorArgs {
+                name: name("a"),
+                sample: None,
+                coalesce: Some(DEFAULT_COALESCE_WINDOW_MS),
+            }
+        );
+        assert_eq!(
+            parse_args(quote! { coalesce = 250, name = "a" })
+                .unwrap()
+                .coalesce,
+            Some(250)
+        );
+    }
+
+    #[test]
+    fn test_parse_argument_errors() {
+        assert!(parse_error(quote! { threshold = 5 }).starts_with("Unknown argument 'threshold'"));
+        assert_eq!(
+            parse_error(quote! { "a", name = "b" }),
+            "'name' is given more than once"
+        );
+        assert_eq!(
+            parse_error(quote! { sample = 1, sample = 2 }),
+            "'sample' is given more than once"
+        );
+        assert_eq!(
+            parse_error(quote! { sample = 0 }),
+            format!("'sample' must be between 1 and {}", u32::MAX)
+        );
+        assert_eq!(
+            parse_error(quote! { coalesce = 70000 }),
+            "'coalesce' must be between 1 and 60000"
+        );
+        assert_eq!(
+            parse_error(quote! { sample = "often" }),
+            "'sample' expects an integer"
+        );
+        assert_eq!(parse_error(quote! { name = 5 }), "'name' expects a string");
+        assert_eq!(
+            parse_error(quote! { sample }),
+            "'sample' expects a value (sample = ...)"
+        );
+        assert_eq!(
+            parse_error(quote! { sample = 10, coalesce }),
+            "'sample' and 'coalesce' cannot be combined"
+        );
+        assert!(parse_args(quote! { "a" "b" }).is_err());
     }
 }
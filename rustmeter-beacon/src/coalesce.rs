@@ -0,0 +1,8 @@
+use embassy_time::Instant;
+
+/// Current time in µs of `#[monitor_fn(coalesce)]` (truncated, all arithmetic is wrapping)
+#[doc(hidden)]
+#[inline(always)]
+pub fn __coalesce_now_us() -> u32 {
+    Instant::now().as_micros() as u32
+}
@@ -0,0 +1,45 @@
+//! Compile-fail checks for the instrumentation macros (run as doctests).
+//!
+//! `event_metric!` rejects non-numeric values with a readable error:
+//!
+//! ```compile_fail,E0277
+//! use rustmeter_beacon::*;
+//!
+//! fn record_state() {
+//!     event_metric!("state", "idle");
+//! }
+//! ```
+//!
+//! ```compile_fail,E0277
+//! use rustmeter_beacon::*;
+//!
+//! struct Reading(u32);
+//!
+//! fn record_reading(reading: Reading) {
+//!     event_metric!("reading", reading);
+//! }
+//! ```
+//!
+//! Metric units are capped to a few bytes:
+//!
+//! ```compile_fail,E0080
+//! use rustmeter_beacon::*;
+//!
+//! fn record_current(current: u32) {
+//!     event_metric!("current", current, unit = "milliampere");
+//! }
+//! ```
+
+/// Without the `embassy-time` feature `#[monitor_fn(coalesce)]` has no clock on the target and
+/// names the missing feature:
+///
+/// ```compile_fail
+/// use rustmeter_beacon::*;
+///
+/// #[monitor_fn(coalesce)]
+/// fn checksum(data: &[u8]) -> u8 {
+///     data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+/// }
+/// ```
+#[cfg(not(feature = "embassy-time"))]
+pub struct CoalesceWithoutEmbassyTime;
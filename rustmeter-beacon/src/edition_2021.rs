@@ -0,0 +1,12 @@
+//! Compile checks for the instrumentation macros in edition 2021 crates (run as doctests).
+//!
+//! The expansion of `monitor!` must not rely on the edition 2024 prelude:
+//!
+//! ```edition2021
+//! use rustmeter_beacon::*;
+//!
+//! async fn request() -> u32 {
+//!     let reply = monitor!("request", async { 42 }.await);
+//!     monitor!("retry", async move { reply + 1 }).await
+//! }
+//! ```
@@ -1,7 +1,15 @@
-use rustmeter_beacon_core::get_current_core_id;
+// With the `metrics-only` feature all hooks compile down to no-ops, so only monitors and
+// metrics are emitted (with `disabled` nothing at all). The hooks must still exist because
+// embassy-executor links against them.
+
+use rustmeter_beacon_core::{__executor_poll_end, __executor_poll_start, get_current_core_id};
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_poll_start(executor_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
+    __executor_poll_start(executor_id);
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_POLL_START(executor_id={}, core_id={})",
@@ -12,6 +20,10 @@ fn _embassy_trace_poll_start(executor_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_executor_idle(executor_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
+    __executor_poll_end();
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id={}, core_id={})",
@@ -22,6 +34,9 @@ fn _embassy_trace_executor_idle(executor_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_task_new(executor_id: u32, task_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_TASK_NEW(executor_id={}, core_id={}, task_id={})",
@@ -33,6 +48,9 @@ fn _embassy_trace_task_new(executor_id: u32, task_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_task_end(executor_id: u32, task_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_TASK_END(executor_id={}, core_id={}, task_id={})",
@@ -44,6 +62,9 @@ fn _embassy_trace_task_end(executor_id: u32, task_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_task_exec_begin(executor_id: u32, task_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id={}, core_id={}, task_id={})",
@@ -55,6 +76,9 @@ fn _embassy_trace_task_exec_begin(executor_id: u32, task_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_task_exec_end(excutor_id: u32, task_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_TASK_EXEC_END(executor_id={}, core_id={}, task_id={})",
@@ -66,6 +90,9 @@ fn _embassy_trace_task_exec_end(excutor_id: u32, task_id: u32) {
 
 #[unsafe(no_mangle)]
 fn _embassy_trace_task_ready_begin(executor_id: u32, task_id: u32) {
+    if cfg!(any(feature = "metrics-only", feature = "disabled")) {
+        return;
+    }
     let core_id = get_current_core_id();
     defmt::info!(
         "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id={}, core_id={}, task_id={})",
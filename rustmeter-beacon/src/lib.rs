@@ -4,3 +4,59 @@ pub use rustmeter_beacon_core::*;
 pub use rustmeter_beacon_function_monitor::*;
 
 mod embassy;
+mod spawn;
+
+#[cfg(doctest)]
+mod compile_fail;
+#[cfg(doctest)]
+mod edition_2021;
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(any(feature = "metrics-only", feature = "disabled")))]
+/// Executor and task events enabled ==> emit the wrapped statements as they are
+macro_rules! __trace_executor {
+    ($($statements:tt)*) => {
+        $($statements)*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(any(feature = "metrics-only", feature = "disabled"))]
+/// Executor and task events compiled out (`metrics-only` or `disabled` feature)
+macro_rules! __trace_executor {
+    ($($statements:tt)*) => {};
+}
+
+#[cfg(feature = "embassy-time")]
+mod coalesce;
+#[cfg(feature = "embassy-time")]
+mod timer;
+#[cfg(feature = "embassy-time")]
+pub use coalesce::*;
+#[cfg(feature = "embassy-time")]
+pub use timer::*;
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "embassy-time")]
+/// Coalescer and clock of a `#[monitor_fn(coalesce)]` function
+macro_rules! __coalescer {
+    ($name:literal) => {{
+        static COALESCER: $crate::MonitorCoalescer = $crate::MonitorCoalescer::new($name);
+        (&COALESCER, $crate::__coalesce_now_us as fn() -> u32)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "embassy-time"))]
+/// Coalescing needs a clock on the target ==> name the missing feature instead of unresolved paths
+macro_rules! __coalescer {
+    ($name:literal) => {
+        compile_error!(
+            "#[monitor_fn(coalesce)] requires the `embassy-time` feature of rustmeter-beacon"
+        )
+    };
+}
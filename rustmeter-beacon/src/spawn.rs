@@ -0,0 +1,33 @@
+#[macro_export]
+/// Spawns a task like `spawner.spawn(task(args))` and records a failed spawn in the trace.
+///
+/// A spawn fails when every instance of the task's pool is already running. The task then never
+/// runs and leaves no other trace; with this macro the host shows a red `Spawn failed` marker on
+/// the executor. The result of the spawn is passed through.
+///
+/// Only spawns routed through this macro are covered, `Spawner::spawn` itself is not hooked. Like
+/// the embassy trace hooks it emits nothing with the `metrics-only` feature.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// if try_spawn_traced!(spawner, blink(led)).is_err() {
+///     // fall back ...
+/// }
+/// ```
+macro_rules! try_spawn_traced {
+    ($spawner:expr, $token:expr) => {{
+        let spawner: &embassy_executor::Spawner = &$spawner;
+        let result = spawner.spawn($token);
+        rustmeter_beacon::__trace_executor! {
+            if result.is_err() {
+                defmt::info!(
+                    "@EVENT_EMBASSY_TASK_SPAWN_FAILED(executor_id={=u32},core_id={})",
+                    spawner.executor_id() as u32,
+                    rustmeter_beacon::get_current_core_id()
+                );
+            }
+        }
+        result
+    }};
+}
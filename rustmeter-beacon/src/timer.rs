@@ -0,0 +1,90 @@
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use rustmeter_beacon_core::{get_current_core_id, metric_value};
+
+/// Number of traced timers that are awaited right now
+static PENDING_TIMERS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Counts a traced timer as pending while it lives. Dropped when the timer fired or when its
+/// future was cancelled (e.g. the losing branch of a `select` or `with_timeout`)
+struct PendingTimer;
+
+impl PendingTimer {
+    fn new() -> Self {
+        critical_section::with(|cs| {
+            let pending = PENDING_TIMERS.borrow(cs);
+            pending.set(pending.get().saturating_add(1));
+        });
+        PendingTimer
+    }
+}
+
+impl Drop for PendingTimer {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            let pending = PENDING_TIMERS.borrow(cs);
+            pending.set(pending.get().saturating_sub(1));
+        });
+    }
+}
+
+/// Traced replacement for `Timer::at(deadline).await`.
+///
+/// Emits a timer-set event before waiting and a timer-fired event once the calling task resumes.
+/// The host attaches both to the task running on this core and reports how late the task became
+/// ready / got polled again compared to the requested deadline (wakeup latency).
+pub async fn traced_timer_at(deadline: Instant) {
+    if cfg!(feature = "disabled") {
+        return Timer::at(deadline).await;
+    }
+
+    let deadline_us = deadline.as_micros();
+    defmt::info!(
+        "@EVENT_TIMER_SET(deadline_us={=u64},core_id={})",
+        deadline_us,
+        get_current_core_id()
+    );
+
+    let pending_timer = PendingTimer::new();
+    Timer::at(deadline).await;
+    drop(pending_timer);
+
+    defmt::info!(
+        "@EVENT_TIMER_FIRED(deadline_us={=u64},core_id={})",
+        deadline_us,
+        get_current_core_id()
+    );
+}
+
+/// Traced replacement for `Timer::after(duration).await` (see [`traced_timer_at`]).
+pub async fn traced_timer_after(duration: Duration) {
+    traced_timer_at(Instant::now() + duration).await
+}
+
+/// Number of [`traced_timer_at`] / [`traced_timer_after`] calls awaiting their deadline right
+/// now, over all tasks and cores
+pub fn pending_timer_count() -> u32 {
+    critical_section::with(|cs| PENDING_TIMERS.borrow(cs).get())
+}
+
+/// Report [`pending_timer_count`] as metric `traced_timers_pending`, shown as counter track by the
+/// host.
+///
+/// The timer queue of the embassy-time driver cannot be read from outside, so this only counts
+/// the traced timers (plain `Timer`s, `Ticker`s and timeouts of other crates are missing) and
+/// is a sample of the moment it is called, not an exact history. Call it periodically from a
+/// low-priority task, e.g. every 100 ms.
+pub fn report_pending_timers() {
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    defmt::info!(
+        "@EVENT_METRIC(name={=istr},value={},core_id={})",
+        defmt::intern!("traced_timers_pending"),
+        metric_value(pending_timer_count()),
+        get_current_core_id()
+    );
+}
@@ -43,18 +43,44 @@ impl CargoChildProcess {
         self.logs_recver.clone()
     }
 
-    pub fn new_start_run(release: bool, project_dir: &str) -> anyhow::Result<Self> {
+    /// Start `cargo run` (build, flash and attach via the configured runner). With `detach_stdin`
+    /// the runner does not get the terminal input (rustmeter reads it itself)
+    pub fn new_start_run(
+        release: bool,
+        project_dir: &str,
+        detach_stdin: bool,
+    ) -> anyhow::Result<Self> {
+        let stdin = if detach_stdin {
+            Stdio::null()
+        } else {
+            Stdio::inherit()
+        };
+        Self::new_start("run", release, project_dir, stdin)
+    }
+
+    /// Start `cargo build` only (no hardware involved)
+    pub fn new_start_build(release: bool, project_dir: &str) -> anyhow::Result<Self> {
+        Self::new_start("build", release, project_dir, Stdio::inherit())
+    }
+
+    fn new_start(
+        subcommand: &str,
+        release: bool,
+        project_dir: &str,
+        stdin: Stdio,
+    ) -> anyhow::Result<Self> {
         let (build_status_sender, build_status_recver) = crossbeam::channel::unbounded();
         let (logs_sender, logs_recver) = crossbeam::channel::unbounded();
 
         // Create Command
         let mut cmd = Command::new("cargo");
+        cmd.stdin(stdin);
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::inherit()); // directly inherit stderr to main process
         cmd.current_dir(project_dir);
 
         // Add arguments
-        cmd.arg("run");
+        cmd.arg(subcommand);
         cmd.arg("--message-format")
             .arg("json-diagnostic-rendered-ansi"); // for easier parsing of build output
         if release {
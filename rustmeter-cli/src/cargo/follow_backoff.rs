@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Delay before the first re-attach of `--follow`
+const INITIAL_DELAY: Duration = Duration::from_millis(250);
+/// Longest delay between re-attaches of a runner that keeps exiting
+const MAX_DELAY: Duration = Duration::from_secs(4);
+/// A runner that stayed attached this long worked, its next exit starts over with a short delay
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+/// Number of re-attaches in a row that exited right away before `--follow` gives up
+pub const MAX_QUICK_RESTARTS: u32 = 10;
+
+/// Backoff of the `--follow` restart loop: a runner that cannot attach (e.g. probe unplugged)
+/// exits immediately, which would otherwise restart `cargo run` in a tight loop
+#[derive(Debug)]
+pub struct FollowBackoff {
+    delay: Duration,
+    quick_restarts: u32,
+}
+
+impl FollowBackoff {
+    pub fn new() -> Self {
+        Self {
+            delay: INITIAL_DELAY,
+            quick_restarts: 0,
+        }
+    }
+
+    /// The runner exited after being attached for `uptime`. Returns how long to wait before
+    /// re-attaching, or `None` once it exited right away [`MAX_QUICK_RESTARTS`] times in a row
+    pub fn on_runner_exit(&mut self, uptime: Duration) -> Option<Duration> {
+        if uptime >= STABLE_AFTER {
+            *self = Self::new();
+        }
+        if self.quick_restarts >= MAX_QUICK_RESTARTS {
+            return None;
+        }
+
+        self.quick_restarts += 1;
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow_backoff() {
+        let mut backoff = FollowBackoff::new();
+        let delays: Vec<_> = (0..MAX_QUICK_RESTARTS)
+            .map(|_| backoff.on_runner_exit(Duration::ZERO).unwrap())
+            .collect();
+        assert_eq!(delays[0], INITIAL_DELAY);
+        assert_eq!(delays[1], INITIAL_DELAY * 2);
+        assert_eq!(*delays.last().unwrap(), MAX_DELAY);
+
+        // Keeps exiting right away ==> give up
+        assert_eq!(backoff.on_runner_exit(Duration::ZERO), None);
+
+        // A runner that stayed attached starts over
+        assert_eq!(backoff.on_runner_exit(STABLE_AFTER), Some(INITIAL_DELAY));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod cargo_build;
 pub mod cargo_child;
+pub mod follow_backoff;
+pub mod runner_config;
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+/// Read the target chip from the runner configured in `.cargo/config.toml` of the project
+/// (e.g. `runner = "probe-rs run --chip STM32F446RCTx"`). Best effort: `None` if there is no
+/// such runner
+pub fn configured_chip(project_dir: &Path) -> Option<String> {
+    let config = std::fs::read_to_string(project_dir.join(".cargo").join("config.toml")).ok()?;
+    config
+        .lines()
+        .filter(|line| line.trim_start().starts_with("runner"))
+        .find_map(chip_of_runner)
+}
+
+/// Get the value of the `--chip` argument of a runner line
+fn chip_of_runner(runner_line: &str) -> Option<String> {
+    let (_, command) = runner_line.split_once('=')?;
+    let mut arguments = command.trim().trim_matches(['"', '\'']).split_whitespace();
+    while let Some(argument) = arguments.next() {
+        if argument == "--chip" {
+            return arguments.next().map(str::to_string);
+        }
+        if let Some(chip) = argument.strip_prefix("--chip=") {
+            return Some(chip.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chip_of_runner() {
+        assert_eq!(
+            chip_of_runner(
+                r#"runner = "espflash flash --monitor --chip esp32 --log-format defmt""#
+            ),
+            Some("esp32".to_string())
+        );
+        assert_eq!(
+            chip_of_runner("runner = 'probe-rs run --chip=STM32F446RCTx'"),
+            Some("STM32F446RCTx".to_string())
+        );
+        assert_eq!(chip_of_runner(r#"runner = "probe-rs run""#), None);
+    }
+}
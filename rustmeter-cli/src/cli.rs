@@ -1,4 +1,6 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+use crate::{perfetto_backend::trigger_ring::Trigger, tracing::task::TaskView};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -10,6 +12,207 @@ pub struct CommandLineArgs {
     // Choose Embedded Project Directory
     #[clap(long, default_value = ".")]
     pub project: String,
+
+    /// Only build the firmware and check its instrumentation (no flashing, no tracing)
+    #[clap(long, action)]
+    pub dry_run: bool,
+
+    /// Run a synthetic trace through the whole host pipeline to check the installation (no hardware needed)
+    #[clap(long, action)]
+    pub selftest: bool,
+
+    /// Convert an `.rmt` capture into a Perfetto trace (written next to it as `.json`) and exit
+    #[clap(long, value_name = "FILE")]
+    pub convert: Option<String>,
+
+    /// Only convert the part of the --convert capture from this device time (in seconds) on
+    #[clap(long, value_name = "SECS", requires = "convert")]
+    pub from: Option<f64>,
+
+    /// Check the invariants of a written Perfetto trace (matched slices, ordered timestamps, named tracks) and exit
+    #[clap(long, value_name = "TRACE")]
+    pub validate: Option<String>,
+
+    /// Compare the monitor durations of two `.rmt` captures and exit (fails on regressions, see --threshold)
+    #[clap(long, num_args = 2, value_names = ["BASELINE", "CANDIDATE"])]
+    pub diff: Option<Vec<String>>,
+
+    /// Slowdown of a monitor's mean or p99 duration in percent that --diff reports as regression
+    #[clap(long, value_name = "PCT", default_value_t = 10.0)]
+    pub threshold: f64,
+
+    /// Format of the written capture
+    #[clap(long, value_enum, default_value_t = OutputFormat::Perfetto)]
+    pub output_format: OutputFormat,
+
+    /// Render task states as slices on the task track or as async tracks (one per task and state)
+    #[clap(long, value_enum, default_value_t = TaskView::Slices)]
+    pub task_view: TaskView,
+
+    /// Merge executor idle periods shorter than this many µs into the surrounding busy slice
+    #[clap(long, value_name = "US")]
+    pub merge_idle_us: Option<u64>,
+
+    /// Show one process per core holding its executors and tasks, instead of one process per executor
+    #[clap(long, action)]
+    pub per_core_processes: bool,
+
+    /// JSON file mapping monitor and category names to trace colors, e.g. `{"monitors": {"sensor_read": "good"}}`
+    #[clap(long, value_name = "PATH")]
+    pub color_config: Option<String>,
+
+    /// JSON file grouping metrics into named panel processes
+    #[clap(long, value_name = "PATH")]
+    pub metric_panels: Option<String>,
+
+    /// Only write monitors whose name matches this glob pattern (repeatable)
+    #[clap(long, value_name = "PATTERN")]
+    pub include_monitor: Vec<String>,
+
+    /// Do not write monitors whose name matches this glob pattern (repeatable, wins over includes)
+    #[clap(long, value_name = "PATTERN")]
+    pub exclude_monitor: Vec<String>,
+
+    /// Only write tasks whose name matches this glob pattern (repeatable)
+    #[clap(long, value_name = "PATTERN")]
+    pub include_task: Vec<String>,
+
+    /// Do not write tasks whose name matches this glob pattern (repeatable, wins over includes)
+    #[clap(long, value_name = "PATTERN")]
+    pub exclude_task: Vec<String>,
+
+    /// Show a live dashboard (core utilization, busiest tasks, metrics) instead of the log output
+    #[cfg(feature = "tui")]
+    #[clap(long, action)]
+    pub tui: bool,
+
+    /// Emit running min/max counter tracks next to every metric
+    #[clap(long, action)]
+    pub counter_bands: bool,
+
+    /// Fail the session if a monitor exceeds its budget, e.g. `--assert-max-us "process_data=500"` (repeatable)
+    #[clap(long = "assert-max-us", value_name = "MONITOR=US", value_parser = parse_monitor_budget)]
+    pub assert_max_us: Vec<(String, u64)>,
+
+    /// Name an application defined yield reason code of `yield_reason!`, e.g. `--yield-reason 16=modem` (repeatable)
+    #[clap(long = "yield-reason", value_name = "CODE=LABEL", value_parser = parse_yield_reason)]
+    pub yield_reasons: Vec<(u8, String)>,
+
+    /// Leave monitors starting within this many ms after the first event out of budgets, summaries and reports (still shown in the trace)
+    #[clap(long, value_name = "MS")]
+    pub warmup_ms: Option<u64>,
+
+    /// Stop the session automatically after the given number of seconds
+    #[clap(long, value_name = "SECS")]
+    pub capture_duration: Option<f64>,
+
+    /// Maximum size of the trace file in MB. Stops the capture when reached (see --rotate)
+    #[clap(long, value_name = "MB")]
+    pub max_file_size: Option<u64>,
+
+    /// Continue in a new trace file when --max-file-size is reached instead of stopping
+    #[clap(long, action, requires = "max_file_size")]
+    pub rotate: bool,
+
+    /// Flag tasks preempted for at least this long (µs) inside a monitored scope as potential priority inversion
+    #[clap(long, value_name = "US")]
+    pub inversion_threshold_us: Option<u64>,
+
+    /// Buffer at most this many trace events for the file writer and drop new ones while it is full
+    #[clap(long, value_name = "EVENTS")]
+    pub max_buffered_events: Option<usize>,
+
+    /// Drop tracing events whose timestamp jumps back by more than this many µs (decoder desync)
+    #[clap(long, value_name = "US")]
+    pub out_of_order_tolerance_us: Option<u64>,
+
+    /// Force-close the oldest open monitors of a task once this many are nested (default 64)
+    #[clap(long, value_name = "DEPTH")]
+    pub max_monitor_depth: Option<usize>,
+
+    /// Show a monitor calling itself as one slice with its recursion depth instead of one per call
+    #[clap(long, action)]
+    pub collapse_recursion: bool,
+
+    /// Keep capturing across target resets: re-attach when the runner exits and continue the timeline
+    #[clap(long, action)]
+    pub follow: bool,
+
+    /// Firmware timestamps are CPU cycles (`cycle_timestamp!` of rustmeter-beacon) of a core clocked at this frequency
+    #[clap(long, value_name = "HZ")]
+    pub cpu_hz: Option<u64>,
+
+    /// Read host markers from stdin (`[<device seconds>] <label>` per line) and add them to the trace
+    #[clap(long, action)]
+    pub stdin_markers: bool,
+
+    /// Place log lines without defmt timestamp at the latest trace timestamp instead of dropping them
+    #[clap(long, action)]
+    pub align_logs: bool,
+
+    /// Anchor trace timestamps to the host wall-clock time at session start
+    #[clap(long, action)]
+    pub wall_clock: bool,
+
+    /// Write a JSON summary (monitor statistics, task and core utilization, dropped events) to this file at session end
+    #[clap(long, value_name = "PATH")]
+    pub report_json: Option<String>,
+
+    /// Print the poll count, poll rate and mean running time per poll of the most polled tasks
+    #[clap(long, action)]
+    pub poll_stats: bool,
+
+    /// Write rustmeter version, build profile, chip and capture options as metadata into the trace
+    #[clap(long, action)]
+    pub embed_metadata: bool,
+
+    /// Only keep the trace events of the last SECS seconds in memory and write them once --trigger fires
+    #[clap(long, value_name = "SECS", requires = "trigger")]
+    pub ring_seconds: Option<f64>,
+
+    /// Condition that writes out the --ring-seconds window: `METRIC>VALUE`, `METRIC<VALUE` or `marker=LABEL`
+    #[clap(long, value_name = "CONDITION", value_parser = Trigger::parse, requires = "ring_seconds")]
+    pub trigger: Option<Trigger>,
+
+    /// End the session once the --trigger fired instead of recording on
+    #[clap(long, action, requires = "trigger")]
+    pub stop_on_trigger: bool,
+
+    /// Open the finished trace in Perfetto UI (served from a local HTTP server on port 9001)
+    #[clap(long, action)]
+    pub open: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Perfetto JSON trace (open it in ui.perfetto.dev)
+    Perfetto,
+    /// Compact binary rustmeter capture with a time index (see `--convert`)
+    Rmt,
+}
+
+/// Parse a monitor budget in the form `name=us`
+fn parse_monitor_budget(arg: &str) -> Result<(String, u64), String> {
+    let (name, budget) = arg
+        .rsplit_once('=')
+        .ok_or(format!("Expected MONITOR=US, got '{arg}'"))?;
+    let budget = budget
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid budget in '{arg}': {e}"))?;
+    Ok((name.trim().to_string(), budget))
+}
+
+/// Parse a yield reason name in the form `code=label`
+fn parse_yield_reason(arg: &str) -> Result<(u8, String), String> {
+    let (code, label) = arg
+        .split_once('=')
+        .ok_or(format!("Expected CODE=LABEL, got '{arg}'"))?;
+    let code = code
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid code in '{arg}': {e}"))?;
+    Ok((code, label.trim().to_string()))
 }
 
 impl CommandLineArgs {
@@ -17,3 +220,28 @@ impl CommandLineArgs {
         <Self as Parser>::parse()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_monitor_budget() {
+        assert_eq!(
+            parse_monitor_budget("process_data=500"),
+            Ok(("process_data".to_string(), 500))
+        );
+        assert!(parse_monitor_budget("process_data").is_err());
+        assert!(parse_monitor_budget("process_data=fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_yield_reason() {
+        assert_eq!(
+            parse_yield_reason("16=modem"),
+            Ok((16, "modem".to_string()))
+        );
+        assert!(parse_yield_reason("16").is_err());
+        assert!(parse_yield_reason("256=modem").is_err());
+    }
+}
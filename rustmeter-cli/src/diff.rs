@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    rmt_file::RmtCapture,
+    tracing::{
+        duration_histogram::DurationHistogram,
+        tracing_instance::{TracingInstance, TracingOptions},
+    },
+};
+
+/// Duration statistics of one monitor in a capture
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorStats {
+    pub count: usize,
+    pub min_us: u128,
+    pub max_us: u128,
+    pub mean_us: f64,
+    pub p99_us: u128,
+}
+
+impl From<&DurationHistogram> for MonitorStats {
+    fn from(durations: &DurationHistogram) -> Self {
+        Self {
+            count: durations.count() as usize,
+            min_us: durations.min_us(),
+            max_us: durations.max_us(),
+            mean_us: durations.mean_us(),
+            p99_us: durations.percentile_us(99),
+        }
+    }
+}
+
+/// Comparison of one monitor between baseline and candidate
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorDiff {
+    Both {
+        baseline: MonitorStats,
+        candidate: MonitorStats,
+        /// Mean or p99 got slower by more than the threshold
+        regression: bool,
+    },
+    OnlyBaseline(MonitorStats),
+    OnlyCandidate(MonitorStats),
+}
+
+/// Relative change in percent (`None` if the baseline is zero)
+fn change_pct(baseline: f64, candidate: f64) -> Option<f64> {
+    (baseline > 0.0).then(|| (candidate - baseline) / baseline * 100.0)
+}
+
+/// Replay a capture and collect the duration statistics per monitor name
+pub fn collect_monitor_stats(
+    rmt_filename: &Path,
+) -> anyhow::Result<BTreeMap<String, MonitorStats>> {
+    let capture = RmtCapture::open(rmt_filename)?;
+    let mut tracing_instance =
+        TracingInstance::new(capture.symbols.clone(), TracingOptions::default());
+
+    // Only the monitors are needed, not the trace
+    let trace_event_recver = tracing_instance.get_trace_event_receiver();
+    std::thread::spawn(move || trace_event_recver.iter().for_each(drop));
+
+    capture.replay(&mut tracing_instance, None)?;
+
+    Ok(tracing_instance
+        .get_monitor_durations()
+        .iter()
+        .map(|(name, durations)| (name.clone(), MonitorStats::from(durations)))
+        .collect())
+}
+
+/// Match the monitors of both captures by name
+pub fn compare_monitor_stats(
+    baseline: &BTreeMap<String, MonitorStats>,
+    candidate: &BTreeMap<String, MonitorStats>,
+    threshold_pct: f64,
+) -> BTreeMap<String, MonitorDiff> {
+    let mut diffs = BTreeMap::new();
+    for (name, baseline_stats) in baseline {
+        let diff = match candidate.get(name) {
+            Some(candidate_stats) => {
+                let slower = |baseline: f64, candidate: f64| {
+                    change_pct(baseline, candidate).is_some_and(|pct| pct > threshold_pct)
+                };
+                MonitorDiff::Both {
+                    regression: slower(baseline_stats.mean_us, candidate_stats.mean_us)
+                        || slower(baseline_stats.p99_us as f64, candidate_stats.p99_us as f64),
+                    baseline: baseline_stats.clone(),
+                    candidate: candidate_stats.clone(),
+                }
+            }
+            None => MonitorDiff::OnlyBaseline(baseline_stats.clone()),
+        };
+        diffs.insert(name.clone(), diff);
+    }
+    for (name, candidate_stats) in candidate {
+        if !baseline.contains_key(name) {
+            diffs.insert(
+                name.clone(),
+                MonitorDiff::OnlyCandidate(candidate_stats.clone()),
+            );
+        }
+    }
+    diffs
+}
+
+/// Format a value change, e.g. `120.0 -> 150.0 (+25.0%)`
+fn format_change(baseline: f64, candidate: f64) -> String {
+    match change_pct(baseline, candidate) {
+        Some(pct) => format!("{baseline:.1} -> {candidate:.1} ({pct:+.1}%)"),
+        None => format!("{baseline:.1} -> {candidate:.1}"),
+    }
+}
+
+/// Compare two `.rmt` captures per monitor (`--diff`). Fails if a monitor got slower than the
+/// threshold allows
+pub fn run_diff(baseline: &Path, candidate: &Path, threshold_pct: f64) -> anyhow::Result<()> {
+    let diffs = compare_monitor_stats(
+        &collect_monitor_stats(baseline)?,
+        &collect_monitor_stats(candidate)?,
+        threshold_pct,
+    );
+
+    println!(
+        "{:<32} {:>15} {:>32} {:>32}  status",
+        "monitor", "calls", "mean us", "p99 us"
+    );
+    let mut regressions = 0;
+    for (name, diff) in &diffs {
+        match diff {
+            MonitorDiff::Both {
+                baseline,
+                candidate,
+                regression,
+            } => {
+                regressions += *regression as usize;
+                println!(
+                    "{name:<32} {:>15} {:>32} {:>32}  {}",
+                    format!("{} -> {}", baseline.count, candidate.count),
+                    format_change(baseline.mean_us, candidate.mean_us),
+                    format_change(baseline.p99_us as f64, candidate.p99_us as f64),
+                    if *regression { "REGRESSION" } else { "ok" }
+                );
+            }
+            MonitorDiff::OnlyBaseline(stats) | MonitorDiff::OnlyCandidate(stats) => {
+                println!(
+                    "{name:<32} {:>15} {:>32} {:>32}  only in {}",
+                    stats.count,
+                    format!("{:.1}", stats.mean_us),
+                    stats.p99_us,
+                    if matches!(diff, MonitorDiff::OnlyBaseline(_)) {
+                        "baseline"
+                    } else {
+                        "candidate"
+                    }
+                );
+            }
+        }
+    }
+
+    if regressions > 0 {
+        return Err(anyhow::anyhow!(
+            "{regressions} monitor(s) got slower by more than {threshold_pct}%"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(durations_us: &[u128]) -> MonitorStats {
+        let mut durations = DurationHistogram::default();
+        for duration_us in durations_us {
+            durations.record(*duration_us);
+        }
+        MonitorStats::from(&durations)
+    }
+
+    #[test]
+    fn test_compare_monitor_stats() {
+        let baseline = BTreeMap::from([
+            ("parse".to_string(), stats(&[100, 100, 100, 100])),
+            ("encrypt".to_string(), stats(&[50, 50])),
+            ("removed".to_string(), stats(&[10])),
+        ]);
+        let candidate = BTreeMap::from([
+            ("parse".to_string(), stats(&[100, 100, 100, 300])),
+            ("encrypt".to_string(), stats(&[52, 52])),
+            ("added".to_string(), stats(&[20])),
+        ]);
+        assert_eq!(stats(&[100, 100, 100, 300]).p99_us, 300);
+        assert_eq!(stats(&[100, 100, 100, 300]).mean_us, 150.0);
+
+        let diffs = compare_monitor_stats(&baseline, &candidate, 10.0);
+        assert!(matches!(
+            diffs["parse"],
+            MonitorDiff::Both {
+                regression: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            diffs["encrypt"],
+            MonitorDiff::Both {
+                regression: false,
+                ..
+            }
+        ));
+        assert!(matches!(diffs["removed"], MonitorDiff::OnlyBaseline(_)));
+        assert!(matches!(diffs["added"], MonitorDiff::OnlyCandidate(_)));
+    }
+}
@@ -1,8 +1,8 @@
 use anyhow::Context;
-use object::{Object, ObjectSymbol};
+use object::{Object, ObjectSection, ObjectSymbol};
 use std::{collections::HashMap, path::Path, sync::Arc};
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FirmwareAddressMap(Arc<HashMap<u64, String>>);
 
 impl FirmwareAddressMap {
@@ -16,13 +16,13 @@ impl FirmwareAddressMap {
             }
 
             // Add symbol name if available
-            if let Ok(name) = symbol.name() {
-                if !name.is_empty() {
-                    let demangled = rustc_demangle::demangle(name).to_string();
-                    addr_map.insert(addr, demangled);
+            if let Ok(name) = symbol.name()
+                && !name.is_empty()
+            {
+                let demangled = rustc_demangle::demangle(name).to_string();
+                addr_map.insert(addr, demangled);
 
-                    // Reinsert to overwrite potential aliases
-                }
+                // Reinsert to overwrite potential aliases
             }
         }
 
@@ -37,6 +37,20 @@ impl FirmwareAddressMap {
         Ok(Self::new_from_file(elf_file))
     }
 
+    pub fn from_symbols(symbols: HashMap<u64, String>) -> Self {
+        Self(Arc::new(symbols))
+    }
+
+    /// Iterate over all symbols (address, full name)
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.0.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    /// Number of known symbols
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
     pub fn get_symbol_name(&self, addr: u64) -> Option<String> {
         self.0
             .get(&addr)
@@ -49,3 +63,150 @@ fn try_extract_short_name(full_name: &str) -> String {
     let pool_index = full_name.find("::POOL").unwrap_or(full_name.len());
     full_name[0..pool_index].to_string()
 }
+
+/// Overview of the defmt table of a firmware ELF (used by `--dry-run`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DefmtTableSummary {
+    /// Number of entries (log sites and interned strings) in the `.defmt` section
+    pub entry_count: usize,
+    /// Number of function / scope monitor sites
+    pub monitor_count: usize,
+    /// Number of `event_metric!` sites
+    pub metric_count: usize,
+}
+
+/// Scan the `.defmt` section of the firmware ELF. Returns `None` if there is no defmt table
+pub fn scan_defmt_table(elf_path: &Path) -> anyhow::Result<Option<DefmtTableSummary>> {
+    let bin_data = std::fs::read(elf_path).context("Could not open elf file")?;
+    let elf_file: object::File<'_> =
+        object::File::parse(&*bin_data).context("Could not parse elf file")?;
+
+    let Some(defmt_section) = elf_file.section_by_name(".defmt") else {
+        return Ok(None);
+    };
+
+    let mut summary = DefmtTableSummary::default();
+    for symbol in elf_file.symbols() {
+        if symbol.section_index() != Some(defmt_section.index()) {
+            continue;
+        }
+
+        let Some(data) = symbol.name().ok().and_then(defmt_symbol_data) else {
+            continue;
+        };
+        summary.entry_count += 1;
+        if data.starts_with("@EVENT_MONITOR_START") || data.starts_with("@EVENT_MONITOR_AGGREGATE")
+        {
+            summary.monitor_count += 1;
+        } else if data.starts_with("@EVENT_METRIC") {
+            summary.metric_count += 1;
+        }
+    }
+
+    Ok(Some(summary))
+}
+
+/// Size of a monitor record in the `.rustmeter_monitors` section
+/// (`MonitorRecord` of rustmeter-beacon-core: kind, name length, 62 name bytes)
+const MONITOR_RECORD_SIZE: usize = 64;
+
+/// A monitor compiled into the firmware (from the `.rustmeter_monitors` section)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorRecord {
+    pub kind: MonitorKind,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonitorKind {
+    Function,
+    Scope,
+    Unknown(u8),
+}
+
+/// Read the monitor registry of the firmware. Returns `None` if the firmware was built without
+/// the `monitor-registry` feature of rustmeter-beacon
+pub fn read_monitor_registry(elf_path: &Path) -> anyhow::Result<Option<Vec<MonitorRecord>>> {
+    let bin_data = std::fs::read(elf_path).context("Could not open elf file")?;
+    let elf_file: object::File<'_> =
+        object::File::parse(&*bin_data).context("Could not parse elf file")?;
+
+    let Some(section) = elf_file.section_by_name(".rustmeter_monitors") else {
+        return Ok(None);
+    };
+    let data = section
+        .data()
+        .context("Could not read .rustmeter_monitors section")?;
+
+    Ok(Some(parse_monitor_records(data)))
+}
+
+/// Parse the raw `.rustmeter_monitors` section (sorted by name, duplicates of monitors that got
+/// inlined / monomorphized multiple times removed)
+fn parse_monitor_records(data: &[u8]) -> Vec<MonitorRecord> {
+    let mut records: Vec<MonitorRecord> = data
+        .chunks_exact(MONITOR_RECORD_SIZE)
+        .map(|record| {
+            let kind = match record[0] {
+                0 => MonitorKind::Function,
+                1 => MonitorKind::Scope,
+                other => MonitorKind::Unknown(other),
+            };
+            let name_len = (record[1] as usize).min(MONITOR_RECORD_SIZE - 2);
+            let name = String::from_utf8_lossy(&record[2..2 + name_len]).to_string();
+            MonitorRecord { kind, name }
+        })
+        .collect();
+
+    records.sort_by(|a, b| a.name.cmp(&b.name));
+    records.dedup();
+    records
+}
+
+/// Extract the format string / interned string of a defmt symbol name
+/// (e.g. `{"package":"app","tag":"defmt_info","data":"Hello","disambiguator":"1","crate_name":"app"}`)
+fn defmt_symbol_data(symbol_name: &str) -> Option<String> {
+    let symbol: serde_json::Value = serde_json::from_str(symbol_name).ok()?;
+    symbol.get("data")?.as_str().map(|data| data.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_monitor_records() {
+        let mut data = Vec::new();
+        for (kind, name) in [(1u8, "scope"), (0, "parse"), (0, "parse")] {
+            let mut record = vec![0u8; MONITOR_RECORD_SIZE];
+            record[0] = kind;
+            record[1] = name.len() as u8;
+            record[2..2 + name.len()].copy_from_slice(name.as_bytes());
+            data.extend(record);
+        }
+
+        assert_eq!(
+            parse_monitor_records(&data),
+            vec![
+                MonitorRecord {
+                    kind: MonitorKind::Function,
+                    name: "parse".to_string()
+                },
+                MonitorRecord {
+                    kind: MonitorKind::Scope,
+                    name: "scope".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defmt_symbol_data() {
+        let symbol = r#"{"package":"app","tag":"defmt_info","data":"@EVENT_MONITOR_START(function_name={=istr},core_id={})","disambiguator":"42","crate_name":"app"}"#;
+        assert_eq!(
+            defmt_symbol_data(symbol).as_deref(),
+            Some("@EVENT_MONITOR_START(function_name={=istr},core_id={})")
+        );
+        assert_eq!(defmt_symbol_data("_defmt_version_ = 4"), None);
+    }
+}
@@ -1,23 +1,54 @@
 use std::{
+    collections::BTreeMap,
     path::Path,
     sync::{Arc, atomic::AtomicBool},
     time::Duration,
 };
 
-use crossbeam::select;
+use anyhow::Context;
+use crossbeam::{channel::Receiver, select};
 
 use crate::{
-    cargo::cargo_child::CargoChildProcess, cli::CommandLineArgs, elf_file::FirmwareAddressMap,
-    perfetto_backend::file_writer::spawn_perfetto_file_writer,
-    tracing::tracing_instance::TracingInstance,
+    cargo::{
+        cargo_child::CargoChildProcess,
+        follow_backoff::{FollowBackoff, MAX_QUICK_RESTARTS},
+        runner_config::configured_chip,
+    },
+    cli::{CommandLineArgs, OutputFormat},
+    elf_file::{FirmwareAddressMap, read_monitor_registry, scan_defmt_table},
+    perfetto_backend::{
+        event_tee::TraceEventTee,
+        file_writer::{FileWriterOptions, spawn_perfetto_file_writer},
+        trace_event::TracingEvent,
+        trigger_ring::{TriggerRing, spawn_trigger_ring},
+        ui_server::open_in_perfetto_ui,
+    },
+    report::{SessionReport, TaskReport},
+    rmt_file::spawn_rmt_file_writer,
+    time::{CycleClock, EmbassyTime},
+    tracing::{
+        log_event::LogEvent,
+        log_line::{LogLevel, LogLine},
+        metric_panels::MetricPanels,
+        monitor_colors::MonitorColors,
+        name_filter::NameFilter,
+        tracing_instance::{TracingInstance, TracingOptions, parse_host_marker},
+    },
 };
 
 mod cargo;
 mod cli;
+mod diff;
 mod elf_file;
 mod perfetto_backend;
+mod report;
+mod rmt_file;
+mod selftest;
 mod time;
 mod tracing;
+#[cfg(feature = "tui")]
+mod tui;
+mod validate;
 
 fn main() -> anyhow::Result<()> {
     // Set CTRL-C handler
@@ -30,8 +61,60 @@ fn main() -> anyhow::Result<()> {
     // Parse command line arguments
     let args = CommandLineArgs::parse();
 
+    if args.selftest {
+        return selftest::run_selftest(&std::env::temp_dir().join("rustmeter-selftest.json"));
+    }
+
+    if let Some(trace_filename) = &args.validate {
+        return validate::run_validate(Path::new(trace_filename));
+    }
+
+    if let Some(captures) = &args.diff {
+        return diff::run_diff(
+            Path::new(&captures[0]),
+            Path::new(&captures[1]),
+            args.threshold,
+        );
+    }
+
+    if let Some(rmt_filename) = &args.convert {
+        let rmt_filename = Path::new(rmt_filename);
+        let perfetto_filename = rmt_filename.with_extension("json");
+        let from = args
+            .from
+            .map(|secs| {
+                EmbassyTime::try_from_secs_f64(secs)
+                    .context("--from must be a non-negative number of seconds")
+            })
+            .transpose()?;
+        rmt_file::convert_to_perfetto(rmt_filename, &perfetto_filename, from)?;
+        println!("Trace written to {}", perfetto_filename.display());
+        if args.open {
+            open_in_perfetto_ui(&perfetto_filename, exit_flag)?;
+        }
+        return Ok(());
+    }
+
+    // Load the config files before building, so a broken file fails fast
+    let monitor_colors = args
+        .color_config
+        .as_deref()
+        .map(|path| MonitorColors::load(Path::new(path)))
+        .transpose()?
+        .unwrap_or_default();
+    let metric_panels = args
+        .metric_panels
+        .as_deref()
+        .map(|path| MetricPanels::load(Path::new(path)))
+        .transpose()?
+        .unwrap_or_default();
+
     // Start Cargo child process and wait for build to finish
-    let mut cargo_child_process = CargoChildProcess::new_start_run(args.release, &args.project)?;
+    let mut cargo_child_process = if args.dry_run {
+        CargoChildProcess::new_start_build(args.release, &args.project)?
+    } else {
+        CargoChildProcess::new_start_run(args.release, &args.project, args.stdin_markers)?
+    };
     let build_status = cargo_child_process.wait_build_finish()?;
 
     // Check build status
@@ -52,113 +135,627 @@ fn main() -> anyhow::Result<()> {
     let elf_path = Path::new(&elf_path);
     let firmware_addr_map = FirmwareAddressMap::new_from_elf_path(elf_path)?;
 
-    // filter log events and print everything else to stdout
-    let raw_logs_recver = cargo_child_process.get_logs_receiver();
-    let (log_line_sender, log_line_recver) = crossbeam::channel::unbounded();
-    let (log_event_sender, log_event_recver) = crossbeam::channel::unbounded();
+    if args.dry_run {
+        return dry_run_report(elf_path, &firmware_addr_map);
+    }
+
+    // Without defmt no tracing events can reach the host, but the plain output is still useful
+    if scan_defmt_table(elf_path)?.is_none() {
+        println!(
+            "Notice: no .defmt section found in the firmware. Continuing, but no tracing events will be captured (is defmt linked, e.g. `-C link-arg=-Tdefmt.x`?)"
+        );
+    }
+
+    // filter log events and print everything else to stdout (the logs of every runner started
+    // with --follow end up in the same channel)
+    let (raw_logs_sender, raw_logs_recver) = crossbeam::channel::unbounded();
+    forward_runner_logs(&cargo_child_process, raw_logs_sender.clone());
+    // One channel for all decoded lines, so the tracing thread sees them in stream order
+    let (decoded_line_sender, decoded_line_recver) = crossbeam::channel::unbounded();
+    let align_logs = args.align_logs;
+    let (rmt_line_sender, rmt_line_recver) = crossbeam::channel::unbounded();
+    let rmt_line_sender = (args.output_format == OutputFormat::Rmt).then_some(rmt_line_sender);
+    let mut cycle_clock = args.cpu_hz.map(CycleClock::new);
+    // The dashboard owns the terminal, so the log output is not printed with --tui
+    #[cfg(feature = "tui")]
+    let print_logs = !args.tui;
+    #[cfg(not(feature = "tui"))]
+    let print_logs = true;
     std::thread::spawn(move || {
         while let Ok(log) = raw_logs_recver.recv() {
+            let log_line = match &mut cycle_clock {
+                Some(cycle_clock) => LogLine::from_str_cycles(&log, cycle_clock),
+                None => LogLine::from_str(&log),
+            };
+
             // try to parse log line as LogEvent or just print it
-            if let Ok(log_line) = tracing::log_line::LogLine::from_str(&log) {
+            if let Ok(log_line) = log_line {
+                // rmt captures store every decoded line (events and plain logs)
+                if let Some(rmt_line_sender) = &rmt_line_sender
+                    && rmt_line_sender.send(log_line.clone()).is_err()
+                {
+                    break; // channel closed
+                }
+
                 // Check if it is a LogEvent
-                if let Ok(log_event) = tracing::log_event::LogEvent::from_log_line(&log_line) {
+                if let Ok(log_event) = LogEvent::from_log_line(&log_line) {
                     // successfully parsed LogEvent ==> send it as log event
-                    if log_event_sender.send(log_event).is_err() {
+                    if decoded_line_sender
+                        .send(DecodedLine::Event(log_event))
+                        .is_err()
+                    {
                         break; // channel closed
                     }
 
                     continue;
                 } else {
                     // send log line as well for raw logging
-                    println!("{log_line}");
+                    if print_logs {
+                        println!("{log_line}");
+                    }
 
                     // is log line ==> send log line
-                    if log_line_sender.send(log_line).is_err() {
+                    if decoded_line_sender
+                        .send(DecodedLine::Log(log_line))
+                        .is_err()
+                    {
                         break; // channel closed
                     }
                 }
             } else {
                 // cannot parse it correctly ==> just print the raw log
-                print!("{log}");
+                if print_logs {
+                    print!("{log}");
+                }
+
+                // firmware without defmt timestamps ==> align the line to the trace (--align-logs).
+                // Events cannot be timed without a timestamp, so they are not logged either
+                if align_logs
+                    && let Ok((level, message)) = LogLine::parse_untimed(&log)
+                    && !message.trim_start().starts_with("@EVENT_")
+                    && decoded_line_sender
+                        .send(DecodedLine::Untimed(level, message))
+                        .is_err()
+                {
+                    break; // channel closed
+                }
             }
         }
 
         // error returned because channel closed
     });
 
+    // Read host markers from stdin (--stdin-markers)
+    let mut host_marker_recver = if args.stdin_markers {
+        let (host_marker_sender, host_marker_recver) = crossbeam::channel::unbounded();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if let Some(host_marker) = parse_host_marker(&line)
+                    && host_marker_sender.send(host_marker).is_err()
+                {
+                    break; // channel closed
+                }
+            }
+        });
+        host_marker_recver
+    } else {
+        crossbeam::channel::never()
+    };
+
     // Create tracing instance and start processing log events
-    let mut tracing_instance = TracingInstance::new(firmware_addr_map);
+    let tracing_options = TracingOptions {
+        counter_bands: args.counter_bands,
+        inversion_threshold_us: args.inversion_threshold_us,
+        event_buffer_capacity: args.max_buffered_events,
+        out_of_order_tolerance_us: args.out_of_order_tolerance_us,
+        max_monitor_depth: args.max_monitor_depth,
+        collapse_recursion: args.collapse_recursion,
+        follow_resets: args.follow,
+        task_view: args.task_view,
+        monitor_filter: NameFilter {
+            include: args.include_monitor.clone(),
+            exclude: args.exclude_monitor.clone(),
+        },
+        task_filter: NameFilter {
+            include: args.include_task.clone(),
+            exclude: args.exclude_task.clone(),
+        },
+        idle_merge_threshold_us: args.merge_idle_us,
+        yield_reason_names: args.yield_reasons.iter().cloned().collect(),
+        warmup_us: args.warmup_ms.map(|ms| ms * 1000),
+        monitor_colors,
+        metric_panels,
+        ..Default::default()
+    };
+    let mut tracing_instance = TracingInstance::new(firmware_addr_map.clone(), tracing_options);
     let trace_event_recver = tracing_instance.get_trace_event_receiver();
-    std::thread::spawn(move || {
+    #[cfg(feature = "tui")]
+    let dropped_events_counter = tracing_instance.get_dropped_events_counter();
+    // Dropped on shutdown: the runner (probe-rs, espflash, ...) may outlive cargo, so the end of
+    // its output cannot end the tracing thread
+    let (stop_sender, stop_recver) = crossbeam::channel::bounded::<()>(0);
+    let tracing_handle = std::thread::spawn(move || {
         loop {
             // receive next log-event or log-line
             select! {
-                recv(log_line_recver) -> log_line_res => {
-                    // got log line
-                    match log_line_res {
-                        Ok(log_line) => {
-                            tracing_instance.add_log_line(&log_line);
-                        }
+                recv(stop_recver) -> _ => {
+                    // process what already arrived, but do not wait for more
+                    for decoded_line in decoded_line_recver.try_iter() {
+                        decoded_line.apply(&mut tracing_instance);
+                    }
+                    break;
+                },
+                recv(decoded_line_recver) -> decoded_line_res => {
+                    // got log event or log line
+                    match decoded_line_res {
+                        Ok(decoded_line) => decoded_line.apply(&mut tracing_instance),
                         Err(_) => break, // channel closed
                     }
                 },
-                recv(log_event_recver) -> log_event_res => {
-                    // got log event
-                    match log_event_res {
-                        Ok(log_event) => {
-                            tracing_instance.update(&log_event);
+                recv(host_marker_recver) -> host_marker_res => {
+                    // got host marker
+                    match host_marker_res {
+                        Ok((timestamp, label)) => {
+                            tracing_instance.add_host_marker(&label, timestamp);
                         }
-                        Err(_) => break, // channel closed
+                        // stdin closed ==> only stop listening for markers
+                        Err(_) => host_marker_recver = crossbeam::channel::never(),
                     }
                 },
             }
         }
+
+        // hand back the instance to end the session
+        tracing_instance
+    });
+
+    // Create trace writer and start writing trace events from trace_event_recver. Every consumer
+    // registers its own sink of the tee, so all of them get every event
+    let mut trace_event_tee = TraceEventTee::new();
+    let trigger_fired = Arc::new(AtomicBool::new(false));
+    let build_profile = if args.release { "release" } else { "debug" };
+    let perfetto_filename =
+        Path::new(&args.project).join(format!("rustmeter-perfetto-{build_profile}.json"));
+    let mut writer_fallback = None;
+    let mut writer_sink = None;
+    let file_writer_handle = match args.output_format {
+        OutputFormat::Perfetto => {
+            let file_writer_options = FileWriterOptions {
+                max_file_size: args.max_file_size.map(|mb| mb * 1024 * 1024),
+                rotate: args.rotate,
+                per_core_processes: args.per_core_processes,
+                metadata: args
+                    .embed_metadata
+                    .then(|| capture_metadata(&args, build_profile, elf_path)),
+                ts_offset_us: args
+                    .wall_clock
+                    .then(|| {
+                        std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map(|since_epoch| since_epoch.as_micros())
+                    })
+                    .transpose()
+                    .context("Host clock is before the unix epoch")?,
+                metadata_events: Default::default(),
+            };
+            // Hold the events back in a ring until the trigger fires (--ring-seconds)
+            writer_sink = Some(trace_event_tee.sink_count());
+            let mut trace_events = trace_event_tee.add_sink(None);
+            if let (Some(ring_seconds), Some(trigger)) = (args.ring_seconds, &args.trigger) {
+                let ring = TriggerRing::new((ring_seconds * 1_000_000.0) as u128, trigger.clone());
+                trace_events = spawn_trigger_ring(trace_events, ring, trigger_fired.clone());
+            }
+            // The clone shares the metadata events written so far, the fallback replays them
+            writer_fallback = Some((trace_events.clone(), file_writer_options.clone()));
+            spawn_perfetto_file_writer(
+                perfetto_filename.clone(),
+                trace_events,
+                exit_flag.clone(),
+                file_writer_options,
+            )
+        }
+        OutputFormat::Rmt => {
+            // The capture is built from the log lines, the trace events are not needed
+            let rmt_filename =
+                Path::new(&args.project).join(format!("rustmeter-capture-{build_profile}.rmt"));
+            spawn_rmt_file_writer(rmt_filename, rmt_line_recver, firmware_addr_map)
+        }
+    };
+
+    // Dropped on shutdown, so the dashboard hands the terminal back before the summary is printed
+    #[cfg(feature = "tui")]
+    let (tui_stop_sender, tui_stop_recver) = crossbeam::channel::bounded::<()>(0);
+    #[cfg(feature = "tui")]
+    let tui = args.tui.then(|| {
+        let tui_sink = trace_event_tee.sink_count();
+        // Bounded: a slow terminal drops dashboard updates, never trace events of the file
+        let tui_handle = tui::spawn_tui(
+            trace_event_tee.add_sink(Some(tui::SINK_CAPACITY)),
+            dropped_events_counter,
+            tui_stop_recver,
+        );
+        (tui_sink, tui_handle)
     });
 
-    // Create Perfetto trace writer and start writing trace events from trace_event_recver
-    let perfetto_filename = Path::new(&args.project).join(format!(
-        "rustmeter-perfetto-{}.json",
-        if args.release { "release" } else { "debug" }
-    ));
-    let perfetto_file_writer_handle =
-        spawn_perfetto_file_writer(perfetto_filename, trace_event_recver, exit_flag.clone());
+    let trace_event_tee_handle = trace_event_tee.spawn(trace_event_recver);
 
     // Main loop
+    let mut session_result = Ok(());
+    let session_start = std::time::Instant::now();
+    let mut runner_start = session_start;
+    let mut follow_backoff = FollowBackoff::new();
     while !exit_flag.load(std::sync::atomic::Ordering::SeqCst) {
         std::thread::sleep(Duration::from_millis(100));
 
-        // Check if cargo child process has exited
-        if let Some(status_code) = cargo_child_process.get_status_code()? {
-            return Err(anyhow::anyhow!(
-                "Cargo process exited with status: {status_code}"
-            ));
+        // Stop after the requested capture window
+        if let Some(capture_duration) = args.capture_duration
+            && session_start.elapsed().as_secs_f64() >= capture_duration
+        {
+            println!("Capture duration of {capture_duration}s reached. Stopping session.");
+            break;
         }
 
-        // Check if perfetto file writer thread has exited with error
-        if perfetto_file_writer_handle.is_finished() {
-            // normally this should not happen
-            match perfetto_file_writer_handle.join() {
-                Ok(result) => {
-                    if let Err(e) = result {
-                        return Err(anyhow::anyhow!(
-                            "Perfetto file writer thread exited with error: {e}"
-                        ));
-                    } else {
-                        return Ok(()); // normal exit
-                    }
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(
-                        "Perfetto file writer thread panicked: {e:?}"
+        // Check if cargo child process has exited (it also receives CTRL-C)
+        if let Some(status_code) = cargo_child_process.get_status_code()? {
+            // The runner lost the target (e.g. reset) ==> attach again and keep capturing
+            if args.follow && !exit_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                let Some(delay) = follow_backoff.on_runner_exit(runner_start.elapsed()) else {
+                    session_result = Err(anyhow::anyhow!(
+                        "Runner exited right after re-attaching {MAX_QUICK_RESTARTS} times in a row (last status: {status_code}). Giving up (--follow)"
                     ));
+                    break;
+                };
+                println!(
+                    "Runner exited with status: {status_code}. Re-attaching in {:.2}s (--follow)...",
+                    delay.as_secs_f64()
+                );
+                let retry_at = std::time::Instant::now() + delay;
+                while std::time::Instant::now() < retry_at
+                    && !exit_flag.load(std::sync::atomic::Ordering::SeqCst)
+                {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                if exit_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                    break;
                 }
+
+                runner_start = std::time::Instant::now();
+                cargo_child_process = CargoChildProcess::new_start_run(
+                    args.release,
+                    &args.project,
+                    args.stdin_markers,
+                )?;
+                if cargo_child_process.wait_build_finish()?.has_failed() {
+                    session_result = Err(anyhow::anyhow!("Cargo build failed while re-attaching"));
+                    break;
+                }
+                forward_runner_logs(&cargo_child_process, raw_logs_sender.clone());
+                continue;
+            }
+
+            if !exit_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                session_result = Err(anyhow::anyhow!(
+                    "Cargo process exited with status: {status_code}"
+                ));
             }
+            break;
+        }
+
+        if args.stop_on_trigger && trigger_fired.load(std::sync::atomic::Ordering::SeqCst) {
+            println!("Trigger fired. Stopping session (--stop-on-trigger).");
+            break;
+        }
+
+        // Check if the file writer thread has exited (error or size limit reached)
+        if file_writer_handle.is_finished() {
+            break;
         }
     }
 
     // Clean up
+    #[cfg(feature = "tui")]
+    let tui_sink = match tui {
+        Some((tui_sink, tui_handle)) => {
+            drop(tui_stop_sender);
+            match tui_handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Dashboard failed: {e:#}"),
+                Err(e) => eprintln!("Dashboard thread panicked: {e:?}"),
+            }
+            Some(tui_sink)
+        }
+        None => None,
+    };
     cargo_child_process.kill()?;
-    perfetto_file_writer_handle.join().unwrap()?;
+    drop(raw_logs_sender);
+    drop(stop_sender);
+    let mut tracing_instance = tracing_handle
+        .join()
+        .map_err(|e| anyhow::anyhow!("Tracing thread panicked: {e:?}"))?;
+    if tracing_instance.end_session() {
+        println!("Trace ended cleanly (target flushed its buffer)");
+    } else {
+        println!("Trace may be truncated (no final flush received from target)");
+    }
+
+    let dropped_events = tracing_instance.get_dropped_events();
+    if dropped_events > 0 {
+        println!("Dropped {dropped_events} trace events because the writer could not keep up");
+    }
+
+    let target_resets = tracing_instance.get_target_resets();
+    if target_resets > 0 {
+        println!("Followed {target_resets} target reset(s)");
+    }
+
+    let rejected_events = tracing_instance.get_rejected_events();
+    if rejected_events > 0 {
+        println!("Rejected {rejected_events} tracing events with out-of-order timestamps");
+    }
+
+    let mismatched_monitor_ends = tracing_instance.get_mismatched_monitor_ends();
+    if mismatched_monitor_ends > 0 {
+        println!(
+            "Dropped {mismatched_monitor_ends} monitor ends whose monitor was only open on another core (lost starts, code that moved cores or corrupt core ids)"
+        );
+    }
+
+    // Summary of coalesced monitors (no individual slices in the trace)
+    for (name, (count, total_us)) in tracing_instance.get_monitor_aggregates() {
+        println!(
+            "Coalesced monitor '{name}': {count} calls, avg {:.1} us",
+            total_us as f64 / count.max(1) as f64
+        );
+    }
+
+    // Tasks polled the most often first, a busy-waking task (broken waker) stands out (--poll-stats)
+    if args.poll_stats {
+        let capture_duration_us = tracing_instance.get_capture_duration_us();
+        let usage = tracing_instance.get_usage();
+        let mut tasks: Vec<TaskReport> = usage
+            .task_running_us
+            .into_iter()
+            .map(|(key, running_us)| {
+                let polls = usage.task_polls.get(&key).copied().unwrap_or(0);
+                TaskReport::new(key, running_us, polls, capture_duration_us)
+            })
+            .collect();
+        tasks.sort_by(|a, b| b.polls_per_sec.total_cmp(&a.polls_per_sec));
+        for task in tasks.iter().take(10) {
+            println!(
+                "Task '{}' ({}): {} polls ({:.1}/s), avg {:.1} us per poll",
+                task.task, task.executor, task.polls, task.polls_per_sec, task.mean_poll_us
+            );
+        }
+    }
+
+    // Check monitor budgets (--assert-max-us)
+    if !args.assert_max_us.is_empty() {
+        let max_durations = tracing_instance.get_monitor_max_us();
+        for (name, budget_us) in &args.assert_max_us {
+            match max_durations.get(name) {
+                Some(max_us) => {
+                    println!("Monitor '{name}': max {max_us} us (budget {budget_us} us)")
+                }
+                None => println!("Monitor '{name}': not observed (budget {budget_us} us)"),
+            }
+        }
+
+        let violations = tracing_instance.check_monitor_budgets(&args.assert_max_us);
+        for violation in &violations {
+            eprintln!("{violation}");
+        }
+        if !violations.is_empty() && session_result.is_ok() {
+            session_result = Err(anyhow::anyhow!(
+                "{} monitor budget(s) violated",
+                violations.len()
+            ));
+        }
+    }
+
+    for metric in tracing_instance.get_unseen_panel_metrics() {
+        println!("Warning: metric '{metric}' of --metric-panels was never received");
+    }
+
+    let mut session_report = args
+        .report_json
+        .is_some()
+        .then(|| SessionReport::from_tracing_instance(&tracing_instance));
+
+    drop(tracing_instance); // closes the trace event channel ==> writer finalises the file
+    let sink_dropped_events = trace_event_tee_handle
+        .join()
+        .map_err(|e| anyhow::anyhow!("Trace event tee thread panicked: {e:?}"))?;
+    // only the events the file writer lost are missing from the trace
+    let writer_dropped_events =
+        writer_sink.map_or(0, |writer_sink| sink_dropped_events[writer_sink]);
+    if writer_dropped_events > 0 {
+        println!(
+            "Dropped {writer_dropped_events} trace events because the file writer could not keep up"
+        );
+    }
+    #[cfg(feature = "tui")]
+    if let Some(tui_sink) = tui_sink
+        && sink_dropped_events[tui_sink] > 0
+    {
+        println!(
+            "The dashboard skipped {} trace events because the terminal could not keep up (the trace is complete)",
+            sink_dropped_events[tui_sink]
+        );
+    }
+
+    // Write the summary report (--report-json)
+    if let (Some(session_report), Some(report_filename)) = (&mut session_report, &args.report_json)
+    {
+        session_report.dropped_events += writer_dropped_events;
+        session_report.write(Path::new(report_filename))?;
+        println!("Report written to {report_filename}");
+    }
+    let writer_result = file_writer_handle
+        .join()
+        .map_err(|e| anyhow::anyhow!("File writer thread panicked: {e:?}"))?;
+    if let Err(e) = writer_result {
+        // The trace file is finalised up to the error, the events still queued for the writer
+        // would be lost
+        if let Some((trace_events, options)) = writer_fallback
+            && !trace_events.is_empty()
+        {
+            save_fallback_trace(&perfetto_filename, trace_events, options);
+        }
+        return Err(e.context("File writer thread exited with error"));
+    }
+
+    if args.trigger.is_some()
+        && args.output_format == OutputFormat::Perfetto
+        && !trigger_fired.load(std::sync::atomic::Ordering::SeqCst)
+    {
+        println!("Trigger never fired. The trace holds no events.");
+    }
+
+    // Hand the finished trace to Perfetto UI (--open). Ctrl+C ended the capture, so the flag is
+    // cleared to stop serving on the next one
+    if args.open {
+        if args.output_format == OutputFormat::Perfetto {
+            exit_flag.store(false, std::sync::atomic::Ordering::SeqCst);
+            open_in_perfetto_ui(&perfetto_filename, exit_flag)?;
+        } else {
+            println!("--open needs a Perfetto trace. Convert the capture with --convert first.");
+        }
+    }
 
+    session_result
+}
+
+/// Line of the runner output, decoded for the tracing thread
+enum DecodedLine {
+    Event(LogEvent),
+    Log(LogLine),
+    /// Log line of firmware without defmt timestamps (`--align-logs`)
+    Untimed(LogLevel, String),
+}
+
+impl DecodedLine {
+    fn apply(self, tracing_instance: &mut TracingInstance) {
+        match self {
+            DecodedLine::Event(log_event) => tracing_instance.update(&log_event),
+            DecodedLine::Log(log_line) => tracing_instance.add_log_line(&log_line),
+            DecodedLine::Untimed(level, message) => {
+                tracing_instance.add_untimed_log_line(level, message)
+            }
+        }
+    }
+}
+
+/// Write the trace events a failed file writer left behind into a fallback file in the temp
+/// directory (the trace directory might be the full disk that made the writer fail). `options`
+/// shares the metadata events of the failed writer, so the fallback starts with the process /
+/// thread names
+fn save_fallback_trace(
+    perfetto_filename: &Path,
+    trace_events: Receiver<TracingEvent>,
+    options: FileWriterOptions,
+) {
+    let stem = perfetto_filename
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let fallback_filename = std::env::temp_dir().join(format!("{stem}.fallback.json"));
+    let event_count = trace_events.len();
+    let options = FileWriterOptions {
+        max_file_size: None,
+        rotate: false,
+        ..options
+    };
+
+    let result = spawn_perfetto_file_writer(
+        fallback_filename.clone(),
+        trace_events,
+        Arc::new(AtomicBool::new(false)),
+        options,
+    )
+    .join();
+    match result {
+        Ok(Ok(())) => println!(
+            "Saved {event_count} unwritten trace events into {}",
+            fallback_filename.display()
+        ),
+        Ok(Err(e)) => eprintln!("Failed to save the unwritten trace events: {e:#}"),
+        Err(e) => eprintln!("Fallback file writer panicked: {e:?}"),
+    }
+}
+
+/// Describe the capture for the trace metadata (`--embed-metadata`), so a shared trace can be
+/// reproduced without asking for the setup
+fn capture_metadata(
+    args: &CommandLineArgs,
+    build_profile: &str,
+    elf_path: &Path,
+) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::from([
+        (
+            "rustmeter_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("build_profile".to_string(), build_profile.to_string()),
+        (
+            "capture_config".to_string(),
+            std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+        ),
+    ]);
+    if let Some(firmware) = elf_path.file_name() {
+        metadata.insert(
+            "firmware".to_string(),
+            firmware.to_string_lossy().to_string(),
+        );
+    }
+    if let Some(chip) = configured_chip(Path::new(&args.project)) {
+        metadata.insert("chip".to_string(), chip);
+    }
+    metadata
+}
+
+/// Forward the output of a runner into the shared raw log channel
+fn forward_runner_logs(
+    cargo_child_process: &CargoChildProcess,
+    raw_logs_sender: crossbeam::channel::Sender<String>,
+) {
+    let runner_logs_recver = cargo_child_process.get_logs_receiver();
+    std::thread::spawn(move || {
+        while let Ok(log) = runner_logs_recver.recv() {
+            if raw_logs_sender.send(log).is_err() {
+                break; // channel closed
+            }
+        }
+    });
+}
+
+/// Report what the instrumented firmware contains (`--dry-run`)
+fn dry_run_report(elf_path: &Path, firmware_addr_map: &FirmwareAddressMap) -> anyhow::Result<()> {
+    println!("Firmware: {}", elf_path.display());
+    println!("Symbols: {}", firmware_addr_map.len());
+
+    let summary = scan_defmt_table(elf_path)?.ok_or(anyhow::anyhow!(
+        "No .defmt section found in the firmware. Is defmt linked (e.g. `-C link-arg=-Tdefmt.x`)?"
+    ))?;
+    println!("defmt entries: {}", summary.entry_count);
+    println!("Monitor sites: {}", summary.monitor_count);
+    println!("Metric sites: {}", summary.metric_count);
+
+    match read_monitor_registry(elf_path)? {
+        Some(monitors) => {
+            println!("Registered monitors: {}", monitors.len());
+            for monitor in monitors {
+                println!("  - {} ({:?})", monitor.name, monitor.kind);
+            }
+        }
+        None => println!(
+            "No monitor registry found (enable the `monitor-registry` feature of rustmeter-beacon to list monitors by name)"
+        ),
+    }
+
+    if summary.monitor_count == 0 && summary.metric_count == 0 {
+        println!("No monitors or metrics found. Were they compiled out or optimized away?");
+    }
     Ok(())
 }
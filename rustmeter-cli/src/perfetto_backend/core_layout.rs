@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::perfetto_backend::trace_event::{InstantScope, TracingEvent};
+
+// Pids of the processes the host adds next to the executors. Executor ids are addresses, which
+// are never this small, so they cannot collide: 0 is the `CORE OVERVIEW`, 1 to 256 the core
+// processes of `--per-core-processes`, 999 the DMA channels and 1000 onwards the metric panels
+
+/// Pid of the `DMA` process holding one thread per DMA channel
+pub const DMA_PID: u32 = 999;
+
+/// Pid of the first metric panel process (`--metric-panels`)
+pub const PANEL_PID_BASE: u32 = 1000;
+
+/// Thread of the core overview (running executor and monitors) inside a core process. Executor
+/// and task ids are addresses, so they never collide with it
+const CORE_OVERVIEW_TID: u32 = 0;
+
+/// Pid of the process of a core
+fn core_pid(core_id: u32) -> u32 {
+    core_id + 1
+}
+
+/// Rewrites the shared layout (`CORE OVERVIEW` process with one thread per core and one process
+/// per executor) into one process per core (`--per-core-processes`). A core process holds the
+/// core overview, its executors and their tasks as threads.
+///
+/// Only pids / tids and metadata change. The executor of a pid is learned from its
+/// `process_name` metadata, which the tracing instance sends before any other executor event.
+#[derive(Default)]
+pub struct PerCoreLayout {
+    /// Core and display name of every executor
+    executors: HashMap<u32, (u32, String)>,
+    /// Cores whose process is already named
+    named_cores: HashSet<u32>,
+}
+
+impl PerCoreLayout {
+    /// Translate one event of the shared layout (may yield additional metadata or nothing)
+    pub fn remap(&mut self, event: TracingEvent) -> Vec<TracingEvent> {
+        let mut events = Vec::new();
+
+        let mut event = match event {
+            TracingEvent::Metadata {
+                name,
+                pid,
+                tid,
+                args,
+                ..
+            } => {
+                self.remap_metadata(&name, pid, tid, args, &mut events);
+                return events;
+            }
+            event => event,
+        };
+
+        match &mut event {
+            TracingEvent::Begin { pid, tid, .. } | TracingEvent::End { pid, tid, .. } => {
+                if let Some((new_pid, new_tid)) = self.map_track(*pid, *tid) {
+                    (*pid, *tid) = (new_pid, Some(new_tid));
+                }
+            }
+            TracingEvent::Complete { pid, tid, .. }
+            | TracingEvent::FlowStart { pid, tid, .. }
+            | TracingEvent::FlowEnd { pid, tid, .. } => {
+                if let Some((new_pid, new_tid)) = self.map_track(*pid, Some(*tid)) {
+                    (*pid, *tid) = (new_pid, new_tid);
+                }
+            }
+            TracingEvent::Instant {
+                pid: Some(pid),
+                tid,
+                scope,
+                ..
+            } => {
+                if let Some((new_pid, new_tid)) = self.map_track(*pid, *tid) {
+                    // A process instant of an executor stays on the executor's thread
+                    if tid.is_none() {
+                        *scope = InstantScope::Thread;
+                    }
+                    (*pid, *tid) = (new_pid, Some(new_tid));
+                }
+            }
+            TracingEvent::Counter {
+                name,
+                pid: Some(pid),
+                ..
+            } => {
+                // Counters are per process ==> keep the executors of a core apart by name
+                if let Some((core_id, executor_name)) = self.executors.get(pid) {
+                    *name = format!("{executor_name}: {name}");
+                    *pid = core_pid(*core_id);
+                }
+            }
+            TracingEvent::AsyncBegin { pid, .. } | TracingEvent::AsyncEnd { pid, .. } => {
+                if let Some((core_id, _)) = self.executors.get(pid) {
+                    *pid = core_pid(*core_id);
+                }
+            }
+            _ => {}
+        }
+
+        events.push(event);
+        events
+    }
+
+    /// New pid / tid of a track of the shared layout (`None` if it stays as it is)
+    fn map_track(&self, pid: u32, tid: Option<u32>) -> Option<(u32, u32)> {
+        match (pid, tid) {
+            (0, Some(core_id)) => Some((core_pid(core_id), CORE_OVERVIEW_TID)),
+            (0, None) => None,
+            (executor_id, tid) => self
+                .executors
+                .get(&executor_id)
+                .map(|(core_id, _)| (core_pid(*core_id), tid.unwrap_or(executor_id))),
+        }
+    }
+
+    fn remap_metadata(
+        &mut self,
+        name: &str,
+        pid: u32,
+        tid: Option<u32>,
+        args: HashMap<String, String>,
+        events: &mut Vec<TracingEvent>,
+    ) {
+        let core_id = args.get("core").and_then(|core| core.parse::<u32>().ok());
+        match (name, pid, tid, core_id) {
+            // Core overview thread
+            ("thread_name", 0, Some(core_id), _) => {
+                self.name_core(core_id, events);
+                events.push(thread_name(
+                    core_pid(core_id),
+                    CORE_OVERVIEW_TID,
+                    "Overview".to_string(),
+                ));
+            }
+            // Executor process ==> executor thread of its core
+            ("process_name", executor_id, None, Some(core_id)) if executor_id != 0 => {
+                let display_name = args.get("name").cloned().unwrap_or_default();
+                let display_name = display_name
+                    .strip_prefix(&format!("[CORE {core_id}] "))
+                    .unwrap_or(&display_name)
+                    .to_string();
+                self.name_core(core_id, events);
+                events.push(thread_name(
+                    core_pid(core_id),
+                    executor_id,
+                    display_name.clone(),
+                ));
+                self.executors.insert(executor_id, (core_id, display_name));
+            }
+            // Shared process / executor thread names are replaced by the ones above
+            ("process_name", 0, None, _) => {}
+            ("thread_name", executor_id, None, _) if self.executors.contains_key(&executor_id) => {}
+            // Task threads move into the core process
+            ("thread_name", executor_id, Some(task_id), _)
+                if self.executors.contains_key(&executor_id) =>
+            {
+                let (core_id, _) = self.executors[&executor_id];
+                events.push(TracingEvent::Metadata {
+                    name: name.to_string(),
+                    cat: None,
+                    pid: core_pid(core_id),
+                    tid: Some(task_id),
+                    args,
+                });
+            }
+            _ => events.push(TracingEvent::Metadata {
+                name: name.to_string(),
+                cat: None,
+                pid,
+                tid,
+                args,
+            }),
+        }
+    }
+
+    /// Name the process of a core once
+    fn name_core(&mut self, core_id: u32, events: &mut Vec<TracingEvent>) {
+        if self.named_cores.insert(core_id) {
+            events.push(TracingEvent::Metadata {
+                name: "process_name".to_string(),
+                cat: None,
+                pid: core_pid(core_id),
+                tid: None,
+                args: HashMap::from([("name".to_string(), format!("CORE {core_id}"))]),
+            });
+        }
+    }
+}
+
+fn thread_name(pid: u32, tid: u32, name: String) -> TracingEvent {
+    TracingEvent::Metadata {
+        name: "thread_name".to_string(),
+        cat: None,
+        pid,
+        tid: Some(tid),
+        args: HashMap::from([("name".to_string(), name)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elf_file::FirmwareAddressMap,
+        tracing::{
+            log_event::LogEvent,
+            log_line::LogLine,
+            tracing_instance::{TracingInstance, TracingOptions},
+        },
+    };
+
+    #[test]
+    fn test_per_core_processes() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // Two executors with the same task id on different cores
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=4096,core_id=0,task_id=8192)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=4224,core_id=1,task_id=8320)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=4224,core_id=1)",
+            "@EVENT_MONITOR_START(function_name=control,core_id=1)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        instance.end_session();
+
+        let mut layout = PerCoreLayout::default();
+        let events: Vec<_> = receiver
+            .try_iter()
+            .flat_map(|event| layout.remap(event))
+            .collect();
+
+        let names: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                TracingEvent::Metadata {
+                    name,
+                    pid,
+                    tid,
+                    args,
+                    ..
+                } => Some((name.as_str(), *pid, *tid, args["name"].as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("process_name", 1, None, "CORE 0"),
+                ("thread_name", 1, Some(0), "Overview"),
+                ("thread_name", 1, Some(4096), "Executor 0x1000"),
+                ("thread_name", 1, Some(8192), "Task 0x2000"),
+                ("process_name", 2, None, "CORE 1"),
+                ("thread_name", 2, Some(0), "Overview"),
+                ("thread_name", 2, Some(4224), "Executor 0x1080"),
+                ("thread_name", 2, Some(8320), "Task 0x2080"),
+            ]
+        );
+
+        // Every slice lives in a core process now
+        for event in &events {
+            match event {
+                TracingEvent::Begin { pid, tid, .. } | TracingEvent::End { pid, tid, .. } => {
+                    assert!(matches!(pid, 1 | 2), "{event:?}");
+                    assert!(tid.is_some(), "{event:?}");
+                }
+                TracingEvent::Counter { name, pid, .. } => {
+                    assert_eq!(*pid, Some(2));
+                    assert_eq!(name, "Executor 0x1080: Ready tasks");
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use crossbeam::channel::{Receiver, Sender, TrySendError};
+
+use crate::perfetto_backend::trace_event::TracingEvent;
+
+/// Sending side of the trace event channel to the file writer.
+///
+/// With a capacity the channel is bounded and new events are dropped (and counted) while it is
+/// full, so a slow disk cannot make the host buffer events without limit. Without a capacity the
+/// channel is unbounded and never drops.
+#[derive(Clone)]
+pub struct TraceEventSender {
+    sender: Sender<TracingEvent>,
+    dropped_events: Arc<AtomicU64>,
+}
+
+impl TraceEventSender {
+    /// Create a new trace event channel
+    pub fn channel(capacity: Option<usize>) -> (Self, Receiver<TracingEvent>) {
+        let (sender, receiver) = match capacity {
+            Some(capacity) => crossbeam::channel::bounded(capacity),
+            None => crossbeam::channel::unbounded(),
+        };
+
+        let sender = Self {
+            sender,
+            dropped_events: Arc::new(AtomicU64::new(0)),
+        };
+        (sender, receiver)
+    }
+
+    /// Send a trace event without blocking. Returns false if it was dropped because the channel
+    /// is full or closed
+    pub fn send(&self, event: TracingEvent) -> bool {
+        match self.sender.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped_events.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Number of events dropped because the channel was full
+    pub fn get_dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Shared counter of the dropped events (to watch it from another thread)
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    pub fn get_dropped_events_counter(&self) -> Arc<AtomicU64> {
+        self.dropped_events.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn counter_event(ts: u128) -> TracingEvent {
+        TracingEvent::Counter {
+            name: "value".to_string(),
+            cat: None,
+            pid: None,
+            ts,
+            args: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_bounded_channel_drops_when_full() {
+        let (sender, receiver) = TraceEventSender::channel(Some(2));
+        for ts in 0..5 {
+            let _ = sender.send(counter_event(ts));
+        }
+
+        assert_eq!(receiver.len(), 2);
+        assert_eq!(sender.get_dropped_events(), 3);
+
+        // drained channel accepts events again
+        let _ = receiver.try_iter().count();
+        assert!(sender.send(counter_event(5)));
+    }
+}
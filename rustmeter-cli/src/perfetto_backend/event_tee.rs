@@ -0,0 +1,85 @@
+use std::thread::JoinHandle;
+
+use crossbeam::channel::Receiver;
+
+use crate::perfetto_backend::{event_sender::TraceEventSender, trace_event::TracingEvent};
+
+/// Fans the trace events of a tracing instance out to several consumers (file writer, live
+/// views, ...). Every sink gets its own copy of every event.
+///
+/// Sinks never block each other: a sink with a capacity drops (and counts) events while it is
+/// full, exactly like the bounded channel of the tracing instance itself.
+#[derive(Default)]
+pub struct TraceEventTee {
+    sinks: Vec<TraceEventSender>,
+}
+
+impl TraceEventTee {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new consumer. Without a capacity its channel is unbounded
+    pub fn add_sink(&mut self, capacity: Option<usize>) -> Receiver<TracingEvent> {
+        let (sender, receiver) = TraceEventSender::channel(capacity);
+        self.sinks.push(sender);
+        receiver
+    }
+
+    /// Number of registered consumers (the index of the next sink in [`Self::spawn`]'s result)
+    pub fn sink_count(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Copy every event of `source` into all sinks until it closes. The sink channels close
+    /// afterwards and the thread returns the number of events dropped per sink (in registration
+    /// order)
+    pub fn spawn(self, source: Receiver<TracingEvent>) -> JoinHandle<Vec<u64>> {
+        std::thread::spawn(move || {
+            while let Ok(event) = source.recv() {
+                if let Some((last, others)) = self.sinks.split_last() {
+                    for sink in others {
+                        sink.send(event.clone());
+                    }
+                    last.send(event);
+                }
+            }
+
+            self.sinks
+                .iter()
+                .map(|sink| sink.get_dropped_events())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_slow_sink_does_not_block_others() {
+        let (source_sender, source) = crossbeam::channel::unbounded();
+        let mut tee = TraceEventTee::new();
+        let fast = tee.add_sink(None);
+        let slow = tee.add_sink(Some(2)); // never read while the events arrive
+        let handle = tee.spawn(source);
+
+        for ts in 0..10 {
+            let _ = source_sender.send(TracingEvent::Counter {
+                name: "value".to_string(),
+                cat: None,
+                pid: None,
+                ts,
+                args: HashMap::new(),
+            });
+        }
+        drop(source_sender);
+
+        assert_eq!(handle.join().unwrap(), vec![0, 8]);
+        assert_eq!(fast.iter().count(), 10);
+        assert_eq!(slow.iter().count(), 2);
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     fs::File,
-    io::Write,
-    path::PathBuf,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
     },
     thread::JoinHandle,
@@ -12,51 +13,643 @@ use std::{
 use anyhow::Context;
 use crossbeam::channel::Receiver;
 
-use crate::perfetto_backend::trace_event::TracingEvent;
+use crate::perfetto_backend::{core_layout::PerCoreLayout, trace_event::TracingEvent};
 
+const FILE_HEADER: &[u8] = b"{\"traceEvents\": [";
+const FILE_FOOTER: &[u8] = b"\n]}\n";
+const EVENT_SEPERATOR: &[u8] = b",\n";
+
+/// Options limiting the size of the written trace files
+#[derive(Debug, Clone, Default)]
+pub struct FileWriterOptions {
+    /// Maximum size of a single trace file in bytes
+    pub max_file_size: Option<u64>,
+    /// Continue in a new file once `max_file_size` is reached instead of stopping the capture
+    pub rotate: bool,
+    /// Offset (in µs) added to every timestamp, e.g. to anchor the trace to host wall-clock time
+    pub ts_offset_us: Option<u128>,
+    /// Group executors and tasks into one process per core instead of one per executor
+    pub per_core_processes: bool,
+    /// Capture information written as trace-level `metadata` at the start of every file
+    pub metadata: Option<BTreeMap<String, String>>,
+    /// Metadata events (process / thread names) written so far. Shared by the clones of the
+    /// options, so a fallback writer started with them replays the names of the failed writer
+    pub metadata_events: Arc<Mutex<Vec<String>>>,
+}
+
+/// Where a trace file is written to: the file system, or a simulated full disk in tests
+trait TraceStorage: Write {
+    /// Cut the storage back to `len` bytes and continue writing there
+    fn truncate(&mut self, len: u64) -> std::io::Result<()>;
+}
+
+impl TraceStorage for File {
+    fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        self.set_len(len)?;
+        self.seek(SeekFrom::Start(len)).map(|_| ())
+    }
+}
+
+/// A single perfetto trace file that is always finalised to valid JSON. A failed write is cut
+/// off again, so the file still ends with a complete event and can be finalised afterwards
+struct PerfettoFile<S: TraceStorage = File> {
+    file: S,
+    path: PathBuf,
+    bytes_written: u64,
+    event_count: usize,
+    finalised: bool,
+}
+
+impl PerfettoFile {
+    /// Create the file and write its header
+    fn create(path: &Path, metadata: Option<&BTreeMap<String, String>>) -> anyhow::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open perfetto trace file {}", path.display()))?;
+        Self::new(file, path, metadata)
+    }
+}
+
+impl<S: TraceStorage> PerfettoFile<S> {
+    /// Write the header. The metadata goes in front of the trace events as a trace-level field,
+    /// which Perfetto shows in its info page and does not parse further
+    fn new(
+        mut file: S,
+        path: &Path,
+        metadata: Option<&BTreeMap<String, String>>,
+    ) -> anyhow::Result<Self> {
+        let mut header = Vec::new();
+        if let Some(metadata) = metadata {
+            header.extend_from_slice(b"{\"metadata\": ");
+            header.extend(serde_json::to_vec(metadata).context("Failed to jsonify metadata")?);
+            header.extend_from_slice(b",\n\"traceEvents\": [");
+        } else {
+            header.extend_from_slice(FILE_HEADER);
+        }
+        file.write_all(&header).with_context(|| {
+            format!("Failed to write to perfetto trace file {}", path.display())
+        })?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            bytes_written: header.len() as u64,
+            event_count: 0,
+            finalised: false,
+        })
+    }
+
+    /// Check if the file would grow beyond `max_file_size` (including the footer) with this event.
+    /// A file always takes at least one event, so oversized events cannot cause endless rotation
+    fn would_exceed(&self, json_str: &str, max_file_size: u64) -> bool {
+        let event_size = (EVENT_SEPERATOR.len() + json_str.len() + FILE_FOOTER.len()) as u64;
+        self.event_count > 0 && self.bytes_written + event_size > max_file_size
+    }
+
+    fn write_event(&mut self, json_str: &str) -> anyhow::Result<()> {
+        // write comma if not first event
+        let mut bytes = Vec::with_capacity(EVENT_SEPERATOR.len() + json_str.len());
+        if self.event_count > 0 {
+            bytes.extend_from_slice(EVENT_SEPERATOR);
+        }
+        bytes.extend_from_slice(json_str.as_bytes());
+
+        self.write_bytes(&bytes)
+            .with_context(|| format!("Failed to write trace event to {}", self.path.display()))?;
+        self.event_count += 1;
+        Ok(())
+    }
+
+    /// Write all bytes or none of them (a partially written event is cut off again)
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Err(e) = self.file.write_all(bytes) {
+            let _ = self.file.truncate(self.bytes_written);
+            return Err(e);
+        }
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Close the event array. Does nothing if the file was finalised already
+    fn finalise(&mut self) -> anyhow::Result<()> {
+        if self.finalised {
+            return Ok(());
+        }
+        self.write_bytes(FILE_FOOTER)
+            .with_context(|| format!("Failed to finalise {}", self.path.display()))?;
+        self.finalised = true;
+        Ok(())
+    }
+}
+
+/// Get the filename of the n-th rotated trace file (e.g. `trace.json` ==> `trace.1.json`)
+fn rotated_filename(perfetto_filename: &Path, index: usize) -> PathBuf {
+    let stem = perfetto_filename
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let filename = match perfetto_filename.extension() {
+        Some(extension) => format!("{stem}.{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{index}"),
+    };
+    perfetto_filename.with_file_name(filename)
+}
+
+/// Delete the rotated files of an earlier capture (`trace.1.json`, `trace.2.json`, ...), which
+/// would otherwise look like the continuation of this one
+fn remove_stale_rotated_files(perfetto_filename: &Path) -> anyhow::Result<()> {
+    let stale_files: Vec<_> = (1..)
+        .map(|index| rotated_filename(perfetto_filename, index))
+        .take_while(|filename| filename.exists())
+        .collect();
+    for filename in &stale_files {
+        std::fs::remove_file(filename)
+            .with_context(|| format!("Failed to remove stale trace file {}", filename.display()))?;
+    }
+    if !stale_files.is_empty() {
+        println!(
+            "Removed {} rotated trace files of an earlier capture",
+            stale_files.len()
+        );
+    }
+    Ok(())
+}
+
+/// Write the trace events into a perfetto file until the channel closes. If writing fails, the
+/// current file is still finalised, so everything written up to the error stays loadable
 pub fn spawn_perfetto_file_writer(
     perfetto_filename: PathBuf,
     trace_event_recver: Receiver<TracingEvent>,
     exit_flag: Arc<AtomicBool>,
+    options: FileWriterOptions,
 ) -> JoinHandle<anyhow::Result<()>> {
     std::thread::spawn(move || {
+        remove_stale_rotated_files(&perfetto_filename)?;
+
         // Create file
-        let mut file = File::options()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(&perfetto_filename)
-            .context("Failed to open perfetto trace file")?;
-        file.write("{\"traceEvents\": [".as_bytes())
-            .context("Failed to write to perfetto trace file")?;
-
-        let mut first_event = true;
-        while !exit_flag.load(Ordering::SeqCst) {
-            match trace_event_recver.recv() {
-                Ok(trace_event) => {
-                    // write comma if not first event
-                    if !first_event {
-                        file.write_all(b",\n")
-                            .context("Failed to add comma seperator")?;
-                    } else {
-                        first_event = false;
-                    }
-
-                    // write trace event as json
-                    let json_str = "\t".to_string()
-                        + &trace_event
-                            .to_json()
-                            .context("Failed to jsonify trace event")?;
-                    file.write_all(json_str.as_bytes())
-                        .context("Failed to write trace event to perfetto file")?;
+        let mut file = PerfettoFile::create(&perfetto_filename, options.metadata.as_ref())?;
+
+        let result = write_trace_events(
+            &mut file,
+            &perfetto_filename,
+            &trace_event_recver,
+            &exit_flag,
+            &options,
+        );
+
+        // finalise file and exit
+        let finalised = file.finalise();
+        result.and(finalised)
+    })
+}
+
+/// Write the received trace events into `file`, rotating to the next one when it is full
+fn write_trace_events(
+    file: &mut PerfettoFile,
+    perfetto_filename: &Path,
+    trace_event_recver: &Receiver<TracingEvent>,
+    exit_flag: &AtomicBool,
+    options: &FileWriterOptions,
+) -> anyhow::Result<()> {
+    let mut file_index = 0;
+
+    // Metadata (process / thread names) is replayed into every rotated file and into a fallback
+    // writer started with the same options
+    let mut metadata_events = options.metadata_events.lock().unwrap();
+
+    // Record the absolute start time of the trace (already replayed if another writer did)
+    if let Some(ts_offset_us) = options.ts_offset_us
+        && metadata_events.is_empty()
+    {
+        let json_str = "\t".to_string()
+            + &TracingEvent::Metadata {
+                name: "process_labels".to_string(),
+                cat: None,
+                pid: 0,
+                tid: None,
+                args: HashMap::from([(
+                    "labels".to_string(),
+                    format!("wall clock start: {ts_offset_us} us since unix epoch"),
+                )]),
+            }
+            .to_json()?;
+        metadata_events.push(json_str);
+    }
+    for metadata in metadata_events.iter() {
+        file.write_event(metadata)?;
+    }
+    drop(metadata_events);
+
+    let mut per_core_layout = options.per_core_processes.then(PerCoreLayout::default);
+    while !exit_flag.load(Ordering::SeqCst) {
+        let Ok(trace_event) = trace_event_recver.recv() else {
+            break; // channel closed
+        };
+        let trace_events = match &mut per_core_layout {
+            Some(per_core_layout) => per_core_layout.remap(trace_event),
+            None => vec![trace_event],
+        };
+
+        for mut trace_event in trace_events {
+            if let Some(ts_offset_us) = options.ts_offset_us {
+                trace_event.offset_ts(ts_offset_us);
+            }
+
+            // jsonify trace event
+            let json_str = "\t".to_string()
+                + &trace_event
+                    .to_json()
+                    .context("Failed to jsonify trace event")?;
+
+            // Check size limit
+            if let Some(max_file_size) = options.max_file_size
+                && file.would_exceed(&json_str, max_file_size)
+            {
+                file.finalise()?;
+                if !options.rotate {
+                    println!(
+                        "Trace file reached its maximum size of {max_file_size} bytes. Stopping capture."
+                    );
+                    return Ok(());
+                }
+
+                // Continue in the next file
+                file_index += 1;
+                let next_filename = rotated_filename(perfetto_filename, file_index);
+                println!("Continuing trace in {}", next_filename.display());
+                *file = PerfettoFile::create(&next_filename, options.metadata.as_ref())?;
+                for metadata in options.metadata_events.lock().unwrap().iter() {
+                    file.write_event(metadata)?;
                 }
-                Err(_) => break, // channel closed
+
+                // The replayed metadata alone fills the file ==> every further event would rotate
+                if file.bytes_written + FILE_FOOTER.len() as u64 > max_file_size {
+                    anyhow::bail!(
+                        "--max-file-size of {max_file_size} bytes is too small for the {} bytes of process/thread names repeated in every rotated file",
+                        file.bytes_written
+                    );
+                }
+            }
+            if let TracingEvent::Metadata { .. } = trace_event {
+                options
+                    .metadata_events
+                    .lock()
+                    .unwrap()
+                    .push(json_str.clone());
             }
+
+            // write trace event as json
+            file.write_event(&json_str)?;
         }
+    }
+    Ok(())
+}
 
-        // finalise file and exit
-        file.write_all(b"\n]}\n")
-            .context("Failed to finalise perfetto trace file")?;
-        Ok(())
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instant_event(ts: u128) -> TracingEvent {
+        TracingEvent::Instant {
+            name: "event".to_string(),
+            cat: None,
+            ts,
+            pid: None,
+            tid: None,
+            scope: crate::perfetto_backend::trace_event::InstantScope::Global,
+            args: HashMap::new(),
+            cname: crate::perfetto_backend::trace_event::CName::Good,
+        }
+    }
+
+    /// Storage that runs out of space after `capacity` bytes, like a full disk
+    struct FullDisk {
+        content: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let free = self.capacity - self.content.len();
+            if free == 0 {
+                return Err(std::io::ErrorKind::StorageFull.into());
+            }
+            let written = buf.len().min(free);
+            self.content.extend_from_slice(&buf[..written]);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TraceStorage for FullDisk {
+        fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+            self.content.truncate(len as usize);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_full_disk_keeps_file_valid() {
+        let disk = FullDisk {
+            content: Vec::new(),
+            capacity: 400,
+        };
+        let mut file = PerfettoFile::new(disk, Path::new("trace.json"), None).unwrap();
+
+        let mut written_events = 0;
+        let error = loop {
+            let json_str = instant_event(written_events).to_json().unwrap();
+            match file.write_event(&json_str) {
+                Ok(()) => written_events += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(format!("{error:#}").contains("trace.json"));
+        assert!(written_events > 0);
+
+        // The event that did not fit is cut off, which leaves room for the footer
+        file.finalise().unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&file.file.content).unwrap();
+        assert_eq!(
+            json["traceEvents"].as_array().unwrap().len() as u128,
+            written_events
+        );
+    }
+
+    #[test]
+    fn test_rotated_filename() {
+        assert_eq!(
+            rotated_filename(Path::new("out/rustmeter-perfetto-debug.json"), 2),
+            PathBuf::from("out/rustmeter-perfetto-debug.2.json")
+        );
+        assert_eq!(
+            rotated_filename(Path::new("trace"), 1),
+            PathBuf::from("trace.1")
+        );
+    }
+
+    #[test]
+    fn test_rotation_keeps_every_file_valid() {
+        let dir = std::env::temp_dir().join(format!("rustmeter-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("trace.json");
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(TracingEvent::Metadata {
+            name: "process_name".to_string(),
+            cat: None,
+            args: HashMap::from([("name".to_string(), "CORE OVERVIEW".to_string())]),
+            pid: 0,
+            tid: None,
+        });
+        for ts in 0..20 {
+            let _ = sender.send(instant_event(ts));
+        }
+        drop(sender);
+
+        let options = FileWriterOptions {
+            max_file_size: Some(512),
+            rotate: true,
+            ..Default::default()
+        };
+        spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        let mut files = vec![filename.clone()];
+        files.extend(
+            (1..)
+                .map(|i| rotated_filename(&filename, i))
+                .take_while(|f| f.exists()),
+        );
+        assert!(files.len() > 1);
+
+        let mut instant_count = 0;
+        for file in &files {
+            let content = std::fs::read_to_string(file).unwrap();
+            assert!(content.len() <= 512);
+            let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+            let events = json["traceEvents"].as_array().unwrap();
+            assert_eq!(events[0]["ph"], "M");
+            instant_count += events.iter().filter(|e| e["ph"] == "i").count();
+        }
+        assert_eq!(instant_count, 20);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_rejects_size_below_metadata() {
+        let dir =
+            std::env::temp_dir().join(format!("rustmeter-rotate-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("trace.json");
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        for tid in 0..10 {
+            let _ = sender.send(TracingEvent::Metadata {
+                name: "thread_name".to_string(),
+                cat: None,
+                args: HashMap::from([("name".to_string(), format!("Task {tid}"))]),
+                pid: 1,
+                tid: Some(tid),
+            });
+        }
+        for ts in 0..5 {
+            let _ = sender.send(instant_event(ts));
+        }
+        drop(sender);
+
+        let options = FileWriterOptions {
+            max_file_size: Some(512),
+            rotate: true,
+            ..Default::default()
+        };
+        let error = spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options,
+        )
+        .join()
+        .unwrap()
+        .unwrap_err();
+        assert!(format!("{error:#}").contains("--max-file-size"));
+
+        // Rotation stopped instead of writing one file per event, and every file stays valid
+        let files: Vec<_> = (1..)
+            .map(|i| rotated_filename(&filename, i))
+            .take_while(|f| f.exists())
+            .collect();
+        assert!(!files.is_empty() && files.len() < 5);
+        for file in files.iter().chain([&filename]) {
+            let content = std::fs::read_to_string(file).unwrap();
+            assert!(serde_json::from_str::<serde_json::Value>(&content).is_ok());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_rotated_files_are_removed() {
+        let dir =
+            std::env::temp_dir().join(format!("rustmeter-rotate-stale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("trace.json");
+        for index in 1..=3 {
+            std::fs::write(rotated_filename(&filename, index), "stale").unwrap();
+        }
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(instant_event(5));
+        drop(sender);
+        spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            FileWriterOptions::default(),
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        assert!(filename.exists());
+        assert!(!rotated_filename(&filename, 1).exists());
+        assert!(!rotated_filename(&filename, 3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fallback_writer_replays_metadata() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-fallback-{}.json", std::process::id()));
+        let fallback_filename = std::env::temp_dir().join(format!(
+            "rustmeter-fallback-{}.fallback.json",
+            std::process::id()
+        ));
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(TracingEvent::Metadata {
+            name: "thread_name".to_string(),
+            cat: None,
+            args: HashMap::from([("name".to_string(), "Task 1".to_string())]),
+            pid: 1,
+            tid: Some(1),
+        });
+        drop(sender);
+
+        let options = FileWriterOptions::default();
+        spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options.clone(),
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        // The fallback writer only gets the events left over, but still names the task
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(instant_event(5));
+        drop(sender);
+        spawn_perfetto_file_writer(
+            fallback_filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        let content = std::fs::read_to_string(&fallback_filename).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "thread_name");
+        assert_eq!(events[0]["args"]["name"], "Task 1");
+
+        std::fs::remove_file(&filename).unwrap();
+        std::fs::remove_file(&fallback_filename).unwrap();
+    }
+
+    #[test]
+    fn test_embedded_metadata() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-metadata-{}.json", std::process::id()));
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(instant_event(5));
+        drop(sender);
+
+        let options = FileWriterOptions {
+            metadata: Some(BTreeMap::from([(
+                "rustmeter_version".to_string(),
+                "0.1.0".to_string(),
+            )])),
+            ..Default::default()
+        };
+        spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        let content = std::fs::read_to_string(&filename).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json["metadata"]["rustmeter_version"], "0.1.0");
+        assert_eq!(json["traceEvents"].as_array().unwrap().len(), 1);
+
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_wall_clock_offset() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-wall-clock-{}.json", std::process::id()));
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let _ = sender.send(instant_event(5));
+        drop(sender);
+
+        let options = FileWriterOptions {
+            ts_offset_us: Some(1_700_000_000_000_000),
+            ..Default::default()
+        };
+        spawn_perfetto_file_writer(
+            filename.clone(),
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            options,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+
+        let content = std::fs::read_to_string(&filename).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events[0]["name"], "process_labels");
+        assert_eq!(events[1]["ts"], 1_700_000_000_000_005u64);
+
+        std::fs::remove_file(&filename).unwrap();
+    }
 }
@@ -1,2 +1,7 @@
+pub mod core_layout;
+pub mod event_sender;
+pub mod event_tee;
 pub mod file_writer;
 pub mod trace_event;
+pub mod trigger_ring;
+pub mod ui_server;
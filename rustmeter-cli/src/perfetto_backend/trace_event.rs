@@ -1,7 +1,7 @@
 use anyhow::Context;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum InstantScope {
     #[serde(rename = "t")]
@@ -12,17 +12,48 @@ pub enum InstantScope {
     Global,
 }
 
-#[derive(Debug, Serialize)]
+/// Reserved color names of the trace event format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CName {
-    #[serde(rename = "good")]
     Good,
-    #[serde(rename = "terrible")]
+    Bad,
     Terrible,
+    Yellow,
+    Olive,
+    Black,
+    Grey,
+    White,
+    GenericWork,
+    ThreadStateRunning,
+    ThreadStateRunnable,
+    ThreadStateSleeping,
+    ThreadStateIowait,
+    ThreadStateUninterruptible,
+    RailResponse,
+    RailAnimation,
+    RailIdle,
+    RailLoad,
+    Startup,
+}
+
+/// Slice a flow event binds to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FlowBinding {
+    /// The slice enclosing the flow event (instead of the next slice)
+    #[serde(rename = "e")]
+    Enclosing,
+}
+
+/// Timestamps are written as plain integers. Read them as u64, because the tagged enum buffers
+/// its fields in a form without u128 support
+fn deserialize_ts<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    u64::deserialize(deserializer).map(u128::from)
 }
 
 pub type TracingArgsMap<T> = std::collections::HashMap<String, T>;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 // rename the enum variants to match the Perfetto trace event types
 // ==> {ph = "X", "B", "E", "i", "C", "M", ...other types} in one dictionary (tagged enum)
 #[serde(tag = "ph")]
@@ -35,9 +66,10 @@ pub enum TracingEvent {
         cat: Option<String>,
         pid: u32,
         tid: u32,
+        #[serde(deserialize_with = "deserialize_ts")]
         ts: u128,
         dur: u64,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<String>,
     },
     #[serde(rename = "B")]
@@ -45,12 +77,15 @@ pub enum TracingEvent {
         name: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         cat: Option<String>,
+        #[serde(deserialize_with = "deserialize_ts")]
         ts: u128,
         pid: u32,
         #[serde(skip_serializing_if = "Option::is_none")]
         tid: Option<u32>,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cname: Option<CName>,
     },
     #[serde(rename = "E")]
     End {
@@ -61,8 +96,9 @@ pub enum TracingEvent {
         pid: u32,
         #[serde(skip_serializing_if = "Option::is_none")]
         tid: Option<u32>,
+        #[serde(deserialize_with = "deserialize_ts")]
         ts: u128,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<String>,
     },
     #[serde(rename = "i")]
@@ -70,6 +106,7 @@ pub enum TracingEvent {
         name: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         cat: Option<String>,
+        #[serde(deserialize_with = "deserialize_ts")]
         ts: u128,
         #[serde(skip_serializing_if = "Option::is_none")]
         pid: Option<u32>,
@@ -77,7 +114,7 @@ pub enum TracingEvent {
         tid: Option<u32>,
         #[serde(rename = "s")]
         scope: InstantScope,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<String>,
         cname: CName,
     },
@@ -88,10 +125,56 @@ pub enum TracingEvent {
         cat: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pid: Option<u32>,
+        #[serde(deserialize_with = "deserialize_ts")]
         ts: u128,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<f64>,
     },
+    #[serde(rename = "b")]
+    AsyncBegin {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cat: Option<String>,
+        id: String,
+        pid: u32,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: u128,
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
+        args: TracingArgsMap<String>,
+    },
+    #[serde(rename = "e")]
+    AsyncEnd {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cat: Option<String>,
+        id: String,
+        pid: u32,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: u128,
+    },
+    #[serde(rename = "s")]
+    FlowStart {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cat: Option<String>,
+        id: u64,
+        pid: u32,
+        tid: u32,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: u128,
+    },
+    #[serde(rename = "f")]
+    FlowEnd {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cat: Option<String>,
+        id: u64,
+        pid: u32,
+        tid: u32,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: u128,
+        bp: FlowBinding,
+    },
     #[serde(rename = "M")]
     Metadata {
         name: String,
@@ -100,12 +183,28 @@ pub enum TracingEvent {
         pid: u32,
         #[serde(skip_serializing_if = "Option::is_none")]
         tid: Option<u32>,
-        #[serde(skip_serializing_if = "TracingArgsMap::is_empty")]
+        #[serde(default, skip_serializing_if = "TracingArgsMap::is_empty")]
         args: TracingArgsMap<String>,
     },
 }
 
 impl TracingEvent {
+    /// Shift the timestamp of the event by the given offset (metadata events have none)
+    pub fn offset_ts(&mut self, offset_us: u128) {
+        match self {
+            TracingEvent::Complete { ts, .. }
+            | TracingEvent::Begin { ts, .. }
+            | TracingEvent::End { ts, .. }
+            | TracingEvent::Instant { ts, .. }
+            | TracingEvent::Counter { ts, .. }
+            | TracingEvent::AsyncBegin { ts, .. }
+            | TracingEvent::AsyncEnd { ts, .. }
+            | TracingEvent::FlowStart { ts, .. }
+            | TracingEvent::FlowEnd { ts, .. } => *ts += offset_us,
+            TracingEvent::Metadata { .. } => {}
+        }
+    }
+
     /// Convert the tracing event to a JSON string for Perfetto
     pub fn to_json(&self) -> anyhow::Result<String> {
         serde_json::to_string(self).context("Failed to serialize TracingEvent to JSON")
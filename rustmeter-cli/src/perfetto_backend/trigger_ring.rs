@@ -0,0 +1,366 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crossbeam::channel::Receiver;
+
+use crate::perfetto_backend::trace_event::TracingEvent;
+
+/// Condition that writes out the ring buffer of a triggered capture (`--trigger`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// A metric rises above the value (`name>value`)
+    MetricAbove { name: String, value: f64 },
+    /// A metric falls below the value (`name<value`)
+    MetricBelow { name: String, value: f64 },
+    /// A marker of the device or of the host (`--stdin-markers`) with this label (`marker=label`)
+    Marker { label: String },
+}
+
+impl Trigger {
+    /// Parse a trigger in the form `metric>value`, `metric<value` or `marker=label`
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        if let Some(label) = arg.strip_prefix("marker=") {
+            return Ok(Trigger::Marker {
+                label: label.trim().to_string(),
+            });
+        }
+
+        let parse_value = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| format!("Invalid value in '{arg}': {e}"))
+        };
+        if let Some((name, value)) = arg.split_once('>') {
+            Ok(Trigger::MetricAbove {
+                name: name.trim().to_string(),
+                value: parse_value(value)?,
+            })
+        } else if let Some((name, value)) = arg.split_once('<') {
+            Ok(Trigger::MetricBelow {
+                name: name.trim().to_string(),
+                value: parse_value(value)?,
+            })
+        } else {
+            Err(format!(
+                "Expected METRIC>VALUE, METRIC<VALUE or marker=LABEL, got '{arg}'"
+            ))
+        }
+    }
+
+    /// Check if the trace event fires the trigger
+    fn matches(&self, event: &TracingEvent) -> bool {
+        match (self, event) {
+            (
+                Trigger::MetricAbove { name, value },
+                TracingEvent::Counter {
+                    name: track, args, ..
+                },
+            ) => is_metric_track(track, name) && args.get("value").is_some_and(|v| v > value),
+            (
+                Trigger::MetricBelow { name, value },
+                TracingEvent::Counter {
+                    name: track, args, ..
+                },
+            ) => is_metric_track(track, name) && args.get("value").is_some_and(|v| v < value),
+            (Trigger::Marker { label }, TracingEvent::Instant { name, cat, .. }) => {
+                match cat.as_deref() {
+                    Some("marker") => name == label,
+                    Some("host_marker") => name.strip_prefix("[host] ") == Some(label),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Check if the counter track belongs to the metric (the track name may carry a unit)
+fn is_metric_track(track: &str, metric: &str) -> bool {
+    track
+        .strip_prefix(metric)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(" ["))
+}
+
+/// Keeps the trace events of the last `window_us` in memory until the trigger fires, like the
+/// pre-trigger buffer of an oscilloscope (`--ring-seconds`). Afterwards every event passes.
+///
+/// The written window loads on its own: all metadata (process / thread names) is kept, and
+/// slices that began before the window but are still open are re-opened at its start.
+pub struct TriggerRing {
+    window_us: u128,
+    trigger: Trigger,
+    triggered: bool,
+    metadata: Vec<TracingEvent>,
+    events: VecDeque<TracingEvent>,
+    /// Begin events pushed out of the window whose slice is still open, per (pid, tid) track
+    open_slices: HashMap<(u32, Option<u32>), Vec<TracingEvent>>,
+    /// Async begin events pushed out of the window whose slice is still open, per (pid, id, name)
+    open_async_slices: HashMap<(u32, String, String), TracingEvent>,
+}
+
+impl TriggerRing {
+    pub fn new(window_us: u128, trigger: Trigger) -> Self {
+        Self {
+            window_us,
+            trigger,
+            triggered: false,
+            metadata: Vec::new(),
+            events: VecDeque::new(),
+            open_slices: HashMap::new(),
+            open_async_slices: HashMap::new(),
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Add a trace event and get the events to write: nothing while waiting for the trigger, the
+    /// whole window when it fires and every event afterwards
+    pub fn push(&mut self, event: TracingEvent) -> Vec<TracingEvent> {
+        if self.triggered {
+            return vec![event];
+        }
+        let Some(ts) = event_ts(&event) else {
+            self.metadata.push(event);
+            return Vec::new();
+        };
+
+        if self.trigger.matches(&event) {
+            self.triggered = true;
+            return self.take_window(event);
+        }
+
+        self.events.push_back(event);
+        while self
+            .events
+            .front()
+            .and_then(event_ts)
+            .is_some_and(|front_ts| front_ts + self.window_us < ts)
+        {
+            if let Some(evicted) = self.events.pop_front() {
+                self.evict(evicted);
+            }
+        }
+        Vec::new()
+    }
+
+    /// Remember the slices that are still open when their begin leaves the window
+    fn evict(&mut self, event: TracingEvent) {
+        match &event {
+            TracingEvent::Begin { pid, tid, .. } => {
+                self.open_slices
+                    .entry((*pid, *tid))
+                    .or_default()
+                    .push(event);
+            }
+            TracingEvent::End { pid, tid, .. } => {
+                if let Some(open_slices) = self.open_slices.get_mut(&(*pid, *tid)) {
+                    open_slices.pop();
+                }
+            }
+            TracingEvent::AsyncBegin { pid, id, name, .. } => {
+                self.open_async_slices
+                    .insert((*pid, id.clone(), name.clone()), event);
+            }
+            TracingEvent::AsyncEnd { pid, id, name, .. } => {
+                self.open_async_slices
+                    .remove(&(*pid, id.clone(), name.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Metadata, the re-opened slices and the buffered events followed by the trigger event
+    fn take_window(&mut self, trigger_event: TracingEvent) -> Vec<TracingEvent> {
+        let window_start = self
+            .events
+            .front()
+            .or(Some(&trigger_event))
+            .and_then(event_ts)
+            .unwrap_or(0);
+
+        let mut window = std::mem::take(&mut self.metadata);
+        let open_slices = std::mem::take(&mut self.open_slices)
+            .into_values()
+            .flatten();
+        let open_async_slices = std::mem::take(&mut self.open_async_slices).into_values();
+        for mut begin in open_slices.chain(open_async_slices) {
+            if let TracingEvent::Begin { ts, args, .. }
+            | TracingEvent::AsyncBegin { ts, args, .. } = &mut begin
+            {
+                args.insert("begin_ts_us".to_string(), ts.to_string());
+                *ts = window_start;
+            }
+            window.push(begin);
+        }
+        window.extend(self.events.drain(..));
+        window.push(trigger_event);
+        window
+    }
+}
+
+/// Timestamp of a trace event (metadata has none)
+fn event_ts(event: &TracingEvent) -> Option<u128> {
+    match event {
+        TracingEvent::Complete { ts, .. }
+        | TracingEvent::Begin { ts, .. }
+        | TracingEvent::End { ts, .. }
+        | TracingEvent::Instant { ts, .. }
+        | TracingEvent::Counter { ts, .. }
+        | TracingEvent::AsyncBegin { ts, .. }
+        | TracingEvent::AsyncEnd { ts, .. }
+        | TracingEvent::FlowStart { ts, .. }
+        | TracingEvent::FlowEnd { ts, .. } => Some(*ts),
+        TracingEvent::Metadata { .. } => None,
+    }
+}
+
+/// Run the trace events of `source` through the ring and return the events to write. The
+/// thread sets `triggered` once the trigger fired and closes the returned channel with `source`
+pub fn spawn_trigger_ring(
+    source: Receiver<TracingEvent>,
+    mut ring: TriggerRing,
+    triggered: Arc<AtomicBool>,
+) -> Receiver<TracingEvent> {
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    std::thread::spawn(move || {
+        while let Ok(event) = source.recv() {
+            let was_triggered = ring.is_triggered();
+            for event in ring.push(event) {
+                let _ = sender.send(event);
+            }
+            if !was_triggered && ring.is_triggered() {
+                println!("Trigger fired. Writing the buffered window and recording on.");
+                triggered.store(true, Ordering::SeqCst);
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::perfetto_backend::trace_event::{CName, InstantScope};
+
+    use super::*;
+
+    fn metric(ts: u128, value: f64) -> TracingEvent {
+        TracingEvent::Counter {
+            name: "current [mA]".to_string(),
+            cat: None,
+            pid: None,
+            ts,
+            args: HashMap::from([("value".to_string(), value)]),
+        }
+    }
+
+    fn begin(ts: u128, name: &str) -> TracingEvent {
+        TracingEvent::Begin {
+            name: name.to_string(),
+            cat: None,
+            ts,
+            pid: 1,
+            tid: Some(10),
+            args: HashMap::new(),
+            cname: None,
+        }
+    }
+
+    fn end(ts: u128) -> TracingEvent {
+        TracingEvent::End {
+            name: None,
+            cat: None,
+            pid: 1,
+            tid: Some(10),
+            ts,
+            args: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_trigger() {
+        assert_eq!(
+            Trigger::parse("current>120.5"),
+            Ok(Trigger::MetricAbove {
+                name: "current".to_string(),
+                value: 120.5
+            })
+        );
+        assert_eq!(
+            Trigger::parse("battery < 3.3"),
+            Ok(Trigger::MetricBelow {
+                name: "battery".to_string(),
+                value: 3.3
+            })
+        );
+        assert_eq!(
+            Trigger::parse("marker=Fault"),
+            Ok(Trigger::Marker {
+                label: "Fault".to_string()
+            })
+        );
+        assert!(Trigger::parse("current").is_err());
+        assert!(Trigger::parse("current>high").is_err());
+    }
+
+    #[test]
+    fn test_window_is_written_on_trigger() {
+        let trigger = Trigger::parse("current>100").unwrap();
+        let mut ring = TriggerRing::new(1000, trigger);
+
+        let metadata = TracingEvent::Metadata {
+            name: "process_name".to_string(),
+            cat: None,
+            pid: 1,
+            tid: None,
+            args: HashMap::new(),
+        };
+        assert!(ring.push(metadata).is_empty());
+        assert!(ring.push(begin(0, "Running")).is_empty()); // still open at the trigger
+        assert!(ring.push(begin(100, "Created")).is_empty());
+        assert!(ring.push(end(200)).is_empty());
+        assert!(ring.push(metric(300, 50.0)).is_empty());
+        assert!(ring.push(metric(2500, 80.0)).is_empty());
+        assert!(!ring.is_triggered());
+
+        let window = ring.push(metric(3000, 150.0));
+        assert!(ring.is_triggered());
+        assert_eq!(window.len(), 4);
+        assert!(matches!(window[0], TracingEvent::Metadata { .. }));
+        // The open slice is re-opened at the window start
+        assert!(matches!(
+            &window[1],
+            TracingEvent::Begin { name, ts: 2500, args, .. }
+                if name == "Running" && args["begin_ts_us"] == "0"
+        ));
+        assert!(matches!(window[2], TracingEvent::Counter { ts: 2500, .. }));
+        assert!(matches!(window[3], TracingEvent::Counter { ts: 3000, .. }));
+
+        // Recording goes on after the trigger
+        assert_eq!(ring.push(metric(3100, 10.0)).len(), 1);
+    }
+
+    #[test]
+    fn test_host_marker_trigger() {
+        let trigger = Trigger::parse("marker=dump").unwrap();
+        let host_marker = TracingEvent::Instant {
+            name: "[host] dump".to_string(),
+            cat: Some("host_marker".to_string()),
+            ts: 10,
+            pid: None,
+            tid: None,
+            scope: InstantScope::Global,
+            args: HashMap::new(),
+            cname: CName::Olive,
+        };
+        assert!(trigger.matches(&host_marker));
+        assert!(!trigger.matches(&metric(10, 1.0)));
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+
+const PERFETTO_UI_URL: &str = "https://ui.perfetto.dev";
+
+/// Port the Perfetto UI is allowed to fetch traces from (its content security policy only
+/// permits this one for local servers)
+const SERVER_PORT: u16 = 9001;
+
+/// Serve a finished trace to the Perfetto UI and open it in the browser (`--open`).
+///
+/// The UI cannot read a local path from its URL, so the file is served on localhost and the UI
+/// is opened with a `?url=` deep link to it. Returns once the UI fetched the trace or on Ctrl+C.
+pub fn open_in_perfetto_ui(
+    trace_filename: &Path,
+    exit_flag: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let file_name = trace_filename
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .ok_or(anyhow::anyhow!("Trace path has no file name"))?;
+    let content = std::fs::read(trace_filename).context("Failed to read the trace file")?;
+
+    let listener = TcpListener::bind(("127.0.0.1", SERVER_PORT))
+        .with_context(|| format!("Failed to serve the trace on port {SERVER_PORT}"))?;
+    let url = format!("{PERFETTO_UI_URL}/#!/?url=http://127.0.0.1:{SERVER_PORT}/{file_name}");
+
+    if !open_browser(&url) {
+        println!("Could not open a browser. Open this URL to load the trace:");
+    }
+    println!("{url}");
+    println!("Serving {file_name} until Perfetto UI loaded it (Ctrl+C to stop)...");
+
+    serve_file(&listener, &file_name, &content, &exit_flag)
+}
+
+/// Accept connections until the file was sent once or the exit flag is set
+fn serve_file(
+    listener: &TcpListener,
+    file_name: &str,
+    content: &[u8],
+    exit_flag: &AtomicBool,
+) -> anyhow::Result<()> {
+    listener
+        .set_nonblocking(true)
+        .context("Failed to configure the trace server")?;
+
+    while !exit_flag.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // a single broken connection (e.g. a browser probe) does not end serving
+                if let Ok(true) = handle_request(stream, file_name, content) {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e).context("Failed to accept a connection"),
+        }
+    }
+    Ok(())
+}
+
+/// Answer a single HTTP request. Returns true if the file was sent
+fn handle_request(mut stream: TcpStream, file_name: &str, content: &[u8]) -> anyhow::Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Only the request line matters, the headers are skipped
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header_line = String::new();
+    while reader.read_line(&mut header_line)? > 2 {
+        header_line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let requested_file = path.strip_prefix('/') == Some(file_name);
+
+    // CORS header: the UI fetches from its own origin
+    let cors = format!("Access-Control-Allow-Origin: {PERFETTO_UI_URL}\r\n");
+    match (method, requested_file) {
+        ("GET", true) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n{cors}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content.len()
+            )?;
+            stream.write_all(content)?;
+            Ok(true)
+        }
+        _ => {
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\n{cors}Content-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+            Ok(false)
+        }
+    }
+}
+
+/// Open the URL with the default browser of the platform. Returns false if there is none (e.g.
+/// on a headless machine)
+fn open_browser(url: &str) -> bool {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        // Without a display server xdg-open would fall back to a terminal browser
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return false;
+        }
+        std::process::Command::new("xdg-open")
+    };
+
+    command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn fetch(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_file_until_fetched() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            serve_file(
+                &listener,
+                "trace.json",
+                b"{\"traceEvents\": []}",
+                &AtomicBool::new(false),
+            )
+        });
+
+        let not_found = fetch(port, "/other.json");
+        assert!(not_found.starts_with("HTTP/1.1 404"));
+
+        let response = fetch(port, "/trace.json");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Access-Control-Allow-Origin: https://ui.perfetto.dev"));
+        assert!(response.ends_with("{\"traceEvents\": []}"));
+
+        server.join().unwrap().unwrap();
+    }
+}
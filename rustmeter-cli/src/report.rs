@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{diff::MonitorStats, tracing::tracing_instance::TracingInstance};
+
+/// Version of the report schema. Bumped whenever a field is renamed, removed or changes its
+/// meaning, so archived reports can be told apart
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Machine readable summary of a tracing session (`--report-json`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionReport {
+    pub schema_version: u32,
+    /// Device time between the first and the last received event
+    pub capture_duration_us: u128,
+    /// Trace events dropped because the writer could not keep up (missing from the trace)
+    pub dropped_events: u64,
+    /// Duration statistics per monitor name (coalesced monitors are not included)
+    pub monitors: BTreeMap<String, MonitorStats>,
+    pub cores: Vec<CoreReport>,
+    pub tasks: Vec<TaskReport>,
+}
+
+/// Time a core spent running any executor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoreReport {
+    pub core_id: u8,
+    pub busy_us: u128,
+    /// Share of the capture duration in percent
+    pub utilization_pct: f64,
+}
+
+/// Time a task spent running (being polled) and how often it was polled. Many polls with little
+/// running time each hint at a task that gets woken without having work (a broken waker)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskReport {
+    pub executor: String,
+    pub task: String,
+    pub running_us: u128,
+    /// Share of the capture duration in percent
+    pub utilization_pct: f64,
+    pub polls: u64,
+    pub polls_per_sec: f64,
+    /// Mean running time per poll
+    pub mean_poll_us: f64,
+}
+
+impl TaskReport {
+    /// Report of a task from its usage over the capture duration
+    pub fn new(
+        (executor, task): (String, String),
+        running_us: u128,
+        polls: u64,
+        capture_duration_us: u128,
+    ) -> Self {
+        let capture_duration_us = capture_duration_us.max(1) as f64;
+        Self {
+            executor,
+            task,
+            running_us,
+            utilization_pct: running_us as f64 / capture_duration_us * 100.0,
+            polls,
+            polls_per_sec: polls as f64 * 1_000_000.0 / capture_duration_us,
+            mean_poll_us: running_us as f64 / polls.max(1) as f64,
+        }
+    }
+}
+
+impl SessionReport {
+    /// Build the report from the aggregations of an ended session
+    pub fn from_tracing_instance(tracing_instance: &TracingInstance) -> Self {
+        let capture_duration_us = tracing_instance.get_capture_duration_us();
+        let utilization_pct =
+            |duration_us: u128| duration_us as f64 / capture_duration_us.max(1) as f64 * 100.0;
+
+        let usage = tracing_instance.get_usage();
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            capture_duration_us,
+            dropped_events: tracing_instance.get_dropped_events(),
+            monitors: tracing_instance
+                .get_monitor_durations()
+                .iter()
+                .map(|(name, durations)| (name.clone(), MonitorStats::from(durations)))
+                .collect(),
+            cores: usage
+                .core_busy_us
+                .into_iter()
+                .map(|(core_id, busy_us)| CoreReport {
+                    core_id,
+                    busy_us,
+                    utilization_pct: utilization_pct(busy_us),
+                })
+                .collect(),
+            tasks: usage
+                .task_running_us
+                .into_iter()
+                .map(|(key, running_us)| {
+                    let polls = usage.task_polls.get(&key).copied().unwrap_or(0);
+                    TaskReport::new(key, running_us, polls, capture_duration_us)
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the report as pretty printed JSON
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to jsonify report")?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write report to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elf_file::FirmwareAddressMap,
+        tracing::{log_event::LogEvent, log_line::LogLine, tracing_instance::TracingOptions},
+    };
+
+    #[test]
+    fn test_session_report() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let _receiver = instance.get_trace_event_receiver();
+
+        let events = [
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (100, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (
+                100,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (120, "@EVENT_MONITOR_START(function_name=read,core_id=0)"),
+            (150, "@EVENT_MONITOR_END(function_name=read,core_id=0)"),
+            (160, "@EVENT_MONITOR_START(function_name=read,core_id=0)"),
+            (170, "@EVENT_MONITOR_END(function_name=read,core_id=0)"),
+            (
+                300,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (350, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)"),
+            (1000, "@EVENT_TRACE_FLUSH(core_id=0)"),
+        ];
+        for (ts_us, event) in events {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", ts_us as f64 / 1e6)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        instance.end_session();
+
+        let report = SessionReport::from_tracing_instance(&instance);
+        assert_eq!(report.schema_version, REPORT_SCHEMA_VERSION);
+        assert_eq!(report.capture_duration_us, 1000);
+        assert_eq!(
+            report.monitors["read"],
+            MonitorStats {
+                count: 2,
+                min_us: 10,
+                max_us: 30,
+                mean_us: 20.0,
+                p99_us: 30,
+            }
+        );
+        assert_eq!(
+            report.cores,
+            vec![CoreReport {
+                core_id: 0,
+                busy_us: 250,
+                utilization_pct: 25.0,
+            }]
+        );
+        assert_eq!(report.tasks.len(), 1);
+        assert_eq!(report.tasks[0].task, "Task 0xA");
+        assert_eq!(report.tasks[0].running_us, 200);
+        assert_eq!(report.tasks[0].utilization_pct, 20.0);
+        assert_eq!(report.tasks[0].polls, 1);
+        assert_eq!(report.tasks[0].polls_per_sec, 1000.0);
+        assert_eq!(report.tasks[0].mean_poll_us, 200.0);
+
+        // Stable field names for consumers of the file
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["monitors"]["read"]["p99_us"], 30);
+        assert_eq!(json["tasks"][0]["executor"], report.tasks[0].executor);
+    }
+}
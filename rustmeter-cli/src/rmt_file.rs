@@ -0,0 +1,543 @@
+//! Native rustmeter capture format (`.rmt`).
+//!
+//! Perfetto JSON is made for humans and slow to re-parse. An `.rmt` file stores the decoded log
+//! lines (tracing events and plain logs) in a compact binary form together with the symbol map of
+//! the firmware, so custom tools can read a capture quickly and seek by device time. Use
+//! `rustmeter --convert <FILE>` to turn it into a Perfetto trace afterwards.
+//!
+//! All integers are little endian, strings are UTF-8 without terminator. Version 1:
+//!
+//! ```text
+//! header:  magic "RMTRACE\0" (8 bytes) | version u16 | symbol count u32
+//!          symbol count x (address u64 | name length u16 | name)
+//! records: timestamp in µs u64 | level u8 | message length u32 | message
+//! index:   entry count u32 | entry count x (timestamp in µs u64 | file offset of the record u64)
+//! footer:  file offset of the index u64 | magic "RMTINDEX" (8 bytes)
+//! ```
+//!
+//! The level is 0 = trace, 1 = debug, 2 = info, 3 = warn and 4 = error. The message is the defmt
+//! message as printed by the runner, tracing events keep their `@EVENT_...(...)` form. An index
+//! entry is written for the first record and then every [`INDEX_INTERVAL`] records. Index and
+//! footer are only present if the capture was finished cleanly; without them the records can
+//! still be read sequentially until the first incomplete one.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::AtomicBool},
+    thread::JoinHandle,
+};
+
+use anyhow::Context;
+use crossbeam::channel::Receiver;
+
+use crate::{
+    elf_file::FirmwareAddressMap,
+    perfetto_backend::file_writer::{FileWriterOptions, spawn_perfetto_file_writer},
+    time::EmbassyTime,
+    tracing::{
+        log_event::LogEvent,
+        log_line::{LogLevel, LogLine},
+        tracing_instance::{TracingInstance, TracingOptions},
+    },
+};
+
+pub const RMT_MAGIC: &[u8; 8] = b"RMTRACE\0";
+pub const RMT_INDEX_MAGIC: &[u8; 8] = b"RMTINDEX";
+pub const RMT_VERSION: u16 = 1;
+
+/// Number of records between two index entries
+pub const INDEX_INTERVAL: usize = 1024;
+
+const FOOTER_LEN: usize = 16;
+
+fn level_to_u8(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Trace => 0,
+        LogLevel::Debug => 1,
+        LogLevel::Info => 2,
+        LogLevel::Warn => 3,
+        LogLevel::Error => 4,
+    }
+}
+
+fn level_from_u8(level: u8) -> anyhow::Result<LogLevel> {
+    match level {
+        0 => Ok(LogLevel::Trace),
+        1 => Ok(LogLevel::Debug),
+        2 => Ok(LogLevel::Info),
+        3 => Ok(LogLevel::Warn),
+        4 => Ok(LogLevel::Error),
+        _ => Err(anyhow::anyhow!("Unknown log level {level} in rmt record")),
+    }
+}
+
+/// Streams log lines into an `.rmt` file
+pub struct RmtWriter {
+    file: BufWriter<File>,
+    offset: u64,
+    record_count: usize,
+    index: Vec<(u64, u64)>,
+}
+
+impl RmtWriter {
+    pub fn create(path: &Path, symbols: &FirmwareAddressMap) -> anyhow::Result<Self> {
+        let file = File::create(path).context("Failed to create rmt capture file")?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            offset: 0,
+            record_count: 0,
+            index: Vec::new(),
+        };
+
+        // Header
+        let mut symbols: Vec<(u64, &str)> = symbols.iter().collect();
+        symbols.sort();
+        writer.write_bytes(RMT_MAGIC)?;
+        writer.write_bytes(&RMT_VERSION.to_le_bytes())?;
+        writer.write_bytes(&(symbols.len() as u32).to_le_bytes())?;
+        for (addr, name) in symbols {
+            let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+            writer.write_bytes(&addr.to_le_bytes())?;
+            writer.write_bytes(&(name.len() as u16).to_le_bytes())?;
+            writer.write_bytes(name)?;
+        }
+
+        Ok(writer)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.file
+            .write_all(bytes)
+            .context("Failed to write to rmt capture file")?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub fn write_log_line(&mut self, log_line: &LogLine) -> anyhow::Result<()> {
+        let ts_us = log_line.timestamp.as_micros() as u64;
+        if self.record_count.is_multiple_of(INDEX_INTERVAL) {
+            self.index.push((ts_us, self.offset));
+        }
+        self.record_count += 1;
+
+        let message = log_line.message.as_bytes();
+        self.write_bytes(&ts_us.to_le_bytes())?;
+        self.write_bytes(&[level_to_u8(log_line.level)])?;
+        self.write_bytes(&(message.len() as u32).to_le_bytes())?;
+        self.write_bytes(message)
+    }
+
+    /// Write index and footer
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let index_offset = self.offset;
+        let index = std::mem::take(&mut self.index);
+        self.write_bytes(&(index.len() as u32).to_le_bytes())?;
+        for (ts_us, offset) in index {
+            self.write_bytes(&ts_us.to_le_bytes())?;
+            self.write_bytes(&offset.to_le_bytes())?;
+        }
+        self.write_bytes(&index_offset.to_le_bytes())?;
+        self.write_bytes(RMT_INDEX_MAGIC)?;
+        self.file
+            .flush()
+            .context("Failed to flush rmt capture file")
+    }
+}
+
+pub fn spawn_rmt_file_writer(
+    rmt_filename: PathBuf,
+    log_line_recver: Receiver<LogLine>,
+    symbols: FirmwareAddressMap,
+) -> JoinHandle<anyhow::Result<()>> {
+    std::thread::spawn(move || {
+        let mut writer = RmtWriter::create(&rmt_filename, &symbols)?;
+        while let Ok(log_line) = log_line_recver.recv() {
+            writer.write_log_line(&log_line)?;
+        }
+
+        // channel closed ==> capture ended
+        writer.finish()
+    })
+}
+
+/// Convert an `.rmt` capture into a Perfetto trace (`--convert`), optionally only the part from
+/// the given device time on (`--from`)
+pub fn convert_to_perfetto(
+    rmt_filename: &Path,
+    perfetto_filename: &Path,
+    from: Option<EmbassyTime>,
+) -> anyhow::Result<()> {
+    let capture = RmtCapture::open(rmt_filename)?;
+    let mut tracing_instance =
+        TracingInstance::new(capture.symbols.clone(), TracingOptions::default());
+    let perfetto_file_writer_handle = spawn_perfetto_file_writer(
+        perfetto_filename.to_path_buf(),
+        tracing_instance.get_trace_event_receiver(),
+        Arc::new(AtomicBool::new(false)),
+        FileWriterOptions::default(),
+    );
+
+    capture.replay(&mut tracing_instance, from)?;
+    tracing_instance.end_session();
+
+    drop(tracing_instance); // closes the trace event channel ==> writer finalises the file
+    perfetto_file_writer_handle
+        .join()
+        .map_err(|e| anyhow::anyhow!("Perfetto file writer thread panicked: {e:?}"))?
+        .context("Perfetto file writer thread exited with error")
+}
+
+/// Simple cursor over the raw bytes of an `.rmt` file
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// A loaded `.rmt` capture
+pub struct RmtCapture {
+    data: Vec<u8>,
+    pub symbols: FirmwareAddressMap,
+    /// Offset of the first record
+    records_start: usize,
+    /// End of the records (start of the index, or end of file without index)
+    records_end: usize,
+    /// (timestamp in µs, offset) of every [`INDEX_INTERVAL`]-th record
+    index: Vec<(u64, u64)>,
+}
+
+impl RmtCapture {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).context("Failed to read rmt capture file")?;
+        Self::from_bytes(data)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> anyhow::Result<Self> {
+        let truncated = || anyhow::anyhow!("rmt header is truncated");
+        let mut reader = ByteReader {
+            data: &data,
+            pos: 0,
+        };
+
+        // Header
+        if reader.take(RMT_MAGIC.len()) != Some(RMT_MAGIC.as_slice()) {
+            return Err(anyhow::anyhow!("Not an rmt capture file (wrong magic)"));
+        }
+        let version = reader.u16().ok_or_else(truncated)?;
+        if version != RMT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported rmt version {version} (expected {RMT_VERSION})"
+            ));
+        }
+        let symbol_count = reader.u32().ok_or_else(truncated)?;
+        let mut symbols = HashMap::new();
+        for _ in 0..symbol_count {
+            let addr = reader.u64().ok_or_else(truncated)?;
+            let name_len = reader.u16().ok_or_else(truncated)? as usize;
+            let name = reader.take(name_len).ok_or_else(truncated)?;
+            symbols.insert(addr, String::from_utf8_lossy(name).to_string());
+        }
+        let records_start = reader.pos;
+
+        // Index (optional)
+        let (records_end, index) = Self::read_index(&data, records_start).unwrap_or_default();
+        let records_end = if records_end == 0 {
+            data.len()
+        } else {
+            records_end
+        };
+
+        Ok(Self {
+            symbols: FirmwareAddressMap::from_symbols(symbols),
+            records_start,
+            records_end,
+            index,
+            data,
+        })
+    }
+
+    /// Read index and footer. Returns `None` if the capture was not finished cleanly
+    fn read_index(data: &[u8], records_start: usize) -> Option<(usize, Vec<(u64, u64)>)> {
+        let footer_start = data.len().checked_sub(FOOTER_LEN)?;
+        let mut reader = ByteReader {
+            data,
+            pos: footer_start,
+        };
+        let index_offset = reader.u64()? as usize;
+        if reader.take(RMT_INDEX_MAGIC.len())? != RMT_INDEX_MAGIC || index_offset < records_start {
+            return None;
+        }
+
+        reader.pos = index_offset;
+        let entry_count = reader.u32()?;
+        let mut index = Vec::new();
+        for _ in 0..entry_count {
+            index.push((reader.u64()?, reader.u64()?));
+        }
+        (reader.pos == footer_start).then_some((index_offset, index))
+    }
+
+    /// Feed the records into a tracing instance, like a live session would. With `from` only the
+    /// records from this device time on (found with the index instead of reading all before it)
+    pub fn replay(
+        &self,
+        tracing_instance: &mut TracingInstance,
+        from: Option<EmbassyTime>,
+    ) -> anyhow::Result<()> {
+        let records = match from {
+            Some(timestamp) => self.records_from(timestamp),
+            None => self.records(),
+        };
+        let from_us = from.map_or(0, |timestamp| timestamp.as_micros());
+        for log_line in records {
+            let log_line = log_line?;
+            if log_line.timestamp.as_micros() < from_us {
+                continue; // earlier record of the same index interval
+            }
+            match LogEvent::from_log_line(&log_line) {
+                Ok(log_event) => tracing_instance.update(&log_event),
+                Err(_) => tracing_instance.add_log_line(&log_line),
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterate over all records
+    pub fn records(&self) -> RmtRecords<'_> {
+        self.records_at(self.records_start)
+    }
+
+    /// Iterate over the records starting shortly before the given device time (uses the index,
+    /// so earlier records of the same index interval may be included)
+    pub fn records_from(&self, timestamp: EmbassyTime) -> RmtRecords<'_> {
+        let ts_us = timestamp.as_micros() as u64;
+        let offset = self
+            .index
+            .iter()
+            .take_while(|(index_ts_us, _)| *index_ts_us <= ts_us)
+            .last()
+            .map(|(_, offset)| *offset as usize)
+            .unwrap_or(self.records_start);
+        self.records_at(offset)
+    }
+
+    fn records_at(&self, offset: usize) -> RmtRecords<'_> {
+        RmtRecords {
+            reader: ByteReader {
+                data: &self.data[..self.records_end],
+                pos: offset,
+            },
+        }
+    }
+}
+
+/// Iterator over the log lines of an `.rmt` capture (stops at the first incomplete record)
+pub struct RmtRecords<'a> {
+    reader: ByteReader<'a>,
+}
+
+impl Iterator for RmtRecords<'_> {
+    type Item = anyhow::Result<LogLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ts_us = self.reader.u64()?;
+        let level = self.reader.u8()?;
+        let message_len = self.reader.u32()? as usize;
+        let message = self.reader.take(message_len)?;
+
+        Some(level_from_u8(level).and_then(|level| {
+            let message = std::str::from_utf8(message).context("Invalid UTF-8 in rmt record")?;
+            Ok(LogLine::new(
+                EmbassyTime::from_micros(ts_us),
+                level,
+                message.to_string(),
+            ))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_line(ts_us: u64, message: &str) -> LogLine {
+        LogLine::new(
+            EmbassyTime::from_micros(ts_us),
+            LogLevel::Info,
+            message.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_rmt_round_trip_and_seek() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-capture-{}.rmt", std::process::id()));
+        let symbols =
+            FirmwareAddressMap::from_symbols(HashMap::from([(0x4200_0000, "main".to_string())]));
+
+        let mut writer = RmtWriter::create(&filename, &symbols).unwrap();
+        let record_count = INDEX_INTERVAL * 3 + 5;
+        for i in 0..record_count as u64 {
+            writer
+                .write_log_line(&log_line(
+                    i * 10,
+                    &format!("@EVENT_MARKER(name=M{i},core_id=0)"),
+                ))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let capture = RmtCapture::open(&filename).unwrap();
+        assert_eq!(
+            capture.symbols.get_symbol_name(0x4200_0000).as_deref(),
+            Some("main")
+        );
+        assert_eq!(capture.index.len(), 4);
+
+        let records: Vec<LogLine> = capture.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), record_count);
+        assert_eq!(records[7], log_line(70, "@EVENT_MARKER(name=M7,core_id=0)"));
+
+        // Seek into the third index interval
+        let ts_us = (INDEX_INTERVAL as u64 * 2 + 100) * 10;
+        let first = capture
+            .records_from(EmbassyTime::from_micros(ts_us))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.timestamp.as_micros(), INDEX_INTERVAL as u128 * 2 * 10);
+
+        // A capture without index (e.g. killed writer) is still readable up to the cut:
+        // drop index, footer and the tail of the last record
+        let data = std::fs::read(&filename).unwrap();
+        let cut = 4 + capture.index.len() * 16 + FOOTER_LEN + 5;
+        let capture = RmtCapture::from_bytes(data[..data.len() - cut].to_vec()).unwrap();
+        assert!(capture.index.is_empty());
+        assert_eq!(capture.records().count(), record_count - 1);
+
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_convert_from_timestamp() {
+        let dir = std::env::temp_dir().join(format!("rustmeter-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rmt_filename = dir.join("capture.rmt");
+        let perfetto_filename = dir.join("capture.json");
+
+        let mut writer = RmtWriter::create(&rmt_filename, &FirmwareAddressMap::default()).unwrap();
+        for i in 0..INDEX_INTERVAL as u64 * 3 {
+            writer
+                .write_log_line(&log_line(
+                    i * 10,
+                    &format!("@EVENT_MARKER(name=M{i},core_id=0)"),
+                ))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Starts within an index interval ==> its earlier records are skipped
+        let from_us = (INDEX_INTERVAL as u64 * 2 + 100) * 10;
+        convert_to_perfetto(
+            &rmt_filename,
+            &perfetto_filename,
+            Some(EmbassyTime::from_micros(from_us)),
+        )
+        .unwrap();
+
+        let content = std::fs::read_to_string(&perfetto_filename).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let marker_ts: Vec<u64> = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["cat"] == "marker")
+            .map(|event| event["ts"].as_u64().unwrap())
+            .collect();
+        assert_eq!(marker_ts.len(), INDEX_INTERVAL - 100);
+        assert_eq!(marker_ts[0], from_us);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::test_runner::Config::with_cases(64))]
+
+        #[test]
+        fn test_rmt_round_trip_random_records(
+            records in proptest::collection::vec((proptest::num::u64::ANY, 0u8..5, "\\PC{0,40}"), 0..50),
+        ) {
+            let filename = std::env::temp_dir()
+                .join(format!("rustmeter-proptest-{}.rmt", std::process::id()));
+            let log_lines: Vec<LogLine> = records
+                .into_iter()
+                .map(|(ts_us, level, message)| {
+                    LogLine::new(
+                        EmbassyTime::from_micros(ts_us),
+                        level_from_u8(level).unwrap(),
+                        message,
+                    )
+                })
+                .collect();
+
+            let mut writer = RmtWriter::create(&filename, &FirmwareAddressMap::default()).unwrap();
+            for log_line in &log_lines {
+                writer.write_log_line(log_line).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let capture = RmtCapture::open(&filename).unwrap();
+            let read_back: Vec<LogLine> = capture.records().map(Result::unwrap).collect();
+            std::fs::remove_file(&filename).unwrap();
+            proptest::prop_assert_eq!(read_back, log_lines);
+        }
+
+        #[test]
+        fn test_rmt_random_bytes_never_panic(
+            body in proptest::collection::vec(proptest::num::u8::ANY, 0..512),
+            with_header in proptest::bool::ANY,
+        ) {
+            // With a valid header the random bytes reach the record and index decoding
+            let mut data = Vec::new();
+            if with_header {
+                data.extend_from_slice(RMT_MAGIC);
+                data.extend_from_slice(&RMT_VERSION.to_le_bytes());
+            }
+            data.extend(body);
+
+            if let Ok(capture) = RmtCapture::from_bytes(data) {
+                let _ = capture.records().count();
+                let _ = capture.records_from(EmbassyTime::from_micros(u64::MAX)).count();
+            }
+        }
+    }
+}
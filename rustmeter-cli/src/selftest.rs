@@ -0,0 +1,172 @@
+use std::{
+    path::Path,
+    sync::{Arc, atomic::AtomicBool},
+};
+
+use anyhow::Context;
+
+use crate::{
+    elf_file::FirmwareAddressMap,
+    perfetto_backend::file_writer::{FileWriterOptions, spawn_perfetto_file_writer},
+    tracing::{
+        log_event::LogEvent,
+        log_line::LogLine,
+        tracing_instance::{TracingInstance, TracingOptions},
+    },
+};
+
+/// Synthetic defmt output of a tiny firmware: one executor with two tasks, nested monitors,
+/// metrics, a marker, a plain log line and a final flush
+const SYNTHETIC_LOG: &[&str] = &[
+    "0.000100 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+    "0.000110 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+    "0.000200 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+    "0.000210 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+    "0.000300 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+    "0.000310 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+    "0.000400 [INFO ] @EVENT_MONITOR_START(function_name=outer,core_id=0)",
+    "0.000450 [INFO ] @EVENT_MONITOR_START(function_name=inner,location=src/main.rs:42,core_id=0)",
+    "0.000500 [INFO ] @EVENT_METRIC(name=temperature,value=21.5,core_id=0)",
+    "0.000550 [INFO ] @EVENT_MONITOR_END(function_name=inner,core_id=0)",
+    "0.000600 [INFO ] @EVENT_MONITOR_END(function_name=outer,core_id=0)",
+    "0.000650 [INFO ] sensor initialised value=42",
+    "0.000700 [TRACE] @EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+    "0.000710 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=11)",
+    "0.000800 [INFO ] @EVENT_MARKER(name=SelfTest,core_id=0)",
+    "0.000850 [INFO ] @EVENT_METRIC(name=temperature,value=22,core_id=0)",
+    "0.000900 [TRACE] @EVENT_EMBASSY_TASK_END(executor_id=1,core_id=0,task_id=11)",
+    "0.000910 [TRACE] @EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=11)",
+    "0.001000 [TRACE] @EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)",
+    "0.001100 [INFO ] @EVENT_METRIC(name=temperature,value=-3,core_id=0)",
+    "0.001200 [INFO ] @EVENT_TRACE_FLUSH(core_id=0)",
+];
+
+/// A single check of the self test: description, expected and actual value
+struct SelftestCheck {
+    description: &'static str,
+    expected: usize,
+    actual: usize,
+}
+
+/// Run the synthetic log through parser, tracing instance and file writer (`--selftest`).
+/// No hardware or firmware is needed
+pub fn run_selftest(perfetto_filename: &Path) -> anyhow::Result<()> {
+    let checks = run_pipeline(perfetto_filename)?;
+
+    let mut passed = true;
+    for check in &checks {
+        let ok = check.expected == check.actual;
+        passed &= ok;
+        println!(
+            "[{}] {}: expected {}, got {}",
+            if ok { "ok" } else { "FAILED" },
+            check.description,
+            check.expected,
+            check.actual
+        );
+    }
+
+    println!("Trace written to {}", perfetto_filename.display());
+    if passed {
+        println!("Selftest: PASS");
+        Ok(())
+    } else {
+        println!("Selftest: FAIL");
+        Err(anyhow::anyhow!("Selftest failed"))
+    }
+}
+
+/// Feed the synthetic log and compare the written trace against the expected events
+fn run_pipeline(perfetto_filename: &Path) -> anyhow::Result<Vec<SelftestCheck>> {
+    let mut tracing_instance =
+        TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+    let perfetto_file_writer_handle = spawn_perfetto_file_writer(
+        perfetto_filename.to_path_buf(),
+        tracing_instance.get_trace_event_receiver(),
+        Arc::new(AtomicBool::new(false)),
+        FileWriterOptions::default(),
+    );
+
+    let mut parsed_events = 0;
+    for line in SYNTHETIC_LOG {
+        let log_line = LogLine::from_str(line)
+            .with_context(|| format!("Failed to parse synthetic log line: {line}"))?;
+        match LogEvent::from_log_line(&log_line) {
+            Ok(log_event) => {
+                parsed_events += 1;
+                tracing_instance.update(&log_event);
+            }
+            Err(_) => tracing_instance.add_log_line(&log_line),
+        }
+    }
+    let flushed = tracing_instance.end_session();
+
+    drop(tracing_instance); // closes the trace event channel ==> writer finalises the file
+    perfetto_file_writer_handle
+        .join()
+        .map_err(|e| anyhow::anyhow!("Perfetto file writer thread panicked: {e:?}"))?
+        .context("Perfetto file writer thread exited with error")?;
+
+    // Read back the trace
+    let content = std::fs::read_to_string(perfetto_filename)
+        .context("Failed to read back the selftest trace")?;
+    let json: serde_json::Value =
+        serde_json::from_str(&content).context("Selftest trace is not valid JSON")?;
+    let events = json["traceEvents"]
+        .as_array()
+        .ok_or(anyhow::anyhow!("Selftest trace has no traceEvents"))?;
+    let count = |predicate: &dyn Fn(&serde_json::Value) -> bool| {
+        events.iter().filter(|event| predicate(event)).count()
+    };
+
+    Ok(vec![
+        SelftestCheck {
+            description: "parsed tracing events",
+            expected: SYNTHETIC_LOG.len() - 1,
+            actual: parsed_events,
+        },
+        SelftestCheck {
+            description: "clean trace end",
+            expected: 1,
+            actual: flushed as usize,
+        },
+        SelftestCheck {
+            description: "monitor slices",
+            expected: 2,
+            actual: count(&|e| e["ph"] == "B" && e["cat"] == "function_monitor"),
+        },
+        SelftestCheck {
+            description: "task executions",
+            expected: 2,
+            actual: count(&|e| e["ph"] == "B" && e["pid"] == 1 && e["name"] == "Running"),
+        },
+        SelftestCheck {
+            description: "metric values",
+            expected: 3,
+            actual: count(&|e| e["ph"] == "C" && e["name"] == "temperature"),
+        },
+        SelftestCheck {
+            description: "markers",
+            expected: 1,
+            actual: count(&|e| e["ph"] == "i" && e["cat"] == "marker"),
+        },
+        SelftestCheck {
+            description: "ended tasks",
+            expected: 1,
+            actual: count(&|e| e["ph"] == "B" && e["name"] == "Ended"),
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-selftest-{}.json", std::process::id()));
+        run_selftest(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+    }
+}
@@ -6,10 +6,26 @@ use serde::Serialize;
 pub struct EmbassyTime(Duration);
 
 impl EmbassyTime {
+    #[cfg(test)]
     pub fn from_secs_f64(secs: f64) -> Self {
         Self(Duration::from_secs_f64(secs))
     }
 
+    /// Time from seconds. `None` for negative, non-finite or too large values (e.g. a garbled
+    /// timestamp of a log line)
+    pub fn try_from_secs_f64(secs: f64) -> Option<Self> {
+        Duration::try_from_secs_f64(secs).ok().map(Self)
+    }
+
+    pub fn from_micros(micros: u64) -> Self {
+        Self(Duration::from_micros(micros))
+    }
+
+    /// This time shifted by the given number of microseconds
+    pub fn add_micros(&self, micros: u64) -> Self {
+        Self(self.0 + Duration::from_micros(micros))
+    }
+
     pub fn as_secs_f64(&self) -> f64 {
         self.0.as_secs_f64()
     }
@@ -18,3 +34,83 @@ impl EmbassyTime {
         self.0.as_micros()
     }
 }
+
+/// Converts CPU cycle timestamps (`cycle_timestamp!` of rustmeter-beacon, 32-bit counter) into
+/// time. Wraps of the counter are unwrapped as long as at least one log line arrives per half wrap
+/// period (2^31 / cpu_hz seconds). Shorter steps back are lines that arrived out of order (e.g.
+/// from the other core) and do not count as a wrap
+#[derive(Debug, Clone)]
+pub struct CycleClock {
+    cpu_hz: u64,
+    /// Latest counter value seen (out-of-order lines do not move it back)
+    last_cycles: Option<u32>,
+    wraps: u64,
+}
+
+impl CycleClock {
+    pub fn new(cpu_hz: u64) -> Self {
+        Self {
+            cpu_hz: cpu_hz.max(1),
+            last_cycles: None,
+            wraps: 0,
+        }
+    }
+
+    pub fn convert(&mut self, cycles: u32) -> EmbassyTime {
+        const HALF_RANGE: u32 = 1 << 31;
+
+        let mut wraps = self.wraps;
+        match self.last_cycles {
+            // Far back: the counter wrapped
+            Some(last_cycles) if cycles < last_cycles && last_cycles - cycles > HALF_RANGE => {
+                self.wraps += 1;
+                wraps = self.wraps;
+                self.last_cycles = Some(cycles);
+            }
+            // Slightly back: an older line that arrived late
+            Some(last_cycles) if cycles < last_cycles => {}
+            // Far ahead: an older line from before the latest wrap
+            Some(last_cycles) if cycles - last_cycles > HALF_RANGE && wraps > 0 => {
+                wraps -= 1;
+            }
+            _ => self.last_cycles = Some(cycles),
+        }
+
+        let total_cycles = ((wraps as u128) << 32) | cycles as u128;
+        let nanos = total_cycles * 1_000_000_000 / self.cpu_hz as u128;
+        EmbassyTime(Duration::from_nanos(nanos as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_clock() {
+        let mut clock = CycleClock::new(480_000_000);
+        assert_eq!(clock.convert(480).as_micros(), 1);
+        assert_eq!(clock.convert(480_000_000).as_micros(), 1_000_000);
+        assert_eq!(clock.convert(3_840_000_000).as_micros(), 8_000_000);
+
+        // Counter wrapped: 2^32 cycles at 480 MHz are ~8.95 s
+        assert_eq!(clock.convert(48).as_micros(), 8_947_848);
+        assert_eq!(clock.convert(4800).as_micros(), 8_947_858);
+    }
+
+    #[test]
+    fn test_cycle_clock_reordered_lines() {
+        let mut clock = CycleClock::new(480_000_000);
+        assert_eq!(clock.convert(480_000).as_micros(), 1_000);
+
+        // A slightly older line is no wrap, and does not move the clock back either
+        assert_eq!(clock.convert(240_000).as_micros(), 500);
+        assert_eq!(clock.convert(960_000).as_micros(), 2_000);
+
+        // Wrap, then a late line from just before it
+        assert_eq!(clock.convert(u32::MAX - 480).as_micros(), 8_947_847);
+        assert_eq!(clock.convert(48).as_micros(), 8_947_848);
+        assert_eq!(clock.convert(u32::MAX - 4800).as_micros(), 8_947_838);
+        assert_eq!(clock.convert(4800).as_micros(), 8_947_858);
+    }
+}
@@ -1,23 +1,59 @@
 use std::collections::HashMap;
 
-use crossbeam::channel::Sender;
-
 use crate::{
     elf_file::FirmwareAddressMap,
-    perfetto_backend::trace_event::TracingEvent,
+    perfetto_backend::{
+        event_sender::TraceEventSender,
+        trace_event::{CName, InstantScope, TracingArgsMap, TracingEvent},
+    },
+    time::EmbassyTime,
     tracing::{
-        executor::ExecutorTracing,
+        duration_histogram::DurationHistogram,
+        executor::{ExecutorTracing, merged_idle_args},
         log_event::{LogEvent, LogEventType},
+        task::{MonitorAdmission, MonitorDepthGuard, TaskTraceOptions, yield_reason_label},
+        tracing_instance::{MonitorInterval, TracingOptions, UsageStats},
     },
 };
 
+/// Nesting depth of open monitors per task after which the oldest are force-closed
+pub const DEFAULT_MAX_MONITOR_DEPTH: usize = 64;
+
 /// This container represents a single core of the controller we are tracing from. It can hold up many executors or synchronous tasks (e.g. interrupts or main loop)
 pub struct CoreTracing {
     firmware_addr_map: FirmwareAddressMap,
-    trace_event_sender: Sender<TracingEvent>,
+    trace_event_sender: TraceEventSender,
+    options: TracingOptions,
 
     core_id: u8,
     executors: HashMap<u32, ExecutorTracing>,
+
+    /// Currently open monitors on this core (duration is filled in when they end)
+    monitor_stack: Vec<MonitorInterval>,
+    /// Nesting limit of the monitors started outside of embassy tasks (tasks have their own)
+    monitor_guard: MonitorDepthGuard,
+    /// Duration statistics per monitor name
+    monitor_durations: HashMap<String, DurationHistogram>,
+    /// Total (call count, duration in µs) per coalesced monitor name
+    monitor_aggregates: HashMap<String, (u64, u64)>,
+    /// Completed monitors (only collected on request, otherwise `None`)
+    completed_monitors: Option<Vec<MonitorInterval>>,
+    /// Monitors starting before this timestamp are not recorded in the statistics (warmup)
+    stats_from_us: u128,
+
+    /// Executor that went idle and since when, while the idle period is not known to be longer
+    /// than the merge threshold
+    pending_idle: Option<(u32, EmbassyTime)>,
+    /// Idle time (in µs) merged into the open executor slice of the core overview
+    merged_idle_us: u128,
+
+    /// Start of the current period in which any executor runs
+    busy_since: Option<EmbassyTime>,
+    /// Time (in µs) of the completed periods in which any executor ran
+    busy_us: u128,
+
+    /// Running time and poll count of the tasks of evicted (ended) executors
+    ended_executor_usage: UsageStats,
 }
 
 impl CoreTracing {
@@ -25,7 +61,9 @@ impl CoreTracing {
     pub fn new(
         core_id: u8,
         firmware_addr_map: FirmwareAddressMap,
-        trace_event_sender: Sender<TracingEvent>,
+        trace_event_sender: TraceEventSender,
+        options: TracingOptions,
+        stats_from: EmbassyTime,
     ) -> Self {
         // Send core metadata
         let _ = trace_event_sender.send(TracingEvent::Metadata {
@@ -44,6 +82,22 @@ impl CoreTracing {
             firmware_addr_map,
             trace_event_sender,
             executors: HashMap::new(),
+            monitor_stack: Vec::new(),
+            monitor_guard: MonitorDepthGuard::new(
+                options
+                    .max_monitor_depth
+                    .unwrap_or(DEFAULT_MAX_MONITOR_DEPTH),
+            ),
+            monitor_durations: HashMap::new(),
+            monitor_aggregates: HashMap::new(),
+            completed_monitors: options.collect_monitors.then(Vec::new),
+            stats_from_us: stats_from.as_micros(),
+            pending_idle: None,
+            merged_idle_us: 0,
+            busy_since: None,
+            busy_us: 0,
+            ended_executor_usage: UsageStats::default(),
+            options,
         }
     }
 
@@ -51,6 +105,468 @@ impl CoreTracing {
         self.core_id
     }
 
+    /// (executor id, task id) of the task currently running on this core
+    pub fn get_running_task(&self) -> Option<(u32, u32)> {
+        self.executors.values().find_map(|exe| {
+            exe.get_currently_running_task()
+                .map(|task| (exe.get_executor_id(), task.get_task_id()))
+        })
+    }
+
+    /// Process of a metric's counter tracks: its panel process if it has one, otherwise the
+    /// executor of the currently running task (or global without one)
+    pub fn get_metric_pid(&self, name: &str) -> Option<u32> {
+        self.options.metric_panels.get_pid(name).or_else(|| {
+            self.executors
+                .values()
+                .find_map(|exe| exe.get_currently_running_task())
+                .map(|task| task.get_pid())
+        })
+    }
+
+    /// Check if a task of one of this core's executors is tracked and has not ended yet
+    pub fn has_live_task(&self, executor_id: u32, task_id: u32) -> bool {
+        self.executors
+            .get(&executor_id)
+            .is_some_and(|exe| exe.has_live_task(task_id))
+    }
+
+    /// Check if a monitor with this name is open on this core (a start refused by the nesting
+    /// limit counts as well, its end is expected here too)
+    pub fn has_open_monitor(&self, function_name: &str) -> bool {
+        self.monitor_stack
+            .iter()
+            .any(|monitor| monitor.name == function_name)
+            || self.monitor_guard.expects_rejected_end(function_name)
+            || self
+                .executors
+                .values()
+                .any(|exe| exe.expects_rejected_monitor_end(function_name))
+    }
+
+    /// Duration statistics per monitor name on this core
+    pub fn get_monitor_durations(&self) -> &HashMap<String, DurationHistogram> {
+        &self.monitor_durations
+    }
+
+    /// Total (call count, duration in µs) per coalesced monitor name on this core
+    pub fn get_monitor_aggregates(&self) -> &HashMap<String, (u64, u64)> {
+        &self.monitor_aggregates
+    }
+
+    /// Number of tasks currently tracked on this core
+    #[cfg(test)]
+    pub fn get_tracked_task_count(&self) -> usize {
+        self.executors
+            .values()
+            .map(|exe| exe.get_tracked_task_count())
+            .sum()
+    }
+
+    /// Number of executors currently tracked on this core
+    #[cfg(test)]
+    pub fn get_tracked_executor_count(&self) -> usize {
+        self.executors.len()
+    }
+
+    /// Take all completed monitors collected so far
+    #[cfg(test)]
+    pub fn drain_completed_monitors(&mut self) -> Vec<MonitorInterval> {
+        self.completed_monitors
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Number of currently open monitors on this core
+    #[cfg(test)]
+    pub fn get_monitor_depth(&self) -> usize {
+        self.monitor_stack.len()
+    }
+
+    /// Check if the task (or the code outside of tasks with `None`) refuses monitor starts
+    #[cfg(test)]
+    pub fn is_rejecting_monitors(&mut self, task: Option<(u32, u32)>) -> bool {
+        self.get_monitor_guard_mut(task).is_draining()
+    }
+
+    /// Executor and task a monitor event belongs to: the task of the reported executor if known,
+    /// otherwise whichever one is polling
+    fn get_monitor_task(&self, executor_id: Option<u32>) -> Option<(u32, u32)> {
+        match executor_id {
+            Some(executor_id) => self.executors.get(&executor_id).and_then(|exe| {
+                // The executor may already be preempted if events got reordered
+                exe.get_currently_running_task()
+                    .map(|task| task.get_task_id())
+                    .or_else(|| exe.get_preemption().and_then(|(_, task_id, _)| task_id))
+                    .map(|task_id| (executor_id, task_id))
+            }),
+            None => self.get_running_task(),
+        }
+    }
+
+    /// Nesting limit of the monitors of the task (or of the code outside of tasks with `None`)
+    fn get_monitor_guard_mut(&mut self, task: Option<(u32, u32)>) -> &mut MonitorDepthGuard {
+        let task = task.and_then(|(executor_id, task_id)| {
+            self.executors
+                .get_mut(&executor_id)
+                .and_then(|exe| exe.get_task_mut(task_id))
+        });
+        match task {
+            Some(task) => task.get_monitor_guard_mut(),
+            None => &mut self.monitor_guard,
+        }
+    }
+
+    /// Check if the monitor at this stack position is a recursive call collapsed into the slice of
+    /// its caller (`collapse_recursion`), i.e. the task's previous open monitor has the same name.
+    /// Also holds after the monitor was removed from the stack
+    fn is_collapsed_call(&self, index: usize, function_name: &str, task_id: Option<u32>) -> bool {
+        self.options.collapse_recursion
+            && self.monitor_stack[..index]
+                .iter()
+                .rev()
+                .find(|monitor| monitor.task_id == task_id)
+                .is_some_and(|caller| caller.name == function_name)
+    }
+
+    /// Track the recursion depth of a monitor that starts while the same monitor is the task's
+    /// innermost one. The depth is kept on the outermost call, which carries the slice
+    fn enter_recursion(&mut self, function_name: &str, task_id: Option<u32>) {
+        let recursion: Vec<usize> = (0..self.monitor_stack.len())
+            .rev()
+            .filter(|index| self.monitor_stack[*index].task_id == task_id)
+            .take_while(|index| self.monitor_stack[*index].name == function_name)
+            .collect();
+        let Some(&outermost) = recursion.last() else {
+            return;
+        };
+        let outermost = &mut self.monitor_stack[outermost];
+        let depth = recursion.len() as u32 + 1;
+        outermost.recursion_depth = Some(outermost.recursion_depth.unwrap_or(1).max(depth));
+    }
+
+    /// Close the innermost open monitor with this name and record its duration. Returns the args
+    /// of its end event, or `None` if no monitor with this name is open or its slice was collapsed
+    /// into its caller's
+    fn close_monitor(
+        &mut self,
+        function_name: &str,
+        timestamp: EmbassyTime,
+    ) -> Option<TracingArgsMap<String>> {
+        let index = self
+            .monitor_stack
+            .iter()
+            .rposition(|monitor| monitor.name == function_name)?;
+
+        let mut monitor = self.monitor_stack.remove(index);
+        monitor.duration_us = timestamp
+            .as_micros()
+            .saturating_sub(monitor.start.as_micros());
+
+        let collapsed = self.is_collapsed_call(index, function_name, monitor.task_id);
+        let mut args = HashMap::new();
+        if let Some(depth) = monitor.recursion_depth {
+            args.insert("recursion_depth".to_string(), depth.to_string());
+        }
+
+        if monitor.start.as_micros() >= self.stats_from_us {
+            self.monitor_durations
+                .entry(monitor.name.clone())
+                .or_default()
+                .record(monitor.duration_us);
+
+            if let Some(completed_monitors) = &mut self.completed_monitors {
+                completed_monitors.push(monitor);
+            }
+        }
+        (!collapsed).then_some(args)
+    }
+
+    /// The task reached its monitor nesting limit (see [`MonitorDepthGuard`]), most likely
+    /// because the firmware lost monitor ends under load. Close its `count` oldest open monitors
+    /// without recording their durations. Their late ends find no open monitor anymore and are
+    /// dropped.
+    ///
+    /// An end always closes the innermost slice of a track, so the slices above the oldest closed
+    /// monitor are ended as well and the ones that stay open are begun again right away
+    fn force_close_oldest_monitors(
+        &mut self,
+        task_id: Option<u32>,
+        count: usize,
+        timestamp: EmbassyTime,
+    ) {
+        let closed: Vec<usize> = self
+            .monitor_stack
+            .iter()
+            .enumerate()
+            .filter(|(_, monitor)| monitor.task_id == task_id)
+            .map(|(index, _)| index)
+            .take(count)
+            .collect();
+        let Some(&first_closed) = closed.first() else {
+            return;
+        };
+
+        let max_depth = self
+            .options
+            .max_monitor_depth
+            .unwrap_or(DEFAULT_MAX_MONITOR_DEPTH);
+        let warning = format!("force-closed: {max_depth} nested monitors open in this task");
+        let ts = timestamp.as_micros();
+        let is_traced = |core: &Self, index: usize| {
+            let monitor = &core.monitor_stack[index];
+            core.options.monitor_filter.matches(&monitor.name)
+                && !core.is_collapsed_call(index, &monitor.name, monitor.task_id)
+        };
+
+        for index in (first_closed..self.monitor_stack.len()).rev() {
+            if !is_traced(self, index) {
+                continue;
+            }
+            let monitor = &self.monitor_stack[index];
+            let args = if closed.contains(&index) {
+                HashMap::from([("warning".to_string(), warning.clone())])
+            } else {
+                HashMap::new()
+            };
+            let _ = self.trace_event_sender.send(TracingEvent::End {
+                name: Some(monitor.name.clone()),
+                cat: Some(monitor_category(&monitor.category)),
+                pid: 0,
+                tid: Some(self.core_id as u32),
+                ts,
+                args,
+            });
+        }
+
+        // innermost first, so the remaining indices stay valid
+        for &index in closed.iter().rev() {
+            self.monitor_stack.remove(index);
+        }
+
+        for index in first_closed..self.monitor_stack.len() {
+            if !is_traced(self, index) {
+                continue;
+            }
+            let monitor = &self.monitor_stack[index];
+            let category = monitor_category(&monitor.category);
+            let _ = self.trace_event_sender.send(TracingEvent::Begin {
+                name: monitor.name.clone(),
+                cname: self.options.monitor_colors.get(&monitor.name, &category),
+                cat: Some(category),
+                ts,
+                pid: 0,
+                tid: Some(self.core_id as u32),
+                args: HashMap::from([(
+                    "begin_ts_us".to_string(),
+                    monitor.start.as_micros().to_string(),
+                )]),
+            });
+        }
+
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Monitor stack overflow".to_string(),
+            cat: Some("function_monitor".to_string()),
+            ts,
+            pid: Some(0),
+            tid: Some(self.core_id as u32),
+            scope: InstantScope::Thread,
+            args: HashMap::from([("warning".to_string(), warning)]),
+            cname: CName::Terrible,
+        });
+    }
+
+    /// Close all open monitors (innermost first) without recording their durations
+    fn close_open_monitors(&mut self, timestamp: EmbassyTime, args: TracingArgsMap<String>) {
+        while let Some(monitor) = self.monitor_stack.pop() {
+            if !self.options.monitor_filter.matches(&monitor.name)
+                || self.is_collapsed_call(self.monitor_stack.len(), &monitor.name, monitor.task_id)
+            {
+                continue;
+            }
+            let _ = self.trace_event_sender.send(TracingEvent::End {
+                name: Some(monitor.name),
+                cat: None,
+                pid: 0,
+                tid: Some(self.core_id as u32),
+                ts: timestamp.as_micros(),
+                args: args.clone(),
+            });
+        }
+    }
+
+    /// Begin the slice of a running executor on the core overview
+    fn send_executor_begin(&self, executor_id: u32, timestamp: EmbassyTime) {
+        let _ = self.trace_event_sender.send(TracingEvent::Begin {
+            name: self.executors[&executor_id].get_name().to_string(),
+            cat: Some("executor".to_string()),
+            pid: 0,
+            tid: Some(self.core_id as u32),
+            ts: timestamp.as_micros(),
+            args: HashMap::new(),
+            cname: None,
+        });
+    }
+
+    /// End the slice of a running executor on the core overview
+    fn send_executor_end(&mut self, executor_id: u32, timestamp: EmbassyTime) {
+        let _ = self.trace_event_sender.send(TracingEvent::End {
+            name: Some(self.executors[&executor_id].get_name().to_string()),
+            cat: Some("executor".to_string()),
+            pid: 0,
+            tid: Some(self.core_id as u32),
+            ts: timestamp.as_micros(),
+            args: merged_idle_args(std::mem::take(&mut self.merged_idle_us)),
+        });
+    }
+
+    /// Decide about a held back idle period once an executor runs again. Returns true if it was
+    /// shorter than the merge threshold, so the executor slice before it simply continues
+    fn resume_after_short_idle(&mut self, executor_id: u32, timestamp: EmbassyTime) -> bool {
+        let Some((idle_executor_id, idle_since)) = self.pending_idle.take() else {
+            return false;
+        };
+
+        let idle_us = timestamp.as_micros().saturating_sub(idle_since.as_micros());
+        if idle_executor_id == executor_id
+            && self
+                .options
+                .idle_merge_threshold_us
+                .is_some_and(|threshold_us| idle_us < threshold_us as u128)
+        {
+            self.merged_idle_us += idle_us;
+            true
+        } else {
+            self.send_executor_end(idle_executor_id, idle_since);
+            false
+        }
+    }
+
+    /// Add the busy time of this core and the running time of its tasks up to the given timestamp
+    pub fn add_usage(&self, usage: &mut UsageStats, until: EmbassyTime) {
+        let open_busy_us = self.busy_since.map_or(0, |busy_since| {
+            until.as_micros().saturating_sub(busy_since.as_micros())
+        });
+        *usage.core_busy_us.entry(self.core_id).or_insert(0) += self.busy_us + open_busy_us;
+
+        for executor in self.executors.values() {
+            executor.add_task_usage(usage, until);
+        }
+        for (key, running_us) in &self.ended_executor_usage.task_running_us {
+            *usage.task_running_us.entry(key.clone()).or_insert(0) += running_us;
+        }
+        for (key, poll_count) in &self.ended_executor_usage.task_polls {
+            *usage.task_polls.entry(key.clone()).or_insert(0) += poll_count;
+        }
+    }
+
+    /// Evict the executors whose tasks have all ended and that stayed idle since (their
+    /// statistics are kept), so executors with short-lived tasks do not pile up
+    fn evict_ended_executors(&mut self, timestamp: EmbassyTime) {
+        let pending_idle_executor_id = self.pending_idle.map(|(executor_id, _)| executor_id);
+        let ended_executor_ids: Vec<u32> = self
+            .executors
+            .values()
+            .filter(|exe| {
+                exe.has_ended(timestamp) && Some(exe.get_executor_id()) != pending_idle_executor_id
+            })
+            .map(|exe| exe.get_executor_id())
+            .collect();
+
+        for executor_id in ended_executor_ids {
+            if let Some(mut executor) = self.executors.remove(&executor_id) {
+                executor.finish(timestamp);
+                executor.add_task_usage(&mut self.ended_executor_usage, timestamp);
+            }
+        }
+    }
+
+    /// Send the idle periods that are still held back (e.g. at the end of the session)
+    pub fn flush_pending_idle(&mut self) {
+        if let Some((idle_executor_id, idle_since)) = self.pending_idle.take() {
+            self.send_executor_end(idle_executor_id, idle_since);
+        }
+        for executor in self.executors.values_mut() {
+            executor.flush_pending_idle();
+        }
+    }
+
+    /// Close every open slice of this core: monitors, the running executor on the core overview
+    /// and all executors and tasks (the core is no longer tracked afterwards, e.g. after a
+    /// target reset)
+    pub fn finish(&mut self, timestamp: EmbassyTime) {
+        self.close_open_monitors(timestamp, HashMap::new());
+        self.flush_pending_idle();
+
+        if self
+            .executors
+            .values()
+            .any(|exe| exe.is_currently_running())
+        {
+            let _ = self.trace_event_sender.send(TracingEvent::End {
+                name: None,
+                cat: Some("executor".to_string()),
+                pid: 0,
+                tid: Some(self.core_id as u32),
+                ts: timestamp.as_micros(),
+                args: HashMap::new(),
+            });
+        }
+
+        for executor in self.executors.values_mut() {
+            executor.finish(timestamp);
+        }
+    }
+
+    /// Heuristic: a task that got preempted by a higher priority executor for longer than the
+    /// threshold while inside a monitored scope might hold a resource the preempting side is
+    /// waiting for. Flag it on the task's track
+    fn check_priority_inversion(
+        &self,
+        executor_id: u32,
+        by_executor_id: u32,
+        task_id: u32,
+        since: EmbassyTime,
+        timestamp: EmbassyTime,
+        threshold_us: u64,
+    ) {
+        let preempted_us = timestamp.as_micros().saturating_sub(since.as_micros());
+        if preempted_us < threshold_us as u128 {
+            return;
+        }
+
+        let Some(monitor) = self
+            .monitor_stack
+            .iter()
+            .rev()
+            .find(|monitor| monitor.task_id == Some(task_id))
+        else {
+            return;
+        };
+
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Potential priority inversion (heuristic)".to_string(),
+            cat: Some("priority_inversion".to_string()),
+            ts: timestamp.as_micros(),
+            pid: Some(executor_id),
+            tid: Some(task_id),
+            scope: InstantScope::Thread,
+            args: HashMap::from([
+                ("monitor".to_string(), monitor.name.clone()),
+                ("preempted_us".to_string(), preempted_us.to_string()),
+                (
+                    "preempted_by".to_string(),
+                    self.executors
+                        .get(&by_executor_id)
+                        .map(|exe| exe.get_name().to_string())
+                        .unwrap_or_else(|| format!("Executor 0x{by_executor_id:X}")),
+                ),
+            ]),
+            cname: CName::Terrible,
+        });
+    }
+
     pub fn update(&mut self, log_event: &LogEvent) {
         if let Some(executor_id) = log_event.event_type.get_executor_id() {
             // Check if we have an executor with this ID on this core
@@ -69,6 +585,15 @@ impl CoreTracing {
                                 log_event.timestamp,
                                 self.firmware_addr_map.clone(),
                                 self.trace_event_sender.clone(),
+                                TaskTraceOptions {
+                                    view: self.options.task_view,
+                                    filter: self.options.task_filter.clone(),
+                                    max_monitor_depth: self
+                                        .options
+                                        .max_monitor_depth
+                                        .unwrap_or(DEFAULT_MAX_MONITOR_DEPTH),
+                                },
+                                self.options.idle_merge_threshold_us,
                             ),
                         );
                     }
@@ -76,6 +601,15 @@ impl CoreTracing {
             }
         }
 
+        let preempted_executors: Vec<(u32, u32, Option<u32>, EmbassyTime)> = self
+            .executors
+            .values()
+            .filter_map(|exe| {
+                exe.get_preemption()
+                    .map(|(by, task_id, since)| (exe.get_executor_id(), by, task_id, since))
+            })
+            .collect();
+
         let previously_running_executor = self
             .executors
             .values()
@@ -93,112 +627,298 @@ impl CoreTracing {
             .find(|exe| exe.is_currently_running())
             .map(|exe| exe.get_executor_id());
 
+        // Check for long preemptions of tasks inside a monitored scope
+        if let Some(threshold_us) = self.options.inversion_threshold_us {
+            for (executor_id, by_executor_id, task_id, since) in preempted_executors {
+                let resumed = self
+                    .executors
+                    .get(&executor_id)
+                    .is_some_and(|exe| exe.get_preemption().is_none());
+                if resumed && let Some(task_id) = task_id {
+                    self.check_priority_inversion(
+                        executor_id,
+                        by_executor_id,
+                        task_id,
+                        since,
+                        log_event.timestamp,
+                        threshold_us,
+                    );
+                }
+            }
+        }
+
+        // Busy time of the core (independent of merged idle periods in the trace)
+        let timestamp = log_event.timestamp;
+        match (previously_running_executor, currently_running_executor) {
+            (None, Some(_)) => self.busy_since = Some(timestamp),
+            (Some(_), None) => {
+                if let Some(busy_since) = self.busy_since.take() {
+                    self.busy_us += timestamp.as_micros().saturating_sub(busy_since.as_micros());
+                }
+            }
+            _ => {}
+        }
+
         // Check for executor switches
         match (previously_running_executor, currently_running_executor) {
             (None, Some(exe_id)) => {
-                // Executor started running
-                let _ = self.trace_event_sender.send(TracingEvent::Begin {
-                    name: self.executors.get(&exe_id).unwrap().get_name().to_string(),
-                    cat: Some("executor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
+                // Executor started running (or continues after a short idle period)
+                let continues_slice = self.resume_after_short_idle(exe_id, timestamp);
+                if !continues_slice {
+                    self.send_executor_begin(exe_id, timestamp);
+                }
             }
             (Some(exe_id), None) => {
-                // Executor stopped running
-                let _ = self.trace_event_sender.send(TracingEvent::End {
-                    name: Some(self.executors.get(&exe_id).unwrap().get_name().to_string()),
-                    cat: Some("executor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
+                // Executor stopped running (held back until the idle period is long enough)
+                if self.options.idle_merge_threshold_us.is_some() {
+                    self.pending_idle = Some((exe_id, timestamp));
+                } else {
+                    self.send_executor_end(exe_id, timestamp);
+                }
             }
             (Some(prev_exe), Some(curr_exe)) if prev_exe != curr_exe => {
                 // Executor switch
-                // End previous
-                let _ = self.trace_event_sender.send(TracingEvent::End {
-                    name: Some(
-                        self.executors
-                            .get(&prev_exe)
-                            .unwrap()
-                            .get_name()
-                            .to_string(),
-                    ),
-                    cat: Some("executor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
-                // Begin current
-                let _ = self.trace_event_sender.send(TracingEvent::Begin {
-                    name: self
-                        .executors
-                        .get(&curr_exe)
-                        .unwrap()
-                        .get_name()
-                        .to_string(),
-                    cat: Some("executor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
+                self.send_executor_end(prev_exe, timestamp);
+                self.send_executor_begin(curr_exe, timestamp);
             }
             _ => {} // same executor or both none
         }
+        self.evict_ended_executors(timestamp);
 
         // Handle core-level events
         if log_event.core_id == self.core_id {
             // Check if Function Monitor Start event
-            if let LogEventType::EventMonitorStart { function_name } = &log_event.event_type {
-                // Send start event
-                let _ = self.trace_event_sender.send(TracingEvent::Begin {
-                    name: function_name.to_string(),
-                    cat: Some("function_monitor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
+            if let LogEventType::EventMonitorStart {
+                function_name,
+                category,
+                sampled,
+                executor_id,
+                location,
+            } = &log_event.event_type
+            {
+                // Mark sampled monitors so their slices are not read as contiguous
+                let mut args = HashMap::new();
+                if let Some(sampled) = sampled {
+                    args.insert("sampled".to_string(), sampled.to_string());
+                }
+                // Tells apart scopes of the same name, which share the slice name
+                if let Some(location) = location {
+                    args.insert("location".to_string(), location.to_string());
+                }
+
+                let task = self.get_monitor_task(*executor_id);
+                let task_id = task.map(|(_, task_id)| task_id);
+                let open = self
+                    .monitor_stack
+                    .iter()
+                    .filter(|monitor| monitor.task_id == task_id)
+                    .count();
+                let admission = self.get_monitor_guard_mut(task).admit(function_name, open);
+                if let MonitorAdmission::ForceClose(count) = admission {
+                    self.force_close_oldest_monitors(task_id, count, log_event.timestamp);
+                }
+
+                if admission == MonitorAdmission::Accept {
+                    let collapsed =
+                        self.is_collapsed_call(self.monitor_stack.len(), function_name, task_id);
+                    if collapsed {
+                        self.enter_recursion(function_name, task_id);
+                    }
+                    self.monitor_stack.push(MonitorInterval {
+                        name: function_name.to_string(),
+                        core_id: self.core_id,
+                        task_id,
+                        category: category.clone(),
+                        start: log_event.timestamp,
+                        duration_us: 0,
+                        recursion_depth: None,
+                    });
+
+                    // Send start event (recursive calls continue the slice of their caller)
+                    if self.options.monitor_filter.matches(function_name) && !collapsed {
+                        let category = monitor_category(category);
+                        let _ = self.trace_event_sender.send(TracingEvent::Begin {
+                            name: function_name.to_string(),
+                            cname: self.options.monitor_colors.get(function_name, &category),
+                            cat: Some(category),
+                            pid: 0,
+                            tid: Some(self.core_id as u32),
+                            ts: log_event.timestamp.as_micros(),
+                            args,
+                        });
+                    }
+                }
             }
 
             // Check if Function Monitor End event
-            if let LogEventType::EventMonitorEnd { function_name } = &log_event.event_type {
-                // Send end event
-                let _ = self.trace_event_sender.send(TracingEvent::End {
-                    name: Some(function_name.to_string()),
-                    cat: Some("function_monitor".to_string()),
-                    pid: 0,
-                    tid: Some(self.core_id as u32),
-                    ts: log_event.timestamp.as_micros(),
-                    args: HashMap::new(),
-                });
+            if let LogEventType::EventMonitorEnd {
+                function_name,
+                category,
+            } = &log_event.event_type
+            {
+                // Ends of refused starts are dropped like their starts
+                let task = self.get_running_task();
+                let rejected = self
+                    .get_monitor_guard_mut(task)
+                    .take_rejected_end(function_name);
+
+                // Send end event (unless the monitor was force-closed already or collapsed)
+                if !rejected
+                    && let Some(args) = self.close_monitor(function_name, log_event.timestamp)
+                    && self.options.monitor_filter.matches(function_name)
+                {
+                    let _ = self.trace_event_sender.send(TracingEvent::End {
+                        name: Some(function_name.to_string()),
+                        cat: Some(monitor_category(category)),
+                        pid: 0,
+                        tid: Some(self.core_id as u32),
+                        ts: log_event.timestamp.as_micros(),
+                        args,
+                    });
+                }
             }
 
-            // Check if metric event
-            if let LogEventType::EventMetric { name, value } = &log_event.event_type {
-                // Try to link event to currently running executor
-                let current_running_task = self
+            // Check if timer event of the currently running task
+            match log_event.event_type {
+                LogEventType::EventTimerSet { deadline_us } => {
+                    if let Some(task) = self
+                        .executors
+                        .values_mut()
+                        .find_map(|exe| exe.get_currently_running_task_mut())
+                    {
+                        task.on_timer_set(deadline_us, log_event.timestamp);
+                    }
+                }
+                LogEventType::EventTimerFired { deadline_us } => {
+                    if let Some(task) = self
+                        .executors
+                        .values_mut()
+                        .find_map(|exe| exe.get_currently_running_task_mut())
+                    {
+                        task.on_timer_fired(deadline_us, log_event.timestamp);
+                    }
+                }
+                _ => {}
+            }
+
+            // Check if lock contention event of the currently running task
+            if let Some(task) = self
+                .executors
+                .values_mut()
+                .find_map(|exe| exe.get_currently_running_task_mut())
+            {
+                match &log_event.event_type {
+                    LogEventType::EventResourceWait { name } => {
+                        task.on_resource_wait(name, log_event.timestamp)
+                    }
+                    LogEventType::EventResourceAcquired { name } => {
+                        task.on_resource_acquired(name, log_event.timestamp)
+                    }
+                    LogEventType::EventResourceReleased { name } => {
+                        task.on_resource_released(name, log_event.timestamp)
+                    }
+                    LogEventType::EventResourceAbandoned { name } => {
+                        task.on_resource_abandoned(name, log_event.timestamp)
+                    }
+                    LogEventType::EventYieldReason { code } => task.on_yield_reason(
+                        yield_reason_label(*code, &self.options.yield_reason_names),
+                    ),
+                    _ => {}
+                }
+            }
+
+            // Check if coalesced monitor event ==> rate and average duration instead of slices
+            if let LogEventType::EventMonitorAggregate {
+                function_name,
+                count,
+                total_us,
+                window_us,
+            } = &log_event.event_type
+            {
+                if log_event.timestamp.as_micros() >= self.stats_from_us {
+                    let (total_count, total_duration_us) = self
+                        .monitor_aggregates
+                        .entry(function_name.to_string())
+                        .or_insert((0, 0));
+                    *total_count += *count as u64;
+                    *total_duration_us += *total_us;
+                }
+
+                let pid = self
                     .executors
                     .values()
-                    .find_map(|exe| exe.get_currently_running_task());
-                let pid = current_running_task.map(|task| task.get_pid());
+                    .find_map(|exe| exe.get_currently_running_task())
+                    .map(|task| task.get_pid());
+                let calls_per_sec = *count as f64 * 1_000_000.0 / (*window_us).max(1) as f64;
+                let avg_us = *total_us as f64 / (*count).max(1) as f64;
+                let traced = self.options.monitor_filter.matches(function_name);
+                for (suffix, value) in [("calls/s", calls_per_sec), ("avg us", avg_us)]
+                    .into_iter()
+                    .filter(|_| traced)
+                {
+                    let _ = self.trace_event_sender.send(TracingEvent::Counter {
+                        name: format!("{function_name} {suffix}"),
+                        cat: Some(monitor_category(&None)),
+                        pid,
+                        ts: log_event.timestamp.as_micros(),
+                        args: HashMap::from([("value".to_string(), value)]),
+                    });
+                }
+            }
 
-                // Send counter event
+            // Check if metric event
+            if let LogEventType::EventMetric { name, value, unit } = &log_event.event_type {
                 let tracing_event = TracingEvent::Counter {
-                    pid,
-                    name: name.to_string(),
+                    pid: self.get_metric_pid(name),
+                    name: metric_track_name(name, unit.as_deref()),
                     ts: log_event.timestamp.as_micros(),
                     args: HashMap::from([("value".to_string(), *value)]),
                     cat: None,
                 };
                 let _ = self.trace_event_sender.send(tracing_event);
             }
+
+            // Text values cannot be plotted as counter, show them as annotation instead
+            if let LogEventType::EventText { name, text } = &log_event.event_type {
+                let pid = self
+                    .executors
+                    .values()
+                    .find_map(|exe| exe.get_currently_running_task())
+                    .map(|task| task.get_pid());
+
+                let _ = self.trace_event_sender.send(TracingEvent::Instant {
+                    name: format!("{name}: {text}"),
+                    cat: Some("metric_text".to_string()),
+                    ts: log_event.timestamp.as_micros(),
+                    pid,
+                    tid: None,
+                    scope: if pid.is_some() {
+                        InstantScope::Process
+                    } else {
+                        InstantScope::Global
+                    },
+                    args: HashMap::from([
+                        ("name".to_string(), name.to_string()),
+                        ("text".to_string(), text.to_string()),
+                    ]),
+                    cname: CName::Yellow,
+                });
+            }
         }
     }
 }
+
+/// Name of a metric counter track. Perfetto counters have no unit, so it becomes part of the name
+pub fn metric_track_name(name: &str, unit: Option<&str>) -> String {
+    match unit {
+        Some(unit) => format!("{name} [{unit}]"),
+        None => name.to_string(),
+    }
+}
+
+/// Category of a function / scope monitor (custom category or the default `function_monitor`)
+fn monitor_category(category: &Option<String>) -> String {
+    category
+        .clone()
+        .unwrap_or_else(|| "function_monitor".to_string())
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+/// Sub-buckets per power of two. Durations below `2 * SUB_BUCKETS` µs are counted exactly,
+/// longer ones with a relative error of at most 1/64
+const SUB_BUCKET_BITS: u32 = 6;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Streaming duration statistics of one monitor: count, min, max and sum plus a log-linear
+/// histogram for percentiles. Its size is bounded by the number of buckets, not by the number of
+/// recorded durations, so long captures keep a flat memory footprint
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DurationHistogram {
+    count: u64,
+    min_us: u64,
+    max_us: u64,
+    sum_us: u128,
+    /// Number of durations per bucket index (only buckets that were hit)
+    buckets: BTreeMap<u16, u64>,
+}
+
+/// Bucket of a duration: the exact value below `2 * SUB_BUCKETS`, above that the 6 most
+/// significant bits and the power of two
+fn bucket_index(duration_us: u64) -> u16 {
+    if duration_us < SUB_BUCKETS {
+        return duration_us as u16;
+    }
+    let shift = (u64::BITS - 1 - duration_us.leading_zeros()) - SUB_BUCKET_BITS;
+    ((shift as u64) * SUB_BUCKETS + (duration_us >> shift)) as u16
+}
+
+/// Largest duration that falls into a bucket
+fn bucket_upper_bound(index: u16) -> u64 {
+    let index = index as u64;
+    if index < 2 * SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let mantissa = index % SUB_BUCKETS + SUB_BUCKETS;
+    let upper_bound = (((mantissa + 1) as u128) << shift) - 1;
+    u64::try_from(upper_bound).unwrap_or(u64::MAX)
+}
+
+impl DurationHistogram {
+    /// Record one completed duration
+    pub fn record(&mut self, duration_us: u128) {
+        let duration_us = u64::try_from(duration_us).unwrap_or(u64::MAX);
+        self.min_us = if self.count == 0 {
+            duration_us
+        } else {
+            self.min_us.min(duration_us)
+        };
+        self.max_us = self.max_us.max(duration_us);
+        self.count += 1;
+        self.sum_us += duration_us as u128;
+        *self.buckets.entry(bucket_index(duration_us)).or_insert(0) += 1;
+    }
+
+    /// Add the durations recorded by another histogram (e.g. of another core)
+    pub fn merge(&mut self, other: &DurationHistogram) {
+        if other.count == 0 {
+            return;
+        }
+        self.min_us = if self.count == 0 {
+            other.min_us
+        } else {
+            self.min_us.min(other.min_us)
+        };
+        self.max_us = self.max_us.max(other.max_us);
+        self.count += other.count;
+        self.sum_us += other.sum_us;
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_insert(0) += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min_us(&self) -> u128 {
+        self.min_us as u128
+    }
+
+    pub fn max_us(&self) -> u128 {
+        self.max_us as u128
+    }
+
+    pub fn mean_us(&self) -> f64 {
+        self.sum_us as f64 / self.count.max(1) as f64
+    }
+
+    /// Nearest-rank percentile (0 to 100), at most 1/64 above the exact value and never above the
+    /// longest recorded duration
+    pub fn percentile_us(&self, percentile: u64) -> u128 {
+        let rank = (self.count * percentile).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (index, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper_bound(*index).min(self.max_us) as u128;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for duration_us in [0, 1, 63, 64, 127, 128, 129, 1000, 123_456, u64::MAX] {
+            let index = bucket_index(duration_us);
+            let upper_bound = bucket_upper_bound(index);
+            assert!(upper_bound >= duration_us, "{duration_us}");
+            assert!(upper_bound - duration_us <= duration_us / SUB_BUCKETS);
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < duration_us, "{duration_us}");
+            }
+        }
+        assert_eq!(bucket_upper_bound(bucket_index(100)), 100);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut histogram = DurationHistogram::default();
+        assert_eq!(histogram.percentile_us(99), 0);
+
+        for duration_us in 1..=1000 {
+            histogram.record(duration_us);
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.min_us(), 1);
+        assert_eq!(histogram.max_us(), 1000);
+        assert_eq!(histogram.mean_us(), 500.5);
+        let p99_us = histogram.percentile_us(99);
+        assert!((990..=990 + 990 / 64).contains(&p99_us), "{p99_us}");
+
+        // Merged histograms equal one that recorded everything
+        let mut low = DurationHistogram::default();
+        let mut high = DurationHistogram::default();
+        for duration_us in 1..=1000 {
+            if duration_us <= 500 {
+                low.record(duration_us);
+            } else {
+                high.record(duration_us);
+            }
+        }
+        high.merge(&low);
+        assert_eq!(high, histogram);
+    }
+}
@@ -26,18 +26,24 @@
 
 use std::{collections::HashMap, fmt::Display};
 
-use crossbeam::channel::Sender;
-
 use crate::{
     elf_file::FirmwareAddressMap,
-    perfetto_backend::trace_event::TracingEvent,
+    perfetto_backend::{
+        event_sender::TraceEventSender,
+        trace_event::{CName, InstantScope, TracingArgsMap, TracingEvent},
+    },
     time::EmbassyTime,
     tracing::{
         log_event::{LogEvent, LogEventType},
-        task::TaskTracing,
+        task::{TaskTraceOptions, TaskTracing},
+        tracing_instance::UsageStats,
     },
 };
 
+/// An executor without tasks that stayed idle this long (in µs) is considered ended and is no
+/// longer tracked (it is tracked again if a task gets spawned onto it later)
+const ENDED_EXECUTOR_IDLE_US: u128 = 10_000_000;
+
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum PreemptedPrevState {
     Scheduling,
@@ -86,7 +92,8 @@ pub struct ExecutorTracing {
     display_name: String,
 
     firmware_addr_map: FirmwareAddressMap,
-    trace_event_sender: Sender<TracingEvent>,
+    trace_event_sender: TraceEventSender,
+    task_options: TaskTraceOptions,
 
     /// Current state of the executor
     state: ExecutorState,
@@ -94,6 +101,17 @@ pub struct ExecutorTracing {
     state_start_time: EmbassyTime,
 
     tasks: HashMap<u32, TaskTracing>,
+    /// Running time (in µs) and poll count per task name of the evicted (ended) tasks
+    ended_task_usage: HashMap<String, (u128, u64)>,
+    /// Number of ready tasks last sent as counter
+    ready_task_count: usize,
+
+    /// Idle periods shorter than this (in µs) do not interrupt the Scheduling slice
+    idle_merge_threshold_us: Option<u64>,
+    /// Start of the current Idle period while it is not known to be long enough to be sent
+    pending_idle_since: Option<EmbassyTime>,
+    /// Idle time (in µs) merged into the open Scheduling slice
+    merged_idle_us: u128,
 }
 
 impl ExecutorTracing {
@@ -102,7 +120,9 @@ impl ExecutorTracing {
         core_id: u8,
         created_at: EmbassyTime,
         firmware_addr_map: FirmwareAddressMap,
-        trace_event_sender: Sender<TracingEvent>,
+        trace_event_sender: TraceEventSender,
+        task_options: TaskTraceOptions,
+        idle_merge_threshold_us: Option<u64>,
     ) -> Self {
         // try to find task name from global firmware address map
         let executor_name = firmware_addr_map.get_symbol_name(executor_id as u64);
@@ -145,6 +165,7 @@ impl ExecutorTracing {
             pid: executor_id,
             tid: None,
             args: HashMap::new(),
+            cname: None,
         });
 
         Self {
@@ -155,7 +176,13 @@ impl ExecutorTracing {
             state_start_time: created_at,
             firmware_addr_map,
             trace_event_sender,
+            task_options,
             tasks: HashMap::new(),
+            ended_task_usage: HashMap::new(),
+            ready_task_count: 0,
+            idle_merge_threshold_us,
+            pending_idle_since: None,
+            merged_idle_us: 0,
         }
     }
 
@@ -171,29 +198,117 @@ impl ExecutorTracing {
 
     /// Set a new state for the executor, sending statistics as needed
     fn set_new_state(&mut self, new_state: ExecutorState, timestamp: EmbassyTime) {
-        if self.state != new_state {
-            // Send End trace event for previous state
-            let _ = self.trace_event_sender.send(TracingEvent::End {
-                name: None,
-                cat: None,
-                ts: timestamp.as_micros(),
-                pid: self.executor_id,
-                tid: None,
-                args: HashMap::new(),
-            });
-            // Send Begin trace event for new state
-            let _ = self.trace_event_sender.send(TracingEvent::Begin {
-                name: new_state.to_string(),
+        if self.state == new_state {
+            return;
+        }
+
+        if let Some(threshold_us) = self.idle_merge_threshold_us {
+            // Idle is only sent once it turned out to be long enough
+            if new_state == ExecutorState::Idle {
+                self.pending_idle_since = Some(timestamp);
+                self.state = new_state;
+                self.state_start_time = timestamp;
+                return;
+            }
+
+            if let Some(idle_since) = self.pending_idle_since.take() {
+                let idle_us = timestamp.as_micros().saturating_sub(idle_since.as_micros());
+                if idle_us < threshold_us as u128 && new_state == ExecutorState::Scheduling {
+                    // Micro idle ==> the Scheduling slice before it simply continues
+                    self.merged_idle_us += idle_us;
+                    self.state = new_state;
+                    self.state_start_time = timestamp;
+                    return;
+                }
+                self.send_state_change(ExecutorState::Idle, idle_since);
+            }
+        }
+
+        self.send_state_change(new_state, timestamp);
+        self.state = new_state;
+        self.state_start_time = timestamp;
+    }
+
+    /// Send the end of the current state slice and the begin of the next one
+    fn send_state_change(&mut self, new_state: ExecutorState, timestamp: EmbassyTime) {
+        // Send End trace event for previous state
+        let _ = self.trace_event_sender.send(TracingEvent::End {
+            name: None,
+            cat: None,
+            ts: timestamp.as_micros(),
+            pid: self.executor_id,
+            tid: None,
+            args: merged_idle_args(std::mem::take(&mut self.merged_idle_us)),
+        });
+        // Send Begin trace event for new state
+        let _ = self.trace_event_sender.send(TracingEvent::Begin {
+            name: new_state.to_string(),
+            cat: None,
+            ts: timestamp.as_micros(),
+            pid: self.executor_id,
+            tid: None,
+            args: HashMap::new(),
+            cname: None,
+        });
+    }
+
+    /// Send an Idle period that is still held back (e.g. at the end of the session)
+    pub fn flush_pending_idle(&mut self) {
+        if let Some(idle_since) = self.pending_idle_since.take() {
+            self.send_state_change(ExecutorState::Idle, idle_since);
+        }
+    }
+
+    /// The executor ended polling another task than the one it started polling, i.e. events in
+    /// between were lost. The polled task is stopped, so its running time does not grow until
+    /// its next poll, and the gap is marked on the executor track
+    fn on_exec_end_mismatch(&mut self, polling_task_id: u32, task_id: u32, timestamp: EmbassyTime) {
+        if let Some(task) = self.tasks.get_mut(&polling_task_id) {
+            task.stop_running(timestamp);
+        }
+
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Lost task events".to_string(),
+            cat: Some("warning".to_string()),
+            ts: timestamp.as_micros(),
+            pid: Some(self.executor_id),
+            tid: None,
+            scope: InstantScope::Process,
+            args: HashMap::from([
+                ("polling_task".to_string(), format!("0x{polling_task_id:X}")),
+                ("exec_end_task".to_string(), format!("0x{task_id:X}")),
+            ]),
+            cname: CName::Terrible,
+        });
+    }
+
+    /// A task could not be spawned on this executor (it never runs, so mark it on the executor)
+    fn on_spawn_failed(&self, timestamp: EmbassyTime) {
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Spawn failed".to_string(),
+            cat: Some("warning".to_string()),
+            ts: timestamp.as_micros(),
+            pid: Some(self.executor_id),
+            tid: None,
+            scope: InstantScope::Process,
+            args: HashMap::new(),
+            cname: CName::Terrible,
+        });
+    }
+
+    /// Send the number of ready (queued) tasks as counter whenever it changes. The count is
+    /// derived from the task states, so it resyncs by itself if a transition was missed
+    fn update_ready_task_count(&mut self, timestamp: EmbassyTime) {
+        let ready_task_count = self.tasks.values().filter(|task| task.is_ready()).count();
+        if ready_task_count != self.ready_task_count {
+            self.ready_task_count = ready_task_count;
+            let _ = self.trace_event_sender.send(TracingEvent::Counter {
+                name: "Ready tasks".to_string(),
                 cat: None,
+                pid: Some(self.executor_id),
                 ts: timestamp.as_micros(),
-                pid: self.executor_id,
-                tid: None,
-                args: HashMap::new(),
+                args: HashMap::from([("value".to_string(), ready_task_count as f64)]),
             });
-
-            // update state
-            self.state = new_state;
-            self.state_start_time = timestamp;
         }
     }
 
@@ -202,21 +317,26 @@ impl ExecutorTracing {
         // Check if the log event contains a task for this executor that we do not yet track
         if let Some(executor_id) = log_event.event_type.get_executor_id() {
             // Check executor ID
-            if executor_id == self.executor_id {
-                if let Some(task_id) = log_event.event_type.get_task_id() {
-                    if !self.tasks.contains_key(&task_id) {
-                        // If the task does not exist, create it (probably a TaskNew event)
-                        let new_task = TaskTracing::new(
-                            task_id,
-                            self.executor_id,
-                            self.core_id,
-                            self.trace_event_sender.clone(),
-                            &self.firmware_addr_map,
-                            log_event.timestamp,
-                        );
-                        self.tasks.insert(task_id, new_task);
-                    }
-                }
+            if executor_id == self.executor_id
+                && let Some(task_id) = log_event.event_type.get_task_id()
+                && !self.tasks.contains_key(&task_id)
+                && !matches!(
+                    log_event.event_type,
+                    LogEventType::EventEmbassyTaskExecEnd { .. }
+                        | LogEventType::EventEmbassyTaskEnd { .. }
+                )
+            {
+                // If the task does not exist, create it (probably a TaskNew event)
+                let new_task = TaskTracing::new(
+                    task_id,
+                    self.executor_id,
+                    self.core_id,
+                    self.trace_event_sender.clone(),
+                    &self.firmware_addr_map,
+                    log_event.timestamp,
+                    &self.task_options,
+                );
+                self.tasks.insert(task_id, new_task);
             }
         }
 
@@ -224,30 +344,32 @@ impl ExecutorTracing {
         for task in self.tasks.values_mut() {
             task.update(log_event);
         }
+        self.update_ready_task_count(log_event.timestamp);
 
         // Check preemption state
         match self.state {
             ExecutorState::Polling { .. } | ExecutorState::Scheduling => {
                 // Check if we are beeing preempted
-                if let LogEventType::EventEmbassyPollStart { executor_id } = log_event.event_type {
-                    if executor_id != self.executor_id && log_event.core_id == self.core_id {
-                        // preempt
-                        let prev_state = match self.state {
-                            ExecutorState::Scheduling => PreemptedPrevState::Scheduling,
-                            ExecutorState::Polling { task_id } => {
-                                PreemptedPrevState::Polling { task_id }
-                            }
-                            _ => unreachable!(),
-                        };
-
-                        self.set_new_state(
-                            ExecutorState::Preempted {
-                                by_executor_id: executor_id,
-                                prev_state,
-                            },
-                            log_event.timestamp,
-                        );
-                    }
+                if let LogEventType::EventEmbassyPollStart { executor_id } = log_event.event_type
+                    && executor_id != self.executor_id
+                    && log_event.core_id == self.core_id
+                {
+                    // preempt
+                    let prev_state = match self.state {
+                        ExecutorState::Scheduling => PreemptedPrevState::Scheduling,
+                        ExecutorState::Polling { task_id } => {
+                            PreemptedPrevState::Polling { task_id }
+                        }
+                        _ => unreachable!(),
+                    };
+
+                    self.set_new_state(
+                        ExecutorState::Preempted {
+                            by_executor_id: executor_id,
+                            prev_state,
+                        },
+                        log_event.timestamp,
+                    );
                 }
             }
             ExecutorState::Preempted {
@@ -256,11 +378,10 @@ impl ExecutorTracing {
             } => {
                 // Check if we can resume (the higher prio executor goes back to idle)
                 if let LogEventType::EventEmbassyExecutorIdle { executor_id } = log_event.event_type
+                    && executor_id == by_executor_id
                 {
-                    if executor_id == by_executor_id {
-                        // resume
-                        self.set_new_state(prev_state.into(), log_event.timestamp);
-                    }
+                    // resume
+                    self.set_new_state(prev_state.into(), log_event.timestamp);
                 }
             }
             _ => {}
@@ -272,6 +393,10 @@ impl ExecutorTracing {
             .get_executor_id()
             .is_some_and(|exe_id| exe_id == self.executor_id)
         {
+            if let LogEventType::EventEmbassyTaskSpawnFailed { .. } = log_event.event_type {
+                self.on_spawn_failed(log_event.timestamp);
+            }
+
             // Executor State machine transitions
 
             match self.state {
@@ -291,14 +416,130 @@ impl ExecutorTracing {
                         self.set_new_state(ExecutorState::Idle, log_event.timestamp);
                     }
                 }
-                ExecutorState::Polling { .. } => {
-                    if let LogEventType::EventEmbassyTaskExecEnd { .. } = log_event.event_type {
+                ExecutorState::Polling {
+                    task_id: polling_task_id,
+                } => {
+                    if let LogEventType::EventEmbassyTaskExecEnd { task_id, .. } =
+                        log_event.event_type
+                    {
+                        if task_id != polling_task_id {
+                            self.on_exec_end_mismatch(
+                                polling_task_id,
+                                task_id,
+                                log_event.timestamp,
+                            );
+                        }
                         self.set_new_state(ExecutorState::Scheduling, log_event.timestamp);
                     }
                 }
                 _ => {}
             }
+
+            // Evict ended tasks once they are no longer polled (their id can be reused by a new spawn)
+            if let Some(task_id) = log_event.event_type.get_task_id()
+                && self.state != (ExecutorState::Polling { task_id })
+                && self
+                    .tasks
+                    .get(&task_id)
+                    .is_some_and(|task| task.has_ended())
+                && let Some(task) = self.tasks.remove(&task_id)
+            {
+                task.finish(log_event.timestamp);
+                let (running_us, poll_count) = self
+                    .ended_task_usage
+                    .entry(task.get_name().to_string())
+                    .or_insert((0, 0));
+                *running_us += task.get_running_us(log_event.timestamp);
+                *poll_count += task.get_poll_count();
+            }
+        }
+    }
+
+    /// Close the slices of the executor and all of its tasks (the executor is no longer tracked
+    /// afterwards, e.g. after a target reset)
+    pub fn finish(&mut self, timestamp: EmbassyTime) {
+        for task in self.tasks.values() {
+            task.finish(timestamp);
+        }
+        self.flush_pending_idle();
+
+        let _ = self.trace_event_sender.send(TracingEvent::End {
+            name: None,
+            cat: None,
+            ts: timestamp.as_micros(),
+            pid: self.executor_id,
+            tid: None,
+            args: HashMap::new(),
+        });
+    }
+
+    /// Add the running time and poll count of every task (also the ended ones) up to the given
+    /// timestamp
+    pub fn add_task_usage(&self, usage: &mut UsageStats, until: EmbassyTime) {
+        let task_usage = self
+            .tasks
+            .values()
+            .map(|task| {
+                (
+                    task.get_name(),
+                    task.get_running_us(until),
+                    task.get_poll_count(),
+                )
+            })
+            .chain(
+                self.ended_task_usage
+                    .iter()
+                    .map(|(name, (running_us, poll_count))| {
+                        (name.as_str(), *running_us, *poll_count)
+                    }),
+            );
+        for (task_name, running_us, poll_count) in task_usage {
+            let key = (self.display_name.clone(), task_name.to_string());
+            *usage.task_polls.entry(key.clone()).or_insert(0) += poll_count;
+            *usage.task_running_us.entry(key).or_insert(0) += running_us;
+        }
+    }
+
+    /// Number of tasks currently tracked on this executor
+    #[cfg(test)]
+    pub fn get_tracked_task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Check if all tasks of the executor have ended and it has been idle since for a while
+    pub fn has_ended(&self, timestamp: EmbassyTime) -> bool {
+        self.tasks.is_empty()
+            && !self.ended_task_usage.is_empty()
+            && self.state == ExecutorState::Idle
+            && timestamp
+                .as_micros()
+                .saturating_sub(self.state_start_time.as_micros())
+                >= ENDED_EXECUTOR_IDLE_US
+    }
+
+    /// Check if the task is tracked and has not ended yet
+    pub fn has_live_task(&self, task_id: u32) -> bool {
+        self.tasks
+            .get(&task_id)
+            .is_some_and(|task| !task.has_ended())
+    }
+
+    /// If the executor is currently preempted, get the preempting executor, the task that was
+    /// polled when it got preempted and when the preemption started
+    pub fn get_preemption(&self) -> Option<(u32, Option<u32>, EmbassyTime)> {
+        if let ExecutorState::Preempted {
+            by_executor_id,
+            prev_state,
+        } = self.state
+        {
+            let task_id = match prev_state {
+                PreemptedPrevState::Polling { task_id } => Some(task_id),
+                PreemptedPrevState::Scheduling => None,
+            };
+            return Some((by_executor_id, task_id, self.state_start_time));
         }
+
+        None
     }
 
     /// Check if this executor is currently spending cpu time (scheduling or polling)
@@ -315,4 +556,35 @@ impl ExecutorTracing {
 
         None
     }
+
+    /// Get a tracked task
+    pub fn get_task_mut(&mut self, task_id: u32) -> Option<&mut TaskTracing> {
+        self.tasks.get_mut(&task_id)
+    }
+
+    /// Check if a task of this executor refused the start of a monitor with this name and still
+    /// expects its end
+    pub fn expects_rejected_monitor_end(&self, function_name: &str) -> bool {
+        self.tasks
+            .values()
+            .any(|task| task.get_monitor_guard().expects_rejected_end(function_name))
+    }
+
+    /// Mutable variant of [`Self::get_currently_running_task`]
+    pub fn get_currently_running_task_mut(&mut self) -> Option<&mut TaskTracing> {
+        if let ExecutorState::Polling { task_id } = self.state {
+            return self.tasks.get_mut(&task_id);
+        }
+
+        None
+    }
+}
+
+/// Args of a slice that has short idle periods merged into it
+pub fn merged_idle_args(merged_idle_us: u128) -> TracingArgsMap<String> {
+    if merged_idle_us > 0 {
+        HashMap::from([("merged_idle_us".to_string(), merged_idle_us.to_string())])
+    } else {
+        HashMap::new()
+    }
 }
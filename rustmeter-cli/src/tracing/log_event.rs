@@ -1,18 +1,114 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
 use crate::{time::EmbassyTime, tracing::log_line::LogLine};
 
 #[derive(Debug, Clone, PartialEq)]
 #[allow(clippy::enum_variant_names)]
 pub enum LogEventType {
-    EventEmbassyTaskExecEnd { executor_id: u32, task_id: u32 },
-    EventEmbassyTaskReadyBegin { executor_id: u32, task_id: u32 },
-    EventEmbassyTaskExecBegin { executor_id: u32, task_id: u32 },
-    EventEmbassyTaskEnd { executor_id: u32, task_id: u32 },
-    EventEmbassyTaskNew { executor_id: u32, task_id: u32 },
-    EventEmbassyExecutorIdle { executor_id: u32 },
-    EventEmbassyPollStart { executor_id: u32 },
-    EventMonitorStart { function_name: String },
-    EventMonitorEnd { function_name: String },
-    EventMetric { name: String, value: f64 },
+    EventEmbassyTaskExecEnd {
+        executor_id: u32,
+        task_id: u32,
+    },
+    EventEmbassyTaskReadyBegin {
+        executor_id: u32,
+        task_id: u32,
+    },
+    EventEmbassyTaskExecBegin {
+        executor_id: u32,
+        task_id: u32,
+    },
+    EventEmbassyTaskEnd {
+        executor_id: u32,
+        task_id: u32,
+    },
+    EventEmbassyTaskNew {
+        executor_id: u32,
+        task_id: u32,
+    },
+    EventEmbassyExecutorIdle {
+        executor_id: u32,
+    },
+    EventEmbassyPollStart {
+        executor_id: u32,
+    },
+    /// `try_spawn_traced!` could not spawn a task (its pool is exhausted)
+    EventEmbassyTaskSpawnFailed {
+        executor_id: u32,
+    },
+    EventMonitorStart {
+        function_name: String,
+        category: Option<String>,
+        /// Only 1 in `sampled` invocations of this monitor are traced
+        sampled: Option<u32>,
+        /// Executor polling on the core when the monitor started (`monitor-executor-id` feature)
+        executor_id: Option<u32>,
+        /// Call site (`file:line`) of a scope monitor
+        location: Option<String>,
+    },
+    EventMonitorEnd {
+        function_name: String,
+        category: Option<String>,
+    },
+    /// Call statistics of a coalesced monitor (`#[monitor_fn(coalesce)]`) for one window
+    EventMonitorAggregate {
+        function_name: String,
+        count: u32,
+        total_us: u64,
+        window_us: u64,
+    },
+    EventMetric {
+        name: String,
+        value: f64,
+        /// Display unit of the value (e.g. `mA`)
+        unit: Option<String>,
+    },
+    /// Text value logged via `event_text!`
+    EventText {
+        name: String,
+        text: String,
+    },
+    /// The target flushed its trace buffer (e.g. before a reset or from the panic handler)
+    EventTraceFlush,
+    /// User marker dropped via `trace_marker!`
+    EventMarker {
+        name: String,
+    },
+    /// The running task started waiting on an embassy-time timer
+    EventTimerSet {
+        deadline_us: u64,
+    },
+    /// The running task resumed after its embassy-time timer fired
+    EventTimerFired {
+        deadline_us: u64,
+    },
+    /// The running task started waiting for a traced lock (`traced_acquire!`)
+    EventResourceWait {
+        name: String,
+    },
+    /// The running task got the traced lock it waited for
+    EventResourceAcquired {
+        name: String,
+    },
+    /// The guard of a traced lock was dropped
+    EventResourceReleased {
+        name: String,
+    },
+    /// The running task stopped waiting for a traced lock without acquiring it
+    EventResourceAbandoned {
+        name: String,
+    },
+    /// Why the running task is about to return `Poll::Pending` (see `yield_reason!`)
+    EventYieldReason {
+        code: u8,
+    },
+    /// A DMA transfer started on the channel (`trace_dma!`)
+    EventDmaBegin {
+        channel: u8,
+    },
+    /// The DMA transfer of the channel completed
+    EventDmaEnd {
+        channel: u8,
+    },
 }
 
 impl LogEventType {
@@ -36,104 +132,249 @@ impl LogEventType {
             LogEventType::EventEmbassyTaskNew { executor_id, .. } => Some(*executor_id),
             LogEventType::EventEmbassyExecutorIdle { executor_id } => Some(*executor_id),
             LogEventType::EventEmbassyPollStart { executor_id } => Some(*executor_id),
+            LogEventType::EventEmbassyTaskSpawnFailed { executor_id } => Some(*executor_id),
             _ => None,
         }
     }
 
     pub fn try_from_name_and_param(
         name: &str,
-        params_map: &std::collections::HashMap<&str, &str>,
-    ) -> anyhow::Result<LogEventType> {
+        params_map: &HashMap<&str, &str>,
+    ) -> Result<LogEventType, DecodeError> {
         match name {
             "EVENT_EMBASSY_TASK_EXEC_END" => Ok(LogEventType::EventEmbassyTaskExecEnd {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
-                task_id: params_map
-                    .get("task_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'task_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+                task_id: get_param(params_map, "task_id")?,
             }),
             "EVENT_EMBASSY_TASK_READY_BEGIN" => Ok(LogEventType::EventEmbassyTaskReadyBegin {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
-                task_id: params_map
-                    .get("task_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'task_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+                task_id: get_param(params_map, "task_id")?,
             }),
             "EVENT_EMBASSY_TASK_EXEC_BEGIN" => Ok(LogEventType::EventEmbassyTaskExecBegin {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
-                task_id: params_map
-                    .get("task_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'task_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+                task_id: get_param(params_map, "task_id")?,
             }),
             "EVENT_EMBASSY_TASK_END" => Ok(LogEventType::EventEmbassyTaskEnd {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
-                task_id: params_map
-                    .get("task_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'task_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+                task_id: get_param(params_map, "task_id")?,
             }),
             "EVENT_EMBASSY_TASK_NEW" => Ok(LogEventType::EventEmbassyTaskNew {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
-                task_id: params_map
-                    .get("task_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'task_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+                task_id: get_param(params_map, "task_id")?,
             }),
             "EVENT_EMBASSY_EXECUTOR_IDLE" => Ok(LogEventType::EventEmbassyExecutorIdle {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
             }),
             "EVENT_EMBASSY_POLL_START" => Ok(LogEventType::EventEmbassyPollStart {
-                executor_id: params_map
-                    .get("executor_id")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'executor_id'"))?
-                    .parse()?,
+                executor_id: get_param(params_map, "executor_id")?,
+            }),
+            "EVENT_EMBASSY_TASK_SPAWN_FAILED" => Ok(LogEventType::EventEmbassyTaskSpawnFailed {
+                executor_id: get_param(params_map, "executor_id")?,
             }),
             "EVENT_MONITOR_START" => Ok(LogEventType::EventMonitorStart {
-                function_name: params_map
-                    .get("function_name")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'function_name'"))?
-                    .to_string(),
+                function_name: get_param(params_map, "function_name")?,
+                category: params_map.get("category").map(|cat| cat.to_string()),
+                sampled: get_optional_param(params_map, "sampled")?,
+                // 0 ==> started outside of any executor
+                executor_id: get_optional_param(params_map, "executor_id")?
+                    .filter(|executor_id| *executor_id != 0),
+                location: params_map
+                    .get("location")
+                    .map(|location| location.to_string()),
             }),
             "EVENT_MONITOR_END" => Ok(LogEventType::EventMonitorEnd {
-                function_name: params_map
-                    .get("function_name")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'function_name'"))?
-                    .to_string(),
+                function_name: get_param(params_map, "function_name")?,
+                category: params_map.get("category").map(|cat| cat.to_string()),
+            }),
+            "EVENT_MONITOR_AGGREGATE" => Ok(LogEventType::EventMonitorAggregate {
+                function_name: get_param(params_map, "function_name")?,
+                count: get_param(params_map, "count")?,
+                total_us: get_param(params_map, "total_us")?,
+                window_us: get_param(params_map, "window_us")?,
             }),
             "EVENT_METRIC" => Ok(LogEventType::EventMetric {
-                name: params_map
-                    .get("name")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'name'"))?
-                    .to_string(),
-                value: params_map
-                    .get("value")
-                    .ok_or(anyhow::anyhow!("Missing parameter 'value'"))?
-                    .parse()?,
-            }),
-            _ => Err(anyhow::anyhow!("Unknown LogEvent type: {name}")),
+                name: get_param(params_map, "name")?,
+                value: get_param(params_map, "value")?,
+                unit: params_map.get("unit").map(|unit| unit.to_string()),
+            }),
+            "EVENT_TEXT" => Ok(LogEventType::EventText {
+                name: get_param(params_map, "name")?,
+                text: cap_event_text(&get_param::<String>(params_map, "text")?).to_string(),
+            }),
+            "EVENT_TRACE_FLUSH" => Ok(LogEventType::EventTraceFlush),
+            "EVENT_MARKER" => Ok(LogEventType::EventMarker {
+                name: get_param(params_map, "name")?,
+            }),
+            "EVENT_TIMER_SET" => Ok(LogEventType::EventTimerSet {
+                deadline_us: get_param(params_map, "deadline_us")?,
+            }),
+            "EVENT_TIMER_FIRED" => Ok(LogEventType::EventTimerFired {
+                deadline_us: get_param(params_map, "deadline_us")?,
+            }),
+            "EVENT_RESOURCE_WAIT" => Ok(LogEventType::EventResourceWait {
+                name: get_param(params_map, "name")?,
+            }),
+            "EVENT_RESOURCE_ACQUIRED" => Ok(LogEventType::EventResourceAcquired {
+                name: get_param(params_map, "name")?,
+            }),
+            "EVENT_RESOURCE_RELEASED" => Ok(LogEventType::EventResourceReleased {
+                name: get_param(params_map, "name")?,
+            }),
+            "EVENT_RESOURCE_ABANDONED" => Ok(LogEventType::EventResourceAbandoned {
+                name: get_param(params_map, "name")?,
+            }),
+            "EVENT_YIELD_REASON" => Ok(LogEventType::EventYieldReason {
+                code: get_param(params_map, "code")?,
+            }),
+            "EVENT_DMA_BEGIN" => Ok(LogEventType::EventDmaBegin {
+                channel: get_param(params_map, "channel")?,
+            }),
+            "EVENT_DMA_END" => Ok(LogEventType::EventDmaEnd {
+                channel: get_param(params_map, "channel")?,
+            }),
+            _ => Err(DecodeError::UnknownEvent {
+                name: name.to_string(),
+            }),
         }
     }
 }
 
+/// Why a log line could not be decoded into a [`LogEvent`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The line is a plain log message, not a tracing event
+    NotAnEvent,
+    /// The event name is not known to this version of rustmeter (e.g. a newer beacon)
+    UnknownEvent { name: String },
+    /// The event is malformed: broken brackets, a missing or unparsable parameter
+    Corrupt { detail: String },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::NotAnEvent => write!(f, "Log line is not a tracing event"),
+            DecodeError::UnknownEvent { name } => write!(f, "Unknown LogEvent type: {name}"),
+            DecodeError::Corrupt { detail } => write!(f, "Corrupt LogEvent: {detail}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Get and parse a required event parameter
+fn get_param<T>(params_map: &HashMap<&str, &str>, key: &str) -> Result<T, DecodeError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    get_optional_param(params_map, key)?.ok_or(DecodeError::Corrupt {
+        detail: format!("missing parameter '{key}'"),
+    })
+}
+
+/// Get and parse an optional event parameter
+fn get_optional_param<T>(
+    params_map: &HashMap<&str, &str>,
+    key: &str,
+) -> Result<Option<T>, DecodeError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    params_map
+        .get(key)
+        .map(|value| {
+            value.parse().map_err(|e| DecodeError::Corrupt {
+                detail: format!("invalid parameter '{key}={value}': {e}"),
+            })
+        })
+        .transpose()
+}
+
+/// Maximum length of a text value (matches `MAX_EVENT_TEXT_LEN` of the beacon)
+const MAX_EVENT_TEXT_LEN: usize = 64;
+
+/// Cap a text value to its maximum length without splitting a UTF-8 character (the target already
+/// does this, but a corrupted line must not produce oversized annotations)
+fn cap_event_text(text: &str) -> &str {
+    if text.len() <= MAX_EVENT_TEXT_LEN {
+        return text;
+    }
+    let mut end = MAX_EVENT_TEXT_LEN;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Split the `key=value,...` parameters of an event into a map.
+///
+/// A value may contain commas and equal signs: it ends at the first comma that starts another
+/// `key=` parameter, or it is put in double quotes (`text="a, b"`). A parameter given twice is
+/// reported as corrupt, since a value that looks like a parameter would otherwise silently
+/// replace e.g. the `core_id` of the event
+fn parse_params(params_str: &str) -> Result<HashMap<&str, &str>, DecodeError> {
+    let mut params_map = HashMap::new();
+    let mut rest = params_str;
+    while !rest.trim().is_empty() {
+        let (key, after_key) = rest.split_once('=').ok_or(DecodeError::Corrupt {
+            detail: format!("parameter without value: '{}'", rest.trim()),
+        })?;
+        let key = key.trim();
+        if !is_param_key(key) {
+            return Err(DecodeError::Corrupt {
+                detail: format!("invalid parameter name '{key}'"),
+            });
+        }
+
+        let after_key = after_key.trim_start();
+        let (value, remainder) = if let Some(quoted) = after_key.strip_prefix('"') {
+            let end = quoted.find('"').ok_or(DecodeError::Corrupt {
+                detail: format!("unterminated quote in parameter '{key}'"),
+            })?;
+            let remainder = quoted[end + 1..].trim_start();
+            let remainder = match remainder.strip_prefix(',') {
+                Some(remainder) => remainder,
+                None if remainder.is_empty() => remainder,
+                None => {
+                    return Err(DecodeError::Corrupt {
+                        detail: format!("unexpected text after quoted parameter '{key}'"),
+                    });
+                }
+            };
+            (&quoted[..end], remainder)
+        } else {
+            match next_param_start(after_key) {
+                Some(end) => (after_key[..end].trim(), &after_key[end + 1..]),
+                None => (after_key.trim(), ""),
+            }
+        };
+
+        if params_map.insert(key, value).is_some() {
+            return Err(DecodeError::Corrupt {
+                detail: format!("duplicate parameter '{key}'"),
+            });
+        }
+        rest = remainder;
+    }
+    Ok(params_map)
+}
+
+/// Position of the first comma in an unquoted value that starts another `key=` parameter
+fn next_param_start(value: &str) -> Option<usize> {
+    value.match_indices(',').map(|(idx, _)| idx).find(|idx| {
+        value[idx + 1..]
+            .split_once('=')
+            .is_some_and(|(key, _)| is_param_key(key.trim()))
+    })
+}
+
+/// Parameter names are identifiers (`executor_id`, `function_name`, ...)
+fn is_param_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct LogEvent {
     pub timestamp: EmbassyTime,
@@ -150,39 +391,30 @@ impl LogEvent {
     }
 
     /// Parse a LogEvent from a LogLine
-    pub fn from_log_line(log_line: &LogLine) -> anyhow::Result<LogEvent> {
+    pub fn from_log_line(log_line: &LogLine) -> Result<LogEvent, DecodeError> {
         // Trim and check prefix
         let message = log_line.message.trim();
         if !message.starts_with("@") {
-            return Err(anyhow::anyhow!(
-                "LogEvent message does not start with '@': {message}"
-            ));
+            return Err(DecodeError::NotAnEvent);
         }
 
         // Find event type name and parameters
-        let opening_bracket = message.find('(').ok_or(anyhow::anyhow!(
-            "Invalid LogEvent message format (found no opening bracket): {message}"
-        ))?;
-        let closing_bracket = message.find(')').ok_or(anyhow::anyhow!(
-            "Invalid LogEvent message format (found no closing bracket): {message}"
-        ))?;
+        let opening_bracket = message.find('(').ok_or(DecodeError::Corrupt {
+            detail: format!("found no opening bracket: {message}"),
+        })?;
+        // only after the opening bracket, user logs may contain a stray ')' before it
+        let closing_bracket = message[opening_bracket..]
+            .rfind(')')
+            .map(|offset| opening_bracket + offset)
+            .ok_or(DecodeError::Corrupt {
+                detail: format!("found no closing bracket: {message}"),
+            })?;
         let event_type_name = &message[1..opening_bracket];
         let params_str = &message[opening_bracket + 1..closing_bracket];
 
-        // Parse parameters into a map
-        let mut params_map = std::collections::HashMap::new();
-        for param in params_str.split(',') {
-            let parts: Vec<&str> = param.splitn(2, '=').collect();
-            if parts.len() == 2 {
-                params_map.insert(parts[0].trim(), parts[1].trim());
-            }
-        }
-
         // Get parameters
-        let core_id = params_map
-            .get("core_id")
-            .ok_or(anyhow::anyhow!("Missing parameter 'core_id'"))?
-            .parse()?;
+        let params_map = parse_params(params_str)?;
+        let core_id = get_param(&params_map, "core_id")?;
         let event_type = LogEventType::try_from_name_and_param(event_type_name, &params_map)?;
 
         Ok(LogEvent::new(log_line.timestamp, core_id, event_type))
@@ -192,6 +424,7 @@ impl LogEvent {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tracing::log_line::LogLevel;
     #[test]
     fn test_log_event_from_log_line() {
         let log_line = LogLine::from_str("1.812321 [DEBUG] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1073610704, core_id=0, task_id=1073425160)").unwrap();
@@ -209,4 +442,294 @@ mod tests {
             e => panic!("Unexpected LogEventType: {e:?}"),
         }
     }
+
+    #[test]
+    fn test_monitor_start_with_category() {
+        let log_line = LogLine::from_str(
+            "0.5 [INFO ] @EVENT_MONITOR_START(function_name=parse,location=src/net/packet.rs:42,category=io,core_id=1)",
+        )
+        .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+
+        assert_eq!(log_event.core_id, 1);
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventMonitorStart {
+                function_name: "parse".to_string(),
+                category: Some("io".to_string()),
+                sampled: None,
+                executor_id: None,
+                location: Some("src/net/packet.rs:42".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_sampled_monitor_start() {
+        let log_line = LogLine::from_str(
+            "0.5 [INFO ] @EVENT_MONITOR_START(function_name=hot_fn,sampled=100,core_id=0)",
+        )
+        .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+
+        match log_event.event_type {
+            LogEventType::EventMonitorStart { sampled, .. } => assert_eq!(sampled, Some(100)),
+            e => panic!("Unexpected LogEventType: {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_timer_events() {
+        let log_line =
+            LogLine::from_str("2.0 [TRACE] @EVENT_TIMER_SET(deadline_us=2010000,core_id=0)")
+                .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventTimerSet {
+                deadline_us: 2010000
+            }
+        );
+
+        let log_line =
+            LogLine::from_str("2.01 [TRACE] @EVENT_TIMER_FIRED(deadline_us=2010000,core_id=0)")
+                .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventTimerFired {
+                deadline_us: 2010000
+            }
+        );
+    }
+
+    #[test]
+    fn test_resource_events() {
+        let log_line =
+            LogLine::from_str("2.0 [TRACE] @EVENT_RESOURCE_WAIT(name=spi_bus,core_id=0)").unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventResourceWait {
+                name: "spi_bus".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_dma_events() {
+        let log_line =
+            LogLine::from_str("2.0 [INFO ] @EVENT_DMA_BEGIN(channel=3,core_id=1)").unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(log_event.core_id, 1);
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventDmaBegin { channel: 3 }
+        );
+
+        let log_line =
+            LogLine::from_str("2.5 [INFO ] @EVENT_DMA_END(channel=300,core_id=1)").unwrap();
+        assert!(matches!(
+            LogEvent::from_log_line(&log_line),
+            Err(DecodeError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_text_event() {
+        let log_line = LogLine::from_str(
+            "0.5 [INFO ] @EVENT_TEXT(name=wifi_state,text=Connecting 🛜,core_id=0)",
+        )
+        .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventText {
+                name: "wifi_state".to_string(),
+                text: "Connecting 🛜".to_string(),
+            }
+        );
+
+        // Oversized text is capped on a character boundary
+        let text = "ä".repeat(40);
+        let log_line = LogLine::from_str(&format!(
+            "0.5 [INFO ] @EVENT_TEXT(name=id,text={text},core_id=0)"
+        ))
+        .unwrap();
+        match LogEvent::from_log_line(&log_line).unwrap().event_type {
+            LogEventType::EventText { text, .. } => assert_eq!(text, "ä".repeat(32)),
+            e => panic!("Unexpected LogEventType: {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_metric_with_unit() {
+        let log_line =
+            LogLine::from_str("0.5 [INFO ] @EVENT_METRIC(name=current,value=12,unit=mA,core_id=0)")
+                .unwrap();
+        assert_eq!(
+            LogEvent::from_log_line(&log_line).unwrap().event_type,
+            LogEventType::EventMetric {
+                name: "current".to_string(),
+                value: 12.0,
+                unit: Some("mA".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_values_with_commas() {
+        let text_of =
+            |message: &str| match LogEvent::from_log_line(&LogLine::from_str(message).unwrap())
+                .unwrap()
+                .event_type
+            {
+                LogEventType::EventText { text, .. } => text,
+                e => panic!("Unexpected LogEventType: {e:?}"),
+            };
+
+        // Commas and equal signs that do not start another parameter stay in the value
+        assert_eq!(
+            text_of("0.5 [INFO ] @EVENT_TEXT(name=state,text=a, b (x=1),core_id=0)"),
+            "a, b (x=1)"
+        );
+        assert_eq!(
+            text_of(r#"0.5 [INFO ] @EVENT_TEXT(name=state,text="x,core_id=7",core_id=0)"#),
+            "x,core_id=7"
+        );
+
+        let log_line =
+            LogLine::from_str(r#"0.5 [INFO ] @EVENT_METRIC(name="rx,tx",value=3,core_id=1)"#)
+                .unwrap();
+        let log_event = LogEvent::from_log_line(&log_line).unwrap();
+        assert_eq!(log_event.core_id, 1);
+        assert_eq!(
+            log_event.event_type,
+            LogEventType::EventMetric {
+                name: "rx,tx".to_string(),
+                value: 3.0,
+                unit: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_params_are_rejected() {
+        let decode = |message: &str| {
+            LogEvent::from_log_line(&LogLine::new(
+                EmbassyTime::from_micros(0),
+                LogLevel::Info,
+                message.to_string(),
+            ))
+        };
+
+        // An unquoted value that looks like a parameter must not replace the real one
+        assert!(matches!(
+            decode("@EVENT_TEXT(name=x,text=a,core_id=7,core_id=0)"),
+            Err(DecodeError::Corrupt { detail }) if detail == "duplicate parameter 'core_id'"
+        ));
+        assert!(matches!(
+            decode("@EVENT_EMBASSY_TASK_NEW(executor_id=1,task_id=2,task_id=3,core_id=0)"),
+            Err(DecodeError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            decode(r#"@EVENT_TEXT(name=x,text="open,core_id=0)"#),
+            Err(DecodeError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            decode(r#"@EVENT_TEXT(name=x,text="a"b,core_id=0)"#),
+            Err(DecodeError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            decode("@EVENT_TRACE_FLUSH(garbage,core_id=0)"),
+            Err(DecodeError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let decode = |message: &str| {
+            LogEvent::from_log_line(&LogLine::new(
+                EmbassyTime::from_micros(0),
+                LogLevel::Info,
+                message.to_string(),
+            ))
+        };
+
+        assert_eq!(decode("sensor ready"), Err(DecodeError::NotAnEvent));
+        assert_eq!(
+            decode("@EVENT_FROM_THE_FUTURE(core_id=0)"),
+            Err(DecodeError::UnknownEvent {
+                name: "EVENT_FROM_THE_FUTURE".to_string()
+            })
+        );
+        assert!(matches!(
+            decode("@EVENT_METRIC(name=temp,core_id=0"),
+            Err(DecodeError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            decode("@user) says (hi"),
+            Err(DecodeError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            decode("@EVENT_METRIC(name=temp,core_id=0)"),
+            Err(DecodeError::Corrupt { detail }) if detail == "missing parameter 'value'"
+        ));
+        assert!(matches!(
+            decode("@EVENT_METRIC(name=temp,value=hot,core_id=0)"),
+            Err(DecodeError::Corrupt { .. })
+        ));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_random_lines_never_panic(line in "\\PC{0,80}") {
+            if let Ok(log_line) = LogLine::from_str(&line) {
+                let _ = LogEvent::from_log_line(&log_line);
+            }
+        }
+
+        #[test]
+        fn test_random_events_never_panic(
+            name in "@EVENT_[A-Z_]{0,30}",
+            params in "[a-z_=,()\"\\\\0-9 .-]{0,60}",
+        ) {
+            let log_line = LogLine::new(
+                EmbassyTime::from_micros(0),
+                LogLevel::Info,
+                format!("{name}({params})"),
+            );
+            let _ = LogEvent::from_log_line(&log_line);
+        }
+
+        #[test]
+        fn test_random_messages_never_panic(message in "([()=,\"a-zA-Z0-9_ ]|\\PC){0,80}") {
+            // Any text behind the prefix, including stray and unbalanced brackets
+            let log_line = LogLine::new(
+                EmbassyTime::from_micros(0),
+                LogLevel::Info,
+                format!("@{message}"),
+            );
+            let _ = LogEvent::from_log_line(&log_line);
+        }
+
+        #[test]
+        fn test_metric_round_trip(
+            name in "[a-z_][a-z0-9_.]{0,20}",
+            value in -1e9f64..1e9,
+            core_id in 0u8..4,
+        ) {
+            let log_line = LogLine::new(
+                EmbassyTime::from_micros(0),
+                LogLevel::Info,
+                format!("@EVENT_METRIC(name={name},value={value},core_id={core_id})"),
+            );
+            let log_event = LogEvent::from_log_line(&log_line).unwrap();
+            proptest::prop_assert_eq!(log_event.core_id, core_id);
+            proptest::prop_assert_eq!(
+                log_event.event_type,
+                LogEventType::EventMetric { name, value, unit: None }
+            );
+        }
+    }
 }
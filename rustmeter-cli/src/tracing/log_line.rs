@@ -2,7 +2,10 @@ use std::fmt::Display;
 
 use anyhow::Context;
 
-use crate::{perfetto_backend::trace_event::CName, time::EmbassyTime};
+use crate::{
+    perfetto_backend::trace_event::CName,
+    time::{CycleClock, EmbassyTime},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum LogLevel {
@@ -79,34 +82,58 @@ impl LogLine {
 
     /// Parse a log line from a string: e.q. "0.438284 [DEBUG ] pop - New prio level: 0 (esp_rtos esp-rtos-0.2.0/src/run_queue.rs:292)"
     pub fn from_str(line: &str) -> anyhow::Result<LogLine> {
-        // Find open / close brackets for log level
-        let open_bracket = line.find('[').ok_or(anyhow::anyhow!(
-            "Invalid log line format (found no opening bracket): {line}"
+        let (timestamp_str, level, message) = split_log_line(line)?;
+        let secs = timestamp_str
+            .parse::<f64>()
+            .context("Failed to parse timestamp of log line")?;
+        let timestamp = EmbassyTime::try_from_secs_f64(secs).ok_or(anyhow::anyhow!(
+            "Timestamp of log line out of range: {timestamp_str}"
         ))?;
-        let close_bracket = line.find(']').ok_or(anyhow::anyhow!(
-            "Invalid log line format (found no closing bracket): {line}"
-        ))?;
-        if open_bracket > close_bracket.saturating_sub(3) {
-            return Err(anyhow::anyhow!(
-                "Invalid log line format (malformed log level brackets): {line}"
-            ));
+        Ok(LogLine::new(timestamp, level, message))
+    }
+
+    /// Parse a log line of firmware without defmt timestamp: e.q. "[INFO ] Booting" (see
+    /// `--align-logs`). Lines with a timestamp are rejected
+    pub fn parse_untimed(line: &str) -> anyhow::Result<(LogLevel, String)> {
+        let (timestamp_str, level, message) = split_log_line(line)?;
+        if !timestamp_str.is_empty() {
+            return Err(anyhow::anyhow!("Log line has a timestamp: {line}"));
         }
+        Ok((level, message))
+    }
 
-        // Extract parts
-        let timestamp_str = &line[0..open_bracket].trim();
-        let level_str = &line[open_bracket + 1..close_bracket].trim();
-        let message = line[close_bracket + 1..].trim().to_string();
+    /// Parse a log line timestamped in CPU cycles (`cycle_timestamp!`): e.q. "123456 [INFO ] ..."
+    pub fn from_str_cycles(line: &str, cycle_clock: &mut CycleClock) -> anyhow::Result<LogLine> {
+        let (timestamp_str, level, message) = split_log_line(line)?;
+        let cycles = timestamp_str
+            .parse::<u32>()
+            .context("Failed to parse cycle timestamp of log line")?;
+        Ok(LogLine::new(cycle_clock.convert(cycles), level, message))
+    }
+}
 
-        // Parse
-        let timestamp = EmbassyTime::from_secs_f64(
-            timestamp_str
-                .parse::<f64>()
-                .context("Failed to parse timestamp of log line")?,
-        );
-        let level =
-            LogLevel::from_str(level_str).context("Failed to parse log level of log line")?;
-        Ok(LogLine::new(timestamp, level, message))
+/// Split a log line into timestamp, log level and message
+fn split_log_line(line: &str) -> anyhow::Result<(&str, LogLevel, String)> {
+    // Find open / close brackets for log level
+    let open_bracket = line.find('[').ok_or(anyhow::anyhow!(
+        "Invalid log line format (found no opening bracket): {line}"
+    ))?;
+    let close_bracket = line.find(']').ok_or(anyhow::anyhow!(
+        "Invalid log line format (found no closing bracket): {line}"
+    ))?;
+    if open_bracket > close_bracket.saturating_sub(3) {
+        return Err(anyhow::anyhow!(
+            "Invalid log line format (malformed log level brackets): {line}"
+        ));
     }
+
+    // Extract parts
+    let timestamp_str = line[0..open_bracket].trim();
+    let level_str = line[open_bracket + 1..close_bracket].trim();
+    let message = line[close_bracket + 1..].trim().to_string();
+
+    let level = LogLevel::from_str(level_str).context("Failed to parse log level of log line")?;
+    Ok((timestamp_str, level, message))
 }
 
 impl Display for LogLine {
@@ -137,4 +164,48 @@ mod tests {
             "pop - New prio level: 0 (esp_rtos esp-rtos-0.2.0/src/run_queue.rs:292)"
         );
     }
+
+    #[test]
+    fn test_log_line_parsing_cycles() {
+        let mut cycle_clock = CycleClock::new(64_000_000);
+        let log_line = LogLine::from_str_cycles(
+            "6400 [INFO ] @EVENT_TRACE_FLUSH(core_id=0)",
+            &mut cycle_clock,
+        )
+        .expect("Failed to parse log line");
+
+        assert_eq!(log_line.timestamp.as_micros(), 100);
+        assert_eq!(log_line.message, "@EVENT_TRACE_FLUSH(core_id=0)");
+        assert!(LogLine::from_str_cycles("0.5 [INFO ] x", &mut cycle_clock).is_err());
+    }
+
+    #[test]
+    fn test_untimed_log_line_parsing() {
+        assert_eq!(
+            LogLine::parse_untimed("[WARN ] sensor timeout").unwrap(),
+            (LogLevel::Warn, "sensor timeout".to_string())
+        );
+        assert!(LogLine::parse_untimed("0.5 [INFO ] timed").is_err());
+        assert!(LogLine::parse_untimed("no brackets").is_err());
+    }
+
+    use proptest::strategy::Strategy;
+
+    proptest::proptest! {
+        #[test]
+        fn test_random_timestamps_never_panic(
+            timestamp in proptest::prop_oneof![
+                proptest::num::f64::ANY.prop_map(|secs| secs.to_string()),
+                "[-+0-9.eEinfNa]{0,24}",
+            ],
+            level in "(TRACE|DEBUG|INFO |WARN |ERROR|INFO)",
+            message in "\\PC{0,40}",
+        ) {
+            let _ = LogLine::from_str(&format!("{timestamp} [{level}] {message}"));
+            let _ = LogLine::from_str_cycles(
+                &format!("{timestamp} [{level}] {message}"),
+                &mut CycleClock::new(64_000_000),
+            );
+        }
+    }
 }
@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::perfetto_backend::{core_layout::PANEL_PID_BASE, trace_event::TracingEvent};
+
+/// Groups of metrics shown as one process each (`--metric-panels`), e.g.
+///
+/// ```json
+/// { "panels": [ { "name": "Motor", "metrics": ["motor.current", "motor.voltage"], "bands": true } ] }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricPanels {
+    #[serde(default)]
+    pub panels: Vec<MetricPanel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricPanel {
+    /// Process name of the panel
+    pub name: String,
+    /// Metric names (without unit) shown in the panel
+    pub metrics: Vec<String>,
+    /// Add running `name.min` / `name.max` tracks to the panel's metrics (like `--counter-bands`)
+    #[serde(default)]
+    pub bands: bool,
+}
+
+impl MetricPanels {
+    /// Load and check a panel config file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read metric panels {}", path.display()))?;
+        let panels: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid metric panels {}", path.display()))?;
+        panels
+            .validate()
+            .with_context(|| format!("Invalid metric panels {}", path.display()))?;
+        Ok(panels)
+    }
+
+    /// Every panel needs a unique name and every metric may only appear in one panel
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut panel_names = HashSet::new();
+        let mut metric_panels: HashMap<&str, &str> = HashMap::new();
+        for panel in &self.panels {
+            if panel.name.trim().is_empty() {
+                return Err(anyhow::anyhow!("Panel without name"));
+            }
+            if !panel_names.insert(panel.name.as_str()) {
+                return Err(anyhow::anyhow!("Panel '{}' is defined twice", panel.name));
+            }
+            for metric in &panel.metrics {
+                if let Some(other) = metric_panels.insert(metric, &panel.name) {
+                    return Err(anyhow::anyhow!(
+                        "Metric '{metric}' is listed in panel '{other}' and '{}'",
+                        panel.name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find(&self, metric: &str) -> Option<(usize, &MetricPanel)> {
+        self.panels
+            .iter()
+            .enumerate()
+            .find(|(_, panel)| panel.metrics.iter().any(|name| name == metric))
+    }
+
+    /// Pid of the panel process showing the metric
+    pub fn get_pid(&self, metric: &str) -> Option<u32> {
+        self.find(metric)
+            .map(|(index, _)| PANEL_PID_BASE + index as u32)
+    }
+
+    /// Check if the metric gets min / max tracks
+    pub fn has_bands(&self, metric: &str) -> bool {
+        self.find(metric).is_some_and(|(_, panel)| panel.bands)
+    }
+
+    /// Name and order the panel processes in the order of the config
+    pub fn metadata_events(&self) -> Vec<TracingEvent> {
+        let mut events = Vec::new();
+        for (index, panel) in self.panels.iter().enumerate() {
+            let pid = PANEL_PID_BASE + index as u32;
+            events.push(TracingEvent::Metadata {
+                name: "process_name".to_string(),
+                cat: None,
+                pid,
+                tid: None,
+                args: HashMap::from([("name".to_string(), panel.name.clone())]),
+            });
+            events.push(TracingEvent::Metadata {
+                name: "process_sort_index".to_string(),
+                cat: None,
+                pid,
+                tid: None,
+                args: HashMap::from([("sort_index".to_string(), index.to_string())]),
+            });
+        }
+        events
+    }
+
+    /// Metrics of the config that are not in the given set of observed metrics
+    pub fn unseen_metrics(&self, seen: &HashSet<String>) -> Vec<String> {
+        self.panels
+            .iter()
+            .flat_map(|panel| &panel.metrics)
+            .filter(|metric| !seen.contains(*metric))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_panels() {
+        let panels: MetricPanels = serde_json::from_str(
+            r#"{"panels": [
+                {"name": "Motor", "metrics": ["motor.current", "motor.voltage"], "bands": true},
+                {"name": "Power", "metrics": ["battery"]}
+            ]}"#,
+        )
+        .unwrap();
+        panels.validate().unwrap();
+        assert_eq!(panels.get_pid("motor.voltage"), Some(1000));
+        assert_eq!(panels.get_pid("battery"), Some(1001));
+        assert_eq!(panels.get_pid("temperature"), None);
+        assert!(panels.has_bands("motor.current"));
+        assert!(!panels.has_bands("battery"));
+        assert_eq!(panels.metadata_events().len(), 4);
+        assert_eq!(
+            panels.unseen_metrics(&HashSet::from(["motor.current".to_string()])),
+            vec!["motor.voltage".to_string(), "battery".to_string()]
+        );
+
+        let twice: MetricPanels = serde_json::from_str(
+            r#"{"panels": [{"name": "A", "metrics": ["x"]}, {"name": "B", "metrics": ["x"]}]}"#,
+        )
+        .unwrap();
+        assert!(twice.validate().is_err());
+        assert!(
+            serde_json::from_str::<MetricPanels>(
+                r#"{"panels": [{"name": "A", "metrics": [], "color": "good"}]}"#
+            )
+            .is_err()
+        );
+    }
+}
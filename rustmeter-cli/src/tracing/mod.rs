@@ -1,7 +1,11 @@
+pub mod duration_histogram;
 pub mod log_event;
 pub mod log_line;
+pub mod metric_panels;
+pub mod monitor_colors;
+pub mod name_filter;
+pub mod task;
 pub mod tracing_instance;
 
 mod core;
 mod executor;
-mod task;
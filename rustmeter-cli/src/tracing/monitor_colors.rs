@@ -0,0 +1,63 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::perfetto_backend::trace_event::CName;
+
+/// Colors of monitor slices by monitor name or by category (`--color-config`), e.g.
+///
+/// ```json
+/// { "monitors": { "sensor_read": "good" }, "categories": { "network": "yellow" } }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorColors {
+    #[serde(default)]
+    pub monitors: HashMap<String, CName>,
+    #[serde(default)]
+    pub categories: HashMap<String, CName>,
+}
+
+impl MonitorColors {
+    /// Load a color config file. Unknown color names are rejected with the list of valid ones
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read color config {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid color config {}", path.display()))
+    }
+
+    /// Color of a monitor slice: the color of the monitor itself, otherwise of its category
+    pub fn get(&self, name: &str, category: &str) -> Option<CName> {
+        self.monitors
+            .get(name)
+            .or_else(|| self.categories.get(category))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitor_colors() {
+        let colors: MonitorColors = serde_json::from_str(
+            r#"{"monitors": {"sensor_read": "good"}, "categories": {"network": "thread_state_iowait"}}"#,
+        )
+        .unwrap();
+        assert_eq!(colors.get("sensor_read", "network"), Some(CName::Good));
+        assert_eq!(
+            colors.get("send", "network"),
+            Some(CName::ThreadStateIowait)
+        );
+        assert_eq!(colors.get("send", "function_monitor"), None);
+
+        let error = serde_json::from_str::<MonitorColors>(r#"{"monitors": {"send": "pink"}}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown variant `pink`"), "{error}");
+        assert!(serde_json::from_str::<MonitorColors>(r#"{"monitor": {}}"#).is_err());
+    }
+}
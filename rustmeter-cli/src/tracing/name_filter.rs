@@ -0,0 +1,73 @@
+/// Include / exclude lists of glob patterns (`*` any characters, `?` one character) selecting
+/// which monitors or tasks are written to the trace
+#[derive(Debug, Clone, Default)]
+pub struct NameFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl NameFilter {
+    /// Check if a name passes the filter. Without include patterns every name is included;
+    /// an exclude pattern always wins over an include pattern
+    pub fn matches(&self, name: &str) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, name));
+        included && !self.exclude.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Match a name against a glob pattern with `*` and `?` wildcards
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Greedy matching, backtracking to the last `*` on a mismatch
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            // let the `*` take one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            last_star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("sensor_*", "sensor_read"));
+        assert!(glob_match("*::blink*", "firmware::blink_task"));
+        assert!(glob_match("ta?k", "task"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("sensor_*", "read_sensor"));
+        assert!(!glob_match("ta?k", "taak_"));
+    }
+
+    #[test]
+    fn test_exclude_wins_over_include() {
+        let filter = NameFilter {
+            include: vec!["sensor_*".to_string()],
+            exclude: vec!["*_debug".to_string()],
+        };
+        assert!(filter.matches("sensor_read"));
+        assert!(!filter.matches("sensor_debug"));
+        assert!(!filter.matches("uart_write"));
+
+        assert!(NameFilter::default().matches("anything"));
+    }
+}
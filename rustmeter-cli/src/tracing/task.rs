@@ -36,15 +36,133 @@
 
 use std::{collections::HashMap, fmt::Display};
 
-use crossbeam::channel::Sender;
-
 use crate::{
     elf_file::FirmwareAddressMap,
-    perfetto_backend::trace_event::TracingEvent,
+    perfetto_backend::{
+        event_sender::TraceEventSender,
+        trace_event::{CName, InstantScope, TracingArgsMap, TracingEvent},
+    },
     time::EmbassyTime,
-    tracing::log_event::{LogEvent, LogEventType},
+    tracing::{
+        log_event::{LogEvent, LogEventType},
+        name_filter::NameFilter,
+    },
 };
 
+/// How the task states are rendered in the trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TaskView {
+    /// Begin/End slices on the task's thread track (monitors nest below the states)
+    #[default]
+    Slices,
+    /// Async slices with one track per task and state, keeping the thread track for monitors
+    Async,
+}
+
+/// How the tasks of an executor are written to the trace
+#[derive(Debug, Clone)]
+pub struct TaskTraceOptions {
+    pub view: TaskView,
+    /// Tasks (by resolved name) written to the trace
+    pub filter: NameFilter,
+    /// Nesting depth of open monitors per task after which the oldest are force-closed
+    pub max_monitor_depth: usize,
+}
+
+/// What to do with a monitor start under the nesting limit of [`MonitorDepthGuard`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorAdmission {
+    Accept,
+    /// Drop the start (and later its end)
+    Reject,
+    /// Force-close this many of the oldest open monitors, then drop the start
+    ForceClose(usize),
+}
+
+/// Limits the nesting depth of the open monitors of one task (or of the code outside of tasks).
+///
+/// If the firmware loses monitor ends under load, the open monitors pile up and every later
+/// duration is wrong. At the limit the oldest half is force-closed and new starts are refused
+/// until the remaining monitors drained. If they lost their ends as well, the task gives up
+/// waiting after refusing another `max_depth` starts and force-closes the rest.
+#[derive(Debug, Clone)]
+pub struct MonitorDepthGuard {
+    max_depth: usize,
+    /// Starts are refused until no monitor is open anymore
+    draining: bool,
+    /// Starts refused while draining
+    rejected_starts: usize,
+    /// Names of refused starts whose end is still expected, innermost last (bounded by `max_depth`)
+    rejected_open: Vec<String>,
+}
+
+impl MonitorDepthGuard {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth: max_depth.max(1),
+            draining: false,
+            rejected_starts: 0,
+            rejected_open: Vec::new(),
+        }
+    }
+
+    /// Decide on the start of a monitor while `open` monitors are open
+    pub fn admit(&mut self, function_name: &str, open: usize) -> MonitorAdmission {
+        if self.draining && open == 0 {
+            self.draining = false;
+        }
+
+        let admission = if self.draining {
+            self.rejected_starts += 1;
+            if self.rejected_starts >= self.max_depth {
+                // the remaining monitors lost their ends as well
+                self.draining = false;
+                MonitorAdmission::ForceClose(open)
+            } else {
+                MonitorAdmission::Reject
+            }
+        } else if open >= self.max_depth {
+            self.draining = true;
+            self.rejected_starts = 1;
+            MonitorAdmission::ForceClose(open - self.max_depth / 2)
+        } else {
+            return MonitorAdmission::Accept;
+        };
+
+        self.rejected_open.push(function_name.to_string());
+        if self.rejected_open.len() > self.max_depth {
+            self.rejected_open.remove(0);
+        }
+        admission
+    }
+
+    /// Check (and forget) if the end belongs to a refused start
+    pub fn take_rejected_end(&mut self, function_name: &str) -> bool {
+        match self
+            .rejected_open
+            .iter()
+            .rposition(|name| name == function_name)
+        {
+            Some(index) => {
+                self.rejected_open.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check if the end of a refused start with this name is still expected
+    pub fn expects_rejected_end(&self, function_name: &str) -> bool {
+        self.rejected_open.iter().any(|name| name == function_name)
+    }
+
+    /// Starts are currently refused
+    #[cfg(test)]
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum TaskTraceState {
     Spawned,
@@ -77,13 +195,30 @@ pub struct TaskTracing {
     task_id: u32,
     executor_id: u32,
     core_id: u8,
+    display_name: String,
+    task_view: TaskView,
 
-    trace_event_sender: Sender<TracingEvent>,
+    trace_event_sender: TraceEventSender,
+    /// The task passed the task filter (otherwise it is tracked, but nothing is sent)
+    traced: bool,
 
     /// Current state of the task
     state: TaskTraceState,
     /// Timestamp when the current state started
     state_start_time: EmbassyTime,
+    /// Time (in µs) spent in completed running slices
+    running_us: u128,
+    /// Number of times the task was polled
+    poll_count: u64,
+
+    /// Deadline (in µs) of the embassy-time timer the task is currently waiting on
+    pending_timer_deadline: Option<u64>,
+    /// Open resource slices (`Blocked on ...` / `Holding ...`), innermost last
+    resource_slices: Vec<String>,
+    /// Why the task is about to yield, added to the end of its running slice (slice view only)
+    yield_reason: Option<String>,
+    /// Nesting limit of the monitors started in this task
+    monitor_guard: MonitorDepthGuard,
 }
 
 impl TaskTracing {
@@ -91,76 +226,310 @@ impl TaskTracing {
         task_id: u32,
         executor_id: u32,
         core_id: u8,
-        trace_event_sender: Sender<TracingEvent>,
+        trace_event_sender: TraceEventSender,
         firmware_addr_map: &FirmwareAddressMap,
         created_at: EmbassyTime,
+        options: &TaskTraceOptions,
     ) -> Self {
-        // try to find task name from global firmware address map
-        let task_name = firmware_addr_map.get_symbol_name(task_id as u64);
-        let display_name = match task_name.as_ref() {
-            Some(name) => name.clone(),
-            None => format!("Task 0x{task_id:X}"),
+        let display_name = task_display_name(firmware_addr_map, task_id);
+
+        let task = TaskTracing {
+            task_id,
+            executor_id,
+            core_id,
+            traced: options.filter.matches(&display_name),
+            display_name,
+            task_view: options.view,
+            trace_event_sender,
+            state: TaskTraceState::Spawned,
+            state_start_time: created_at,
+            running_us: 0,
+            poll_count: 0,
+            pending_timer_deadline: None,
+            resource_slices: Vec::new(),
+            monitor_guard: MonitorDepthGuard::new(options.max_monitor_depth),
+            yield_reason: None,
         };
 
         // Send task metadata
-        let _ = trace_event_sender.send(TracingEvent::Metadata {
+        task.send(TracingEvent::Metadata {
             name: "thread_name".to_string(),
             cat: None,
-            args: HashMap::from([("name".to_string(), display_name)]),
+            args: HashMap::from([("name".to_string(), task.display_name.clone())]),
             pid: executor_id,
             tid: Some(task_id),
         });
 
         // Send Begin trace event for new state SPAWNED
-        let _ = trace_event_sender.send(TracingEvent::Begin {
-            name: TaskTraceState::Spawned.to_string(),
-            cat: None,
-            ts: created_at.as_micros(),
-            pid: executor_id,
-            tid: Some(task_id),
-            args: HashMap::new(),
-        });
+        task.send_state_begin(created_at);
+        task
+    }
 
-        TaskTracing {
-            task_id,
-            executor_id,
-            core_id,
-            trace_event_sender,
-            state: TaskTraceState::Spawned,
-            state_start_time: created_at,
+    /// Send a trace event of this task (dropped if the task is filtered out)
+    fn send(&self, event: TracingEvent) {
+        if self.traced {
+            let _ = self.trace_event_sender.send(event);
         }
     }
 
-    pub fn get_pid(&self) -> u32 {
-        self.executor_id
+    /// Async id of the task (unique per executor + task, so tasks never share a track)
+    fn get_async_id(&self) -> String {
+        format!(
+            "0x{:X}",
+            ((self.executor_id as u64) << 32) | self.task_id as u64
+        )
     }
 
-    /// Set a new state for the task, sending statistics as needed
-    fn set_new_state(&mut self, new_state: TaskTraceState, timestamp: EmbassyTime) {
-        if self.state != new_state {
-            // Send End trace event for state change
-            let _ = self.trace_event_sender.send(TracingEvent::End {
-                name: None,
+    /// Name of the async slice of the current state (async tracks are named after their slices)
+    fn get_async_name(&self) -> String {
+        format!("{}: {}", self.display_name, self.state)
+    }
+
+    /// Send the begin event of the current state
+    fn send_state_begin(&self, timestamp: EmbassyTime) {
+        let event = match self.task_view {
+            TaskView::Slices => TracingEvent::Begin {
+                name: self.state.to_string(),
                 cat: None,
+                ts: timestamp.as_micros(),
                 pid: self.get_pid(),
                 tid: Some(self.task_id),
+                args: HashMap::new(),
+                cname: None,
+            },
+            TaskView::Async => TracingEvent::AsyncBegin {
+                name: self.get_async_name(),
+                cat: Some("task_state".to_string()),
+                id: self.get_async_id(),
+                pid: self.get_pid(),
                 ts: timestamp.as_micros(),
                 args: HashMap::new(),
-            });
+            },
+        };
+        self.send(event);
+    }
 
-            // Send Begin trace event for new state
-            let _ = self.trace_event_sender.send(TracingEvent::Begin {
-                name: new_state.to_string(),
+    /// Send the end event of the current state (with the reason the task yielded, if known)
+    fn send_state_end(&self, timestamp: EmbassyTime, yield_reason: Option<String>) {
+        let event = match self.task_view {
+            TaskView::Slices => TracingEvent::End {
+                name: None,
                 cat: None,
-                ts: timestamp.as_micros(),
                 pid: self.get_pid(),
                 tid: Some(self.task_id),
-                args: HashMap::new(),
-            });
+                ts: timestamp.as_micros(),
+                args: match yield_reason {
+                    Some(reason) => HashMap::from([("yield_reason".to_string(), reason)]),
+                    None => HashMap::new(),
+                },
+            },
+            TaskView::Async => TracingEvent::AsyncEnd {
+                name: self.get_async_name(),
+                cat: Some("task_state".to_string()),
+                id: self.get_async_id(),
+                pid: self.get_pid(),
+                ts: timestamp.as_micros(),
+            },
+        };
+        self.send(event);
+    }
+
+    /// Check if the task has ended (its future completed)
+    pub fn has_ended(&self) -> bool {
+        self.state == TaskTraceState::Ended
+    }
+
+    /// Close the slice of the current state (the task is no longer tracked afterwards)
+    pub fn finish(&self, timestamp: EmbassyTime) {
+        for name in self.resource_slices.iter().rev() {
+            self.send_resource_end(name.clone(), timestamp);
+        }
+        self.send_state_end(timestamp, None);
+    }
+
+    /// Leave the running state without an exec end event of this task (it was lost)
+    pub fn stop_running(&mut self, timestamp: EmbassyTime) {
+        if self.state == TaskTraceState::Running {
+            self.set_new_state(TaskTraceState::Idle, timestamp);
+        }
+    }
+
+    /// Check if the task is ready and waiting to be polled
+    pub fn is_ready(&self) -> bool {
+        self.state == TaskTraceState::Waiting
+    }
+
+    /// Total running time (in µs) up to the given timestamp
+    pub fn get_running_us(&self, until: EmbassyTime) -> u128 {
+        match self.state {
+            TaskTraceState::Running => {
+                self.running_us
+                    + until
+                        .as_micros()
+                        .saturating_sub(self.state_start_time.as_micros())
+            }
+            _ => self.running_us,
+        }
+    }
+
+    /// Number of times the task was polled so far
+    pub fn get_poll_count(&self) -> u64 {
+        self.poll_count
+    }
+
+    /// Display name of the task (its symbol name or `Task 0x...`)
+    pub fn get_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn get_monitor_guard_mut(&mut self) -> &mut MonitorDepthGuard {
+        &mut self.monitor_guard
+    }
+
+    pub fn get_monitor_guard(&self) -> &MonitorDepthGuard {
+        &self.monitor_guard
+    }
+
+    pub fn get_task_id(&self) -> u32 {
+        self.task_id
+    }
+
+    pub fn get_pid(&self) -> u32 {
+        self.executor_id
+    }
+
+    /// Send a marker on this task's track
+    fn send_marker(&self, name: &str, timestamp: EmbassyTime, args: TracingArgsMap<String>) {
+        self.send(TracingEvent::Instant {
+            name: name.to_string(),
+            cat: Some("timer".to_string()),
+            ts: timestamp.as_micros(),
+            pid: Some(self.get_pid()),
+            tid: Some(self.task_id),
+            scope: InstantScope::Thread,
+            args,
+            cname: CName::Good,
+        });
+    }
+
+    /// The task started waiting on a timer with the given deadline
+    pub fn on_timer_set(&mut self, deadline_us: u64, timestamp: EmbassyTime) {
+        self.pending_timer_deadline = Some(deadline_us);
+        self.send_marker(
+            "Timer set",
+            timestamp,
+            HashMap::from([("deadline_us".to_string(), deadline_us.to_string())]),
+        );
+    }
+
+    /// The task resumed after its timer fired
+    pub fn on_timer_fired(&mut self, deadline_us: u64, timestamp: EmbassyTime) {
+        self.pending_timer_deadline = None;
+        self.send_marker(
+            "Timer fired",
+            timestamp,
+            HashMap::from([
+                ("deadline_us".to_string(), deadline_us.to_string()),
+                (
+                    "latency_us".to_string(),
+                    latency_us(deadline_us, timestamp).to_string(),
+                ),
+            ]),
+        );
+    }
+
+    /// Open a resource slice. They live on an async track of the task, because waiting for a
+    /// lock spans several polls and would not nest with the state slices
+    fn send_resource_begin(&mut self, name: String, timestamp: EmbassyTime) {
+        self.send(TracingEvent::AsyncBegin {
+            name: name.clone(),
+            cat: Some("resource".to_string()),
+            id: self.get_async_id(),
+            pid: self.get_pid(),
+            ts: timestamp.as_micros(),
+            args: HashMap::new(),
+        });
+        self.resource_slices.push(name);
+    }
+
+    fn send_resource_end(&self, name: String, timestamp: EmbassyTime) {
+        self.send(TracingEvent::AsyncEnd {
+            name,
+            cat: Some("resource".to_string()),
+            id: self.get_async_id(),
+            pid: self.get_pid(),
+            ts: timestamp.as_micros(),
+        });
+    }
 
-            // update state
+    /// Close the innermost open resource slice with the given name. The slices opened after it
+    /// are ended and begun again, so the slices of the track stay nested
+    fn close_resource_slice(&mut self, name: &str, timestamp: EmbassyTime) {
+        let Some(index) = self.resource_slices.iter().rposition(|open| open == name) else {
+            return;
+        };
+
+        let reopened = self.resource_slices.split_off(index + 1);
+        for open in reopened.iter().rev() {
+            self.send_resource_end(open.clone(), timestamp);
+        }
+        if let Some(name) = self.resource_slices.pop() {
+            self.send_resource_end(name, timestamp);
+        }
+        for open in reopened {
+            self.send_resource_begin(open, timestamp);
+        }
+    }
+
+    /// The task started waiting for a traced lock
+    pub fn on_resource_wait(&mut self, resource: &str, timestamp: EmbassyTime) {
+        self.send_resource_begin(format!("Blocked on {resource}"), timestamp);
+    }
+
+    /// The task got the traced lock it waited for
+    pub fn on_resource_acquired(&mut self, resource: &str, timestamp: EmbassyTime) {
+        self.close_resource_slice(&format!("Blocked on {resource}"), timestamp);
+        self.send_resource_begin(format!("Holding {resource}"), timestamp);
+    }
+
+    /// The task dropped the guard of a traced lock (guards released by another task are ignored)
+    pub fn on_resource_released(&mut self, resource: &str, timestamp: EmbassyTime) {
+        self.close_resource_slice(&format!("Holding {resource}"), timestamp);
+    }
+
+    /// The task stopped waiting for a traced lock (e.g. a timeout or a lost `select`)
+    pub fn on_resource_abandoned(&mut self, resource: &str, timestamp: EmbassyTime) {
+        self.close_resource_slice(&format!("Blocked on {resource}"), timestamp);
+    }
+
+    /// The task announced why it is about to yield (only kept while it is running)
+    pub fn on_yield_reason(&mut self, reason: String) {
+        if self.state == TaskTraceState::Running {
+            self.yield_reason = Some(reason);
+        }
+    }
+
+    /// Set a new state for the task, sending statistics as needed
+    fn set_new_state(&mut self, new_state: TaskTraceState, timestamp: EmbassyTime) {
+        if self.state != new_state {
+            // Send End trace event for state change. A preempted task did not yield, so its
+            // reason is kept for the end of the running slice after it resumes
+            let yield_reason = match new_state {
+                TaskTraceState::Preempted { .. } => None,
+                _ => self.yield_reason.take(),
+            };
+            self.send_state_end(timestamp, yield_reason);
+
+            if self.state == TaskTraceState::Running {
+                self.running_us += timestamp
+                    .as_micros()
+                    .saturating_sub(self.state_start_time.as_micros());
+            }
+
+            // update state and send Begin trace event for new state
             self.state = new_state;
             self.state_start_time = timestamp;
+            self.send_state_begin(timestamp);
         }
     }
 
@@ -169,17 +538,18 @@ impl TaskTracing {
         // Check if we get preempted
         if self.state == TaskTraceState::Running {
             // check if another executor on the same core_id is beginning to poll (that would preempt us because only one executor can run on a core at a time)
-            if let LogEventType::EventEmbassyPollStart { executor_id, .. } = log_event.event_type {
-                if log_event.core_id == self.core_id && executor_id != self.executor_id {
-                    // preempted by another executor
-                    self.set_new_state(
-                        TaskTraceState::Preempted {
-                            by_executor_id: executor_id,
-                        },
-                        log_event.timestamp,
-                    );
-                    return;
-                }
+            if let LogEventType::EventEmbassyPollStart { executor_id, .. } = log_event.event_type
+                && log_event.core_id == self.core_id
+                && executor_id != self.executor_id
+            {
+                // preempted by another executor
+                self.set_new_state(
+                    TaskTraceState::Preempted {
+                        by_executor_id: executor_id,
+                    },
+                    log_event.timestamp,
+                );
+                return;
             }
         }
 
@@ -187,12 +557,11 @@ impl TaskTracing {
         if let TaskTraceState::Preempted { by_executor_id } = self.state {
             // check if the other executor goes to idle
             if let LogEventType::EventEmbassyExecutorIdle { executor_id, .. } = log_event.event_type
+                && executor_id == by_executor_id
             {
-                if executor_id == by_executor_id {
-                    // resume our task to running
-                    self.set_new_state(TaskTraceState::Running, log_event.timestamp);
-                    return;
-                }
+                // resume our task to running
+                self.set_new_state(TaskTraceState::Running, log_event.timestamp);
+                return;
             }
         }
 
@@ -220,6 +589,7 @@ impl TaskTracing {
                     TaskTraceState::Waiting => {
                         if let LogEventType::EventEmbassyTaskExecBegin { .. } = log_event.event_type
                         {
+                            self.poll_count += 1;
                             self.set_new_state(TaskTraceState::Running, log_event.timestamp);
                         }
                     }
@@ -244,6 +614,22 @@ impl TaskTracing {
                             log_event.event_type
                         {
                             self.set_new_state(TaskTraceState::Waiting, log_event.timestamp);
+
+                            // Correlate the wakeup with the timer the task was waiting on
+                            if let Some(deadline_us) = self.pending_timer_deadline {
+                                self.send_marker(
+                                    "Timer ready",
+                                    log_event.timestamp,
+                                    HashMap::from([
+                                        ("deadline_us".to_string(), deadline_us.to_string()),
+                                        (
+                                            "wakeup_latency_us".to_string(),
+                                            latency_us(deadline_us, log_event.timestamp)
+                                                .to_string(),
+                                        ),
+                                    ]),
+                                );
+                            }
                         }
                     }
                     TaskTraceState::Ended => {
@@ -255,3 +641,32 @@ impl TaskTracing {
         }
     }
 }
+
+/// Name of a task in the trace: its symbol from the firmware address map or its address
+pub fn task_display_name(firmware_addr_map: &FirmwareAddressMap, task_id: u32) -> String {
+    match firmware_addr_map.get_symbol_name(task_id as u64) {
+        Some(name) => name,
+        None => format!("Task 0x{task_id:X}"),
+    }
+}
+
+/// Label of a yield reason code: the built-in `YieldReason`s of the beacon, then the names given
+/// on the command line
+pub fn yield_reason_label(code: u8, custom_names: &HashMap<u8, String>) -> String {
+    match code {
+        1 => "timer".to_string(),
+        2 => "io".to_string(),
+        3 => "channel".to_string(),
+        4 => "lock".to_string(),
+        5 => "signal".to_string(),
+        _ => custom_names
+            .get(&code)
+            .cloned()
+            .unwrap_or_else(|| format!("code {code}")),
+    }
+}
+
+/// Signed latency (in µs) of `timestamp` after the given deadline
+fn latency_us(deadline_us: u64, timestamp: EmbassyTime) -> i128 {
+    timestamp.as_micros() as i128 - deadline_us as i128
+}
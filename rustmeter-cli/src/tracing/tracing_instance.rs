@@ -1,27 +1,160 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, atomic::AtomicU64},
+};
 
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::Receiver;
 
 use crate::{
     elf_file::FirmwareAddressMap,
-    perfetto_backend::trace_event::{InstantScope, TracingEvent},
-    tracing::{core::CoreTracing, log_event::LogEvent, log_line::LogLine},
+    perfetto_backend::{
+        core_layout::DMA_PID,
+        event_sender::TraceEventSender,
+        trace_event::{CName, FlowBinding, InstantScope, TracingArgsMap, TracingEvent},
+    },
+    time::EmbassyTime,
+    tracing::{
+        core::{CoreTracing, metric_track_name},
+        duration_histogram::DurationHistogram,
+        log_event::{LogEvent, LogEventType},
+        log_line::{LogLevel, LogLine},
+        metric_panels::MetricPanels,
+        monitor_colors::MonitorColors,
+        name_filter::NameFilter,
+        task::{TaskView, task_display_name},
+    },
 };
 
+/// Options changing how the tracing instance renders the incoming log events
+#[derive(Debug, Clone, Default)]
+pub struct TracingOptions {
+    /// Emit running min/max counters (`name.min`, `name.max`) next to every metric
+    pub counter_bands: bool,
+    /// Keep completed monitors in memory (see [`TracingInstance::drain_completed_monitors`])
+    pub collect_monitors: bool,
+    /// Flag tasks preempted for at least this long (in µs) inside a monitored scope as
+    /// potential priority inversion (heuristic, disabled with `None`)
+    pub inversion_threshold_us: Option<u64>,
+    /// Maximum number of trace events buffered for the file writer. Further events are dropped
+    /// until the writer catches up (unbounded with `None`)
+    pub event_buffer_capacity: Option<usize>,
+    /// Reject tracing events whose timestamp lies more than this many µs before the latest
+    /// timestamp (likely a decoder desync, disabled with `None`)
+    pub out_of_order_tolerance_us: Option<u64>,
+    /// Force-close the oldest open monitors of a task once this many are nested (lost monitor
+    /// ends), 64 with `None`
+    pub max_monitor_depth: Option<usize>,
+    /// Treat a restart of the device time as target reset and continue the timeline after it
+    /// (`--follow`)
+    pub follow_resets: bool,
+    /// Render task states as thread slices (default) or as async tracks
+    pub task_view: TaskView,
+    /// Monitors written to the trace (all of them are still tracked and counted)
+    pub monitor_filter: NameFilter,
+    /// Tasks written to the trace, matched against their resolved names
+    pub task_filter: NameFilter,
+    /// Merge executor idle periods shorter than this many µs into the surrounding busy slice
+    /// (disabled with `None`)
+    pub idle_merge_threshold_us: Option<u64>,
+    /// Names of application defined yield reason codes (`--yield-reason`)
+    pub yield_reason_names: HashMap<u8, String>,
+    /// Leave monitors out of the statistics (budgets, summaries, reports) that start within this
+    /// many µs after the first received timestamp. They are still written to the trace
+    pub warmup_us: Option<u64>,
+    /// Colors of monitor slices by name or category (`--color-config`)
+    pub monitor_colors: MonitorColors,
+    /// Metrics grouped into panel processes (`--metric-panels`)
+    pub metric_panels: MetricPanels,
+    /// Show a monitor that calls itself as one slice with a `recursion_depth` arg instead of a
+    /// nested slice per call
+    pub collapse_recursion: bool,
+}
+
+/// Consecutive out-of-order events after which the new timeline is accepted (e.g. target reset)
+const OUT_OF_ORDER_RESYNC_COUNT: usize = 16;
+
+/// Jump back of the device time (in µs) that is read as target reset with `follow_resets`
+const TARGET_RESET_JUMP_US: u128 = 1000;
+
+/// A completed function / scope monitor
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInterval {
+    pub name: String,
+    pub core_id: u8,
+    /// Task that was running when the monitor started (`None` outside of embassy tasks)
+    pub task_id: Option<u32>,
+    /// Category of the monitor (`None` for the default `function_monitor`)
+    pub category: Option<String>,
+    pub start: EmbassyTime,
+    pub duration_us: u128,
+    /// Deepest recursion collapsed into the slice of this call (`collapse_recursion`, only set on
+    /// the outermost call)
+    pub recursion_depth: Option<u32>,
+}
+
+/// Busy time per core and running time per task (in µs) of a session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageStats {
+    pub core_busy_us: BTreeMap<u8, u128>,
+    /// Running time per (executor name, task name)
+    pub task_running_us: BTreeMap<(String, String), u128>,
+    /// Number of polls per (executor name, task name)
+    pub task_polls: BTreeMap<(String, String), u64>,
+}
+
 /// This container holds the state for the entire tracing system (represents something like the controller)
 pub struct TracingInstance {
     firmware_addr_map: FirmwareAddressMap,
+    options: TracingOptions,
 
     trace_event_receiver: Receiver<TracingEvent>,
-    trace_event_sender: Sender<TracingEvent>,
+    trace_event_sender: TraceEventSender,
 
     cores: Vec<CoreTracing>,
+
+    /// Observed (min, max) per metric name (only tracked with `counter_bands`)
+    metric_bands: HashMap<String, (f64, f64)>,
+    /// Names of the metrics received so far (only tracked with `metric_panels`)
+    seen_metrics: HashSet<String>,
+
+    /// Timestamp of the first log event or log line
+    first_timestamp: Option<EmbassyTime>,
+    /// Timestamp of the latest log event or log line
+    latest_timestamp: Option<EmbassyTime>,
+    /// Whether the target flushed its trace after the last tracing event
+    trace_flushed: bool,
+
+    /// Number of tracing events and log lines rejected because their timestamp went backwards
+    rejected_events: u64,
+    /// Number of consecutively rejected events (resynchronize once it gets too large)
+    consecutive_rejected_events: usize,
+    /// Number of monitor ends dropped because only another core had the monitor open
+    mismatched_monitor_ends: u64,
+
+    /// DMA channels with a running transfer
+    open_dma_transfers: HashSet<u8>,
+    /// DMA channels whose thread is already named
+    named_dma_channels: HashSet<u8>,
+
+    /// Open wakeup flows per (executor id, task id), closed when the task gets polled
+    pending_wakeup_flows: HashMap<(u32, u32), u64>,
+    next_flow_id: u64,
+
+    /// Offset (in µs) added to device timestamps to continue the timeline after target resets
+    time_base_us: u64,
+    /// Number of detected target resets
+    target_resets: u64,
+    /// Usage of the runs before the latest target reset
+    usage_before_reset: UsageStats,
+    /// Monitor duration statistics of the runs before the latest target reset
+    monitor_durations_before_reset: HashMap<String, DurationHistogram>,
 }
 
 impl TracingInstance {
     /// Create a new tracing instance
-    pub fn new(firmware_addr_map: FirmwareAddressMap) -> Self {
-        let (trace_event_sender, trace_event_receiver) = crossbeam::channel::unbounded();
+    pub fn new(firmware_addr_map: FirmwareAddressMap, options: TracingOptions) -> Self {
+        let (trace_event_sender, trace_event_receiver) =
+            TraceEventSender::channel(options.event_buffer_capacity);
 
         // send core overview metadata
         let _ = trace_event_sender.send(TracingEvent::Metadata {
@@ -31,12 +164,32 @@ impl TracingInstance {
             pid: 0,
             tid: None,
         });
+        for panel_metadata in options.metric_panels.metadata_events() {
+            let _ = trace_event_sender.send(panel_metadata);
+        }
 
         TracingInstance {
             firmware_addr_map,
+            options,
             trace_event_receiver,
             trace_event_sender,
             cores: Vec::new(),
+            metric_bands: HashMap::new(),
+            seen_metrics: HashSet::new(),
+            first_timestamp: None,
+            latest_timestamp: None,
+            trace_flushed: false,
+            rejected_events: 0,
+            consecutive_rejected_events: 0,
+            mismatched_monitor_ends: 0,
+            open_dma_transfers: HashSet::new(),
+            named_dma_channels: HashSet::new(),
+            pending_wakeup_flows: HashMap::new(),
+            next_flow_id: 0,
+            time_base_us: 0,
+            target_resets: 0,
+            usage_before_reset: UsageStats::default(),
+            monitor_durations_before_reset: HashMap::new(),
         }
     }
 
@@ -44,8 +197,133 @@ impl TracingInstance {
         self.trace_event_receiver.clone()
     }
 
+    /// Number of trace events dropped because the writer could not keep up
+    pub fn get_dropped_events(&self) -> u64 {
+        self.trace_event_sender.get_dropped_events()
+    }
+
+    /// Shared counter of the trace events dropped because the consumers could not keep up
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    pub fn get_dropped_events_counter(&self) -> Arc<AtomicU64> {
+        self.trace_event_sender.get_dropped_events_counter()
+    }
+
+    /// Number of tasks currently tracked over all cores and executors
+    #[cfg(test)]
+    fn get_tracked_task_count(&self) -> usize {
+        self.cores
+            .iter()
+            .map(|core| core.get_tracked_task_count())
+            .sum()
+    }
+
+    /// Number of executors currently tracked over all cores
+    #[cfg(test)]
+    fn get_tracked_executor_count(&self) -> usize {
+        self.cores
+            .iter()
+            .map(|core| core.get_tracked_executor_count())
+            .sum()
+    }
+
+    /// Number of open monitors over all cores
+    #[cfg(test)]
+    fn get_monitor_depth(&self) -> usize {
+        self.cores.iter().map(|core| core.get_monitor_depth()).sum()
+    }
+
+    /// Check if the task (executor id, task id) refuses monitor starts on any core
+    #[cfg(test)]
+    fn is_rejecting_monitors(&mut self, task: Option<(u32, u32)>) -> bool {
+        self.cores
+            .iter_mut()
+            .any(|core| core.is_rejecting_monitors(task))
+    }
+
     /// Update the tracing instance (and everything underlying) with a new log event
     pub fn update(&mut self, log_event: &LogEvent) {
+        let shifted_log_event;
+        let log_event = if self.options.follow_resets {
+            shifted_log_event = LogEvent {
+                timestamp: self.follow_timestamp(log_event.timestamp),
+                ..log_event.clone()
+            };
+            &shifted_log_event
+        } else {
+            log_event
+        };
+
+        if self.is_out_of_order(log_event.timestamp) {
+            return;
+        }
+        self.first_timestamp.get_or_insert(log_event.timestamp);
+        self.latest_timestamp = Some(log_event.timestamp);
+
+        // A flush marks a clean end as long as no tracing event follows it
+        if log_event.event_type == LogEventType::EventTraceFlush {
+            self.trace_flushed = true;
+            let _ = self.trace_event_sender.send(TracingEvent::Instant {
+                name: "Trace flushed".to_string(),
+                cat: Some("rustmeter".to_string()),
+                ts: log_event.timestamp.as_micros(),
+                pid: None,
+                tid: None,
+                scope: InstantScope::Global,
+                args: HashMap::from([("core".to_string(), log_event.core_id.to_string())]),
+                cname: CName::Good,
+            });
+            return;
+        }
+        self.trace_flushed = false;
+
+        // Markers are global annotations and not bound to any core track
+        if let LogEventType::EventMarker { name } = &log_event.event_type {
+            let _ = self.trace_event_sender.send(TracingEvent::Instant {
+                name: name.to_string(),
+                cat: Some("marker".to_string()),
+                ts: log_event.timestamp.as_micros(),
+                pid: None,
+                tid: None,
+                scope: InstantScope::Global,
+                args: HashMap::from([("core".to_string(), log_event.core_id.to_string())]),
+                cname: CName::Yellow,
+            });
+            return;
+        }
+
+        // DMA transfers run next to the CPU and are not bound to any core track either
+        match log_event.event_type {
+            LogEventType::EventDmaBegin { channel } => {
+                self.on_dma_begin(channel, log_event);
+                return;
+            }
+            LogEventType::EventDmaEnd { channel } => {
+                self.end_dma_transfer(channel, log_event.timestamp, HashMap::new());
+                return;
+            }
+            _ => {}
+        }
+
+        // A task can only be spawned again after it ended. Spawning a live one means the target
+        // restarted without its clock starting over (soft reset)
+        if self.options.follow_resets
+            && let LogEventType::EventEmbassyTaskNew {
+                executor_id,
+                task_id,
+            } = log_event.event_type
+            && self
+                .cores
+                .iter()
+                .any(|core| core.has_live_task(executor_id, task_id))
+        {
+            self.on_target_reset(log_event.timestamp, "task spawned again");
+        }
+
+        if let Some(start_core_id) = self.get_monitor_start_core(log_event) {
+            self.on_mismatched_monitor_end(log_event, start_core_id);
+            return;
+        }
+
         // Check if we have a core for this event's core id
         let core_exists = self
             .cores
@@ -58,18 +336,569 @@ impl TracingInstance {
                 log_event.core_id,
                 self.firmware_addr_map.clone(),
                 self.trace_event_sender.clone(),
+                self.options.clone(),
+                self.get_warmup_end(),
             ));
         }
 
+        // The waker has to be captured before the event changes any state
+        if let LogEventType::EventEmbassyTaskReadyBegin {
+            executor_id,
+            task_id,
+        } = log_event.event_type
+        {
+            self.start_wakeup_flow(log_event, executor_id, task_id);
+        }
+
         // Update all cores
         for core in &mut self.cores {
             core.update(log_event);
             // TODO: Only update the core that matches the log event's core id???
         }
+
+        // Close the wakeup flow once the task slice has begun (the flow binds to it)
+        match log_event.event_type {
+            LogEventType::EventEmbassyTaskExecBegin {
+                executor_id,
+                task_id,
+            } => {
+                if let Some(id) = self.pending_wakeup_flows.remove(&(executor_id, task_id)) {
+                    let _ = self.trace_event_sender.send(TracingEvent::FlowEnd {
+                        name: "Wakeup".to_string(),
+                        cat: Some("wakeup".to_string()),
+                        id,
+                        pid: executor_id,
+                        tid: task_id,
+                        ts: log_event.timestamp.as_micros(),
+                        bp: FlowBinding::Enclosing,
+                    });
+                }
+            }
+            LogEventType::EventEmbassyTaskEnd {
+                executor_id,
+                task_id,
+            } => {
+                self.pending_wakeup_flows.remove(&(executor_id, task_id));
+            }
+            _ => {}
+        }
+
+        if let LogEventType::EventMetric { name, value, unit } = &log_event.event_type {
+            if !self.options.metric_panels.panels.is_empty() && !self.seen_metrics.contains(name) {
+                self.seen_metrics.insert(name.to_string());
+            }
+
+            // Update min/max bands of metrics
+            if self.options.counter_bands || self.options.metric_panels.has_bands(name) {
+                self.update_metric_band(name, unit.as_deref(), *value, log_event);
+            }
+        }
+    }
+
+    /// Core of the open start of a monitor end that arrived from another core, if any.
+    ///
+    /// Start and end carry the core they were logged on, because the events of all cores (and
+    /// their interrupts) share one stream and the same monitor may be open on several cores at
+    /// once. An end without an open start on its own core is most likely the end of a lost or
+    /// force-closed start, rarely of code that moved cores (a thread of a preemptive RTOS). It
+    /// must not close the live monitor of another core
+    fn get_monitor_start_core(&self, log_event: &LogEvent) -> Option<u8> {
+        let LogEventType::EventMonitorEnd { function_name, .. } = &log_event.event_type else {
+            return None;
+        };
+        let is_open_on = |core: &CoreTracing| core.has_open_monitor(function_name);
+        if self
+            .cores
+            .iter()
+            .any(|core| core.get_core_id() == log_event.core_id && is_open_on(core))
+        {
+            return None; // the usual case
+        }
+        self.cores
+            .iter()
+            .find(|core| is_open_on(core))
+            .map(|core| core.get_core_id())
+    }
+
+    /// Drop a monitor end whose start is only open on another core and mark it with a warning
+    fn on_mismatched_monitor_end(&mut self, log_event: &LogEvent, start_core_id: u8) {
+        let LogEventType::EventMonitorEnd { function_name, .. } = &log_event.event_type else {
+            return;
+        };
+        self.mismatched_monitor_ends += 1;
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Monitor end from other core".to_string(),
+            cat: Some("function_monitor".to_string()),
+            ts: log_event.timestamp.as_micros(),
+            pid: Some(0),
+            tid: Some(log_event.core_id as u32),
+            scope: InstantScope::Thread,
+            args: HashMap::from([
+                ("function_name".to_string(), function_name.clone()),
+                ("open_on_core".to_string(), start_core_id.to_string()),
+            ]),
+            cname: CName::Terrible,
+        });
+    }
+
+    /// Check if a task passes the task filter (filtered tasks get no track, so no flow may end or
+    /// start on it either)
+    fn is_task_traced(&self, task_id: u32) -> bool {
+        self.options
+            .task_filter
+            .matches(&task_display_name(&self.firmware_addr_map, task_id))
+    }
+
+    /// Start a flow arrow from whatever readied the task to its next execution. The waker is the
+    /// task running on the event's core or, if none is running, an interrupt. An interrupt has no
+    /// slice to anchor the arrow to, so a short one is added on the core overview track. Flows
+    /// from or to a task that is filtered out are skipped
+    fn start_wakeup_flow(&mut self, log_event: &LogEvent, executor_id: u32, task_id: u32) {
+        if self
+            .pending_wakeup_flows
+            .contains_key(&(executor_id, task_id))
+        {
+            return; // already readied, the first waker counts
+        }
+        if !self.is_task_traced(task_id) {
+            return;
+        }
+
+        let ts = log_event.timestamp.as_micros();
+        let waker = self
+            .cores
+            .iter()
+            .find(|core| core.get_core_id() == log_event.core_id)
+            .and_then(|core| core.get_running_task());
+        let (pid, tid) = match waker {
+            Some((_, waker_task_id)) if !self.is_task_traced(waker_task_id) => return,
+            Some(waker) => waker,
+            None => {
+                let _ = self.trace_event_sender.send(TracingEvent::Complete {
+                    name: "Wake (ISR)".to_string(),
+                    cat: Some("wakeup".to_string()),
+                    pid: 0,
+                    tid: log_event.core_id as u32,
+                    ts,
+                    dur: 1,
+                    args: HashMap::new(),
+                });
+                (0, log_event.core_id as u32)
+            }
+        };
+
+        let id = self.next_flow_id;
+        self.next_flow_id += 1;
+        self.pending_wakeup_flows.insert((executor_id, task_id), id);
+        let _ = self.trace_event_sender.send(TracingEvent::FlowStart {
+            name: "Wakeup".to_string(),
+            cat: Some("wakeup".to_string()),
+            id,
+            pid,
+            tid,
+            ts,
+        });
+    }
+
+    /// Open a transfer slice on the track of the DMA channel. A transfer that is still open lost
+    /// its end (a channel runs one transfer at a time) and is closed first
+    fn on_dma_begin(&mut self, channel: u8, log_event: &LogEvent) {
+        if self.named_dma_channels.is_empty() {
+            let _ = self.trace_event_sender.send(TracingEvent::Metadata {
+                name: "process_name".to_string(),
+                cat: None,
+                args: HashMap::from([("name".to_string(), "DMA".to_string())]),
+                pid: DMA_PID,
+                tid: None,
+            });
+        }
+        if self.named_dma_channels.insert(channel) {
+            let _ = self.trace_event_sender.send(TracingEvent::Metadata {
+                name: "thread_name".to_string(),
+                cat: None,
+                args: HashMap::from([("name".to_string(), format!("Channel {channel}"))]),
+                pid: DMA_PID,
+                tid: Some(channel as u32),
+            });
+        }
+
+        self.end_dma_transfer(
+            channel,
+            log_event.timestamp,
+            HashMap::from([(
+                "warning".to_string(),
+                "end of transfer not received".to_string(),
+            )]),
+        );
+        self.open_dma_transfers.insert(channel);
+        let _ = self.trace_event_sender.send(TracingEvent::Begin {
+            name: "DMA transfer".to_string(),
+            cat: Some("dma".to_string()),
+            ts: log_event.timestamp.as_micros(),
+            pid: DMA_PID,
+            tid: Some(channel as u32),
+            args: HashMap::from([("core".to_string(), log_event.core_id.to_string())]),
+            cname: None,
+        });
+    }
+
+    /// Close the running transfer of the DMA channel (if any)
+    fn end_dma_transfer(
+        &mut self,
+        channel: u8,
+        timestamp: EmbassyTime,
+        args: TracingArgsMap<String>,
+    ) {
+        if !self.open_dma_transfers.remove(&channel) {
+            return;
+        }
+        let _ = self.trace_event_sender.send(TracingEvent::End {
+            name: Some("DMA transfer".to_string()),
+            cat: Some("dma".to_string()),
+            pid: DMA_PID,
+            tid: Some(channel as u32),
+            ts: timestamp.as_micros(),
+            args,
+        });
+    }
+
+    /// Update the observed range of a metric and send the `name.min` / `name.max` counters (in
+    /// the same process as the metric's own counter)
+    fn update_metric_band(
+        &mut self,
+        name: &str,
+        unit: Option<&str>,
+        value: f64,
+        log_event: &LogEvent,
+    ) {
+        let pid = self
+            .cores
+            .iter()
+            .find(|core| core.get_core_id() == log_event.core_id)
+            .and_then(|core| core.get_metric_pid(name));
+        let (min, max) = self
+            .metric_bands
+            .entry(name.to_string())
+            .or_insert((value, value));
+        *min = min.min(value);
+        *max = max.max(value);
+
+        for (suffix, band_value) in [("min", *min), ("max", *max)] {
+            let _ = self.trace_event_sender.send(TracingEvent::Counter {
+                name: metric_track_name(&format!("{name}.{suffix}"), unit),
+                cat: None,
+                pid,
+                ts: log_event.timestamp.as_micros(),
+                args: HashMap::from([("value".to_string(), band_value)]),
+            });
+        }
+    }
+
+    /// Mark the end of the tracing session in the trace. Returns whether the trace ended cleanly
+    /// (the target flushed its buffer) or might be missing its tail (truncated)
+    pub fn end_session(&mut self) -> bool {
+        for core in &mut self.cores {
+            core.flush_pending_idle();
+        }
+
+        if let Some(timestamp) = self.latest_timestamp {
+            let (name, cname) = if self.trace_flushed {
+                ("Trace end (clean)", CName::Good)
+            } else {
+                ("Trace end (truncated)", CName::Terrible)
+            };
+            let _ = self.trace_event_sender.send(TracingEvent::Instant {
+                name: name.to_string(),
+                cat: Some("rustmeter".to_string()),
+                ts: timestamp.as_micros(),
+                pid: None,
+                tid: None,
+                scope: InstantScope::Global,
+                args: HashMap::new(),
+                cname,
+            });
+        }
+
+        self.trace_flushed
+    }
+
+    /// Busy time per core and running time per task up to the latest timestamp (including the
+    /// runs before target resets)
+    pub fn get_usage(&self) -> UsageStats {
+        let mut usage = self.usage_before_reset.clone();
+        if let Some(latest_timestamp) = self.latest_timestamp {
+            for core in &self.cores {
+                core.add_usage(&mut usage, latest_timestamp);
+            }
+        }
+        usage
+    }
+
+    /// End of the warmup period, from which on monitors count towards the statistics
+    fn get_warmup_end(&self) -> EmbassyTime {
+        self.first_timestamp
+            .unwrap_or(EmbassyTime::from_micros(0))
+            .add_micros(self.options.warmup_us.unwrap_or(0))
+    }
+
+    /// Time span (in µs) between the first and the latest received timestamp
+    pub fn get_capture_duration_us(&self) -> u128 {
+        match (self.first_timestamp, self.latest_timestamp) {
+            (Some(first), Some(latest)) => latest.as_micros().saturating_sub(first.as_micros()),
+            _ => 0,
+        }
+    }
+
+    /// Duration statistics per monitor name across all cores (including the runs before target
+    /// resets)
+    pub fn get_monitor_durations(&self) -> BTreeMap<String, DurationHistogram> {
+        let mut durations: BTreeMap<String, DurationHistogram> = BTreeMap::new();
+        let per_core = self.cores.iter().map(|core| core.get_monitor_durations());
+        for core_durations in std::iter::once(&self.monitor_durations_before_reset).chain(per_core)
+        {
+            for (name, histogram) in core_durations {
+                durations.entry(name.clone()).or_default().merge(histogram);
+            }
+        }
+        durations
+    }
+
+    /// Longest observed duration (in µs) per monitor name across all cores
+    pub fn get_monitor_max_us(&self) -> HashMap<String, u128> {
+        self.get_monitor_durations()
+            .into_iter()
+            .map(|(name, histogram)| (name, histogram.max_us()))
+            .collect()
+    }
+
+    /// Total (call count, duration in µs) per coalesced monitor name across all cores
+    pub fn get_monitor_aggregates(&self) -> HashMap<String, (u64, u64)> {
+        let mut aggregates: HashMap<String, (u64, u64)> = HashMap::new();
+        for core in &self.cores {
+            for (name, (count, total_us)) in core.get_monitor_aggregates() {
+                let (total_count, total_duration_us) =
+                    aggregates.entry(name.clone()).or_insert((0, 0));
+                *total_count += count;
+                *total_duration_us += total_us;
+            }
+        }
+        aggregates
+    }
+
+    /// Check the observed monitor durations against the given budgets (name, µs) and
+    /// return a message for every violated budget
+    pub fn check_monitor_budgets(&self, budgets: &[(String, u64)]) -> Vec<String> {
+        let max_durations = self.get_monitor_max_us();
+        budgets
+            .iter()
+            .filter_map(|(name, budget_us)| {
+                let max_us = *max_durations.get(name)?;
+                (max_us > *budget_us as u128).then(|| {
+                    format!(
+                        "Monitor '{name}' exceeded its budget: max {max_us} us > {budget_us} us (+{} us)",
+                        max_us - *budget_us as u128
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Take all monitors completed since the last call, ordered by start time. Only collected
+    /// with the `collect_monitors` option, which allows programmatic captures to assert on
+    /// monitor durations without going through a trace file
+    #[cfg(test)]
+    pub fn drain_completed_monitors(&mut self) -> Vec<MonitorInterval> {
+        let mut monitors: Vec<MonitorInterval> = self
+            .cores
+            .iter_mut()
+            .flat_map(|core| core.drain_completed_monitors())
+            .collect();
+        monitors.sort_by_key(|monitor| monitor.start.as_micros());
+        monitors
+    }
+
+    /// Check if the timestamp went backwards by more than the configured tolerance. Such events are
+    /// counted and rejected, unless too many arrive in a row: then the target most likely restarted
+    /// its clock and the new timeline is accepted. Everything tracked on the old timeline is
+    /// closed then, like after a target reset
+    fn is_out_of_order(&mut self, timestamp: EmbassyTime) -> bool {
+        let (Some(tolerance_us), Some(latest_timestamp)) = (
+            self.options.out_of_order_tolerance_us,
+            self.latest_timestamp,
+        ) else {
+            return false;
+        };
+
+        if timestamp.as_micros() + tolerance_us as u128 >= latest_timestamp.as_micros() {
+            self.consecutive_rejected_events = 0;
+            return false;
+        }
+
+        self.consecutive_rejected_events += 1;
+        if self.consecutive_rejected_events >= OUT_OF_ORDER_RESYNC_COUNT {
+            self.reset_tracked_state(latest_timestamp);
+            let _ = self.trace_event_sender.send(TracingEvent::Instant {
+                name: "Timestamps resynchronized".to_string(),
+                cat: Some("rustmeter".to_string()),
+                ts: timestamp.as_micros(),
+                pid: None,
+                tid: None,
+                scope: InstantScope::Global,
+                args: HashMap::from([(
+                    "previous_ts".to_string(),
+                    latest_timestamp.as_micros().to_string(),
+                )]),
+                cname: CName::Terrible,
+            });
+            self.consecutive_rejected_events = 0;
+            return false;
+        }
+
+        self.rejected_events += 1;
+        true
+    }
+
+    /// Map a device timestamp onto the continuous timeline. If the device time restarted, the
+    /// target was reset: close everything of the previous run and continue after it
+    fn follow_timestamp(&mut self, device_timestamp: EmbassyTime) -> EmbassyTime {
+        let timestamp = device_timestamp.add_micros(self.time_base_us);
+        let Some(latest_timestamp) = self.latest_timestamp else {
+            return timestamp;
+        };
+        if timestamp.as_micros() + TARGET_RESET_JUMP_US >= latest_timestamp.as_micros() {
+            return timestamp;
+        }
+
+        self.time_base_us = latest_timestamp.as_micros() as u64;
+        self.on_target_reset(latest_timestamp, "device time restarted");
+        device_timestamp.add_micros(self.time_base_us)
+    }
+
+    /// Close all slices of the previous run and mark the reset in the trace. Executors and tasks
+    /// are tracked from scratch afterwards, their names still resolve from the firmware
+    fn on_target_reset(&mut self, timestamp: EmbassyTime, reason: &str) {
+        self.reset_tracked_state(timestamp);
+        self.target_resets += 1;
+
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: "Target reset".to_string(),
+            cat: Some("rustmeter".to_string()),
+            ts: timestamp.as_micros(),
+            pid: None,
+            tid: None,
+            scope: InstantScope::Global,
+            args: HashMap::from([
+                ("time_base_us".to_string(), self.time_base_us.to_string()),
+                ("reason".to_string(), reason.to_string()),
+            ]),
+            cname: CName::Terrible,
+        });
+    }
+
+    /// Close everything tracked on the old timeline at `timestamp` (cores, executors, tasks and
+    /// DMA transfers). Their statistics are kept, tracking starts from scratch afterwards
+    fn reset_tracked_state(&mut self, timestamp: EmbassyTime) {
+        for core in &mut self.cores {
+            core.add_usage(&mut self.usage_before_reset, timestamp);
+            for (name, histogram) in core.get_monitor_durations() {
+                self.monitor_durations_before_reset
+                    .entry(name.clone())
+                    .or_default()
+                    .merge(histogram);
+            }
+            core.finish(timestamp);
+        }
+        self.cores.clear();
+        for channel in self.open_dma_transfers.clone() {
+            self.end_dma_transfer(channel, timestamp, HashMap::new());
+        }
+        self.pending_wakeup_flows.clear();
+        self.trace_flushed = false;
+    }
+
+    /// Metrics of the `metric_panels` config that were never received (e.g. a typo in the config)
+    pub fn get_unseen_panel_metrics(&self) -> Vec<String> {
+        self.options
+            .metric_panels
+            .unseen_metrics(&self.seen_metrics)
+    }
+
+    /// Number of target resets detected with `follow_resets`
+    pub fn get_target_resets(&self) -> u64 {
+        self.target_resets
+    }
+
+    /// Add a marker that originates from the host (e.g. a test harness step) to the timeline.
+    /// Without a timestamp it is placed at the latest device timestamp
+    pub fn add_host_marker(&mut self, label: &str, timestamp: Option<EmbassyTime>) {
+        let Some(timestamp) = timestamp.or(self.latest_timestamp) else {
+            return; // nothing received from the device yet ==> no timeline to align to
+        };
+
+        let _ = self.trace_event_sender.send(TracingEvent::Instant {
+            name: format!("[host] {label}"),
+            cat: Some("host_marker".to_string()),
+            ts: timestamp.as_micros(),
+            pid: None,
+            tid: None,
+            scope: InstantScope::Global,
+            args: HashMap::new(),
+            cname: CName::Olive,
+        });
+    }
+
+    /// Number of tracing events and log lines rejected because their timestamp went backwards
+    pub fn get_rejected_events(&self) -> u64 {
+        self.rejected_events
+    }
+
+    /// Number of monitor ends dropped because their monitor was only open on another core
+    pub fn get_mismatched_monitor_ends(&self) -> u64 {
+        self.mismatched_monitor_ends
     }
 
     /// Adds a raw log line to the tracing instance (seperate plane)
     pub fn add_log_line(&mut self, log_line: &LogLine) {
+        let shifted_log_line;
+        let log_line = if self.options.follow_resets {
+            shifted_log_line = LogLine {
+                timestamp: self.follow_timestamp(log_line.timestamp),
+                ..log_line.clone()
+            };
+            &shifted_log_line
+        } else {
+            log_line
+        };
+        if self.is_out_of_order(log_line.timestamp) {
+            return;
+        }
+        self.first_timestamp.get_or_insert(log_line.timestamp);
+        self.latest_timestamp = Some(log_line.timestamp);
+        self.send_log_line(log_line, HashMap::new());
+    }
+
+    /// Adds a log line without device timestamp (firmware without `defmt::timestamp!`, see
+    /// `--align-logs`). It is placed at the latest timestamp of the trace, so its time is only
+    /// approximate and marked as such in its args
+    pub fn add_untimed_log_line(&mut self, level: LogLevel, message: String) {
+        let timestamp = self.latest_timestamp.unwrap_or(EmbassyTime::from_micros(0));
+        let log_line = LogLine::new(timestamp, level, message);
+        self.send_log_line(
+            &log_line,
+            HashMap::from([(
+                "timing".to_string(),
+                "approximate (aligned to the latest trace timestamp)".to_string(),
+            )]),
+        );
+    }
+
+    /// Send a log line as global instant event
+    fn send_log_line(&self, log_line: &LogLine, extra_args: TracingArgsMap<String>) {
+        // Make key=value pairs of the message filterable in Perfetto
+        let mut args = parse_message_args(&log_line.message);
+        args.insert("level".to_string(), log_line.level.to_string());
+        args.extend(extra_args);
+
         // Define event
         let event = TracingEvent::Instant {
             name: log_line.message.to_string(),
@@ -78,7 +907,7 @@ impl TracingInstance {
             pid: None,
             tid: None,
             scope: InstantScope::Global,
-            args: HashMap::from([("level".to_string(), log_line.level.to_string())]),
+            args,
             cname: log_line.level.get_cname(),
         };
 
@@ -86,3 +915,2039 @@ impl TracingInstance {
         let _ = self.trace_event_sender.send(event);
     }
 }
+
+/// Parse `key=value` pairs of a log message into trace args. Supports `@NAME(k=v,...)` as well
+/// as plain messages containing whitespace separated `k=v` tokens. Other text is ignored.
+fn parse_message_args(message: &str) -> TracingArgsMap<String> {
+    let message = message.trim();
+
+    // @NAME(k=v,...) ==> only look at the parameters
+    let pairs: Vec<&str> = if let Some(rest) = message.strip_prefix('@')
+        && let Some((_, params)) = rest.split_once('(')
+        && let Some(params) = params.strip_suffix(')')
+    {
+        params.split(',').collect()
+    } else {
+        message.split_whitespace().collect()
+    };
+
+    pairs
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = key.trim();
+            let is_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+            is_key.then(|| {
+                (
+                    key.to_string(),
+                    value.trim().trim_end_matches(',').to_string(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parse a host marker line: `[<device seconds>] <label>`, e.g. `1.25 start command sent` or
+/// just `start command sent`. Returns `None` for empty lines
+pub fn parse_host_marker(line: &str) -> Option<(Option<EmbassyTime>, String)> {
+    let line = line.trim();
+    let (timestamp, label) = match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => match first
+            .parse::<f64>()
+            .ok()
+            .and_then(EmbassyTime::try_from_secs_f64)
+        {
+            Some(timestamp) => (Some(timestamp), rest.trim()),
+            None => (None, line),
+        },
+        None => (None, line),
+    };
+
+    (!label.is_empty()).then(|| (timestamp, label.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric_event(ts: f64, value: f64) -> LogEvent {
+        LogEvent::new(
+            EmbassyTime::from_secs_f64(ts),
+            0,
+            LogEventType::EventMetric {
+                name: "temp".to_string(),
+                value,
+                unit: None,
+            },
+        )
+    }
+
+    /// Collect the last sent value of every counter track by name
+    fn last_counter_values(receiver: &Receiver<TracingEvent>) -> HashMap<String, f64> {
+        let mut values = HashMap::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let TracingEvent::Counter { name, args, .. } = event {
+                values.insert(name, args["value"]);
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_counter_bands_track_min_max() {
+        let options = TracingOptions {
+            counter_bands: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        for (i, value) in [3.0, -2.0, 7.5, 1.0].into_iter().enumerate() {
+            instance.update(&metric_event(i as f64, value));
+        }
+
+        let values = last_counter_values(&receiver);
+        assert_eq!(values["temp"], 1.0);
+        assert_eq!(values["temp.min"], -2.0);
+        assert_eq!(values["temp.max"], 7.5);
+    }
+
+    #[test]
+    fn test_counter_bands_share_the_metric_pid() {
+        let options = TracingOptions {
+            counter_bands: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_METRIC(name=temp,value=3,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let counters: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Counter { name, pid, .. } if name.starts_with("temp") => {
+                    Some((name, pid))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                ("temp".to_string(), Some(1)),
+                ("temp.min".to_string(), Some(1)),
+                ("temp.max".to_string(), Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter_bands_disabled_by_default() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        instance.update(&metric_event(0.0, 3.0));
+
+        let values = last_counter_values(&receiver);
+        assert_eq!(values.len(), 1);
+        assert!(values.contains_key("temp"));
+    }
+
+    #[test]
+    fn test_end_session_clean_only_after_flush() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+
+        instance.update(&metric_event(0.0, 1.0));
+        assert!(!instance.end_session());
+
+        let flush = LogEvent::new(
+            EmbassyTime::from_secs_f64(1.0),
+            0,
+            LogEventType::EventTraceFlush,
+        );
+        instance.update(&flush);
+        assert!(instance.end_session());
+
+        instance.update(&metric_event(2.0, 1.0));
+        assert!(!instance.end_session());
+    }
+
+    fn monitor_event(ts: f64, name: &str, start: bool) -> LogEvent {
+        let event_type = if start {
+            LogEventType::EventMonitorStart {
+                function_name: name.to_string(),
+                category: None,
+                sampled: None,
+                executor_id: None,
+                location: None,
+            }
+        } else {
+            LogEventType::EventMonitorEnd {
+                function_name: name.to_string(),
+                category: None,
+            }
+        };
+        LogEvent::new(EmbassyTime::from_secs_f64(ts), 0, event_type)
+    }
+
+    #[test]
+    fn test_monitor_budgets() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+
+        // outer: 1.0s, inner: 0.0002s then 0.0004s
+        instance.update(&monitor_event(1.0, "outer", true));
+        instance.update(&monitor_event(1.1, "inner", true));
+        instance.update(&monitor_event(1.1002, "inner", false));
+        instance.update(&monitor_event(1.2, "inner", true));
+        instance.update(&monitor_event(1.2004, "inner", false));
+        instance.update(&monitor_event(2.0, "outer", false));
+
+        let max_durations = instance.get_monitor_max_us();
+        assert_eq!(max_durations["outer"], 1_000_000);
+        assert_eq!(max_durations["inner"], 400);
+
+        let budgets = [
+            ("outer".to_string(), 2_000_000),
+            ("inner".to_string(), 300),
+            ("missing".to_string(), 1),
+        ];
+        let violations = instance.check_monitor_budgets(&budgets);
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("'inner'"));
+        assert!(violations[0].contains("+100 us"));
+    }
+
+    #[test]
+    fn test_parse_message_args() {
+        let args = parse_message_args("@SENSOR_READ(id=3, value=-1.5)");
+        assert_eq!(args.len(), 2);
+        assert_eq!(args["id"], "3");
+        assert_eq!(args["value"], "-1.5");
+
+        let args = parse_message_args("Connected to ap ssid=home rssi=-60, retrying: no");
+        assert_eq!(args.len(), 2);
+        assert_eq!(args["ssid"], "home");
+        assert_eq!(args["rssi"], "-60");
+
+        assert!(parse_message_args("Hello, world!").is_empty());
+        assert!(parse_message_args("a == b").is_empty());
+    }
+
+    #[test]
+    fn test_without_executor_events() {
+        // metrics-only firmware never sends executor / task events
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        instance.update(&monitor_event(1.0, "work", true));
+        instance.update(&metric_event(1.5, 4.0));
+        instance.update(&monitor_event(2.0, "work", false));
+
+        let events: Vec<TracingEvent> = receiver.try_iter().collect();
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, TracingEvent::Begin { .. }))
+        );
+        assert!(events.iter().any(|e| matches!(e, TracingEvent::End { .. })));
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, TracingEvent::Counter { pid: None, .. }))
+        );
+    }
+
+    #[test]
+    fn test_negative_metric_keeps_sign() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        for (value, expected) in [("-5", -5.0), ("-128", -128.0), ("-2.5", -2.5)] {
+            let log_line = LogLine::from_str(&format!(
+                "1.0 [INFO ] @EVENT_METRIC(name=temp,value={value},core_id=0)"
+            ))
+            .unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+
+            let values = last_counter_values(&receiver);
+            assert_eq!(values["temp"], expected);
+        }
+    }
+
+    #[test]
+    fn test_marker_is_global_instant() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        let log_line =
+            LogLine::from_str("3.0 [INFO ] @EVENT_MARKER(name=ButtonPressed,core_id=1)").unwrap();
+        instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+
+        let markers: Vec<TracingEvent> = receiver
+            .try_iter()
+            .filter(|e| matches!(e, TracingEvent::Instant { .. }))
+            .collect();
+        assert_eq!(markers.len(), 1);
+        match &markers[0] {
+            TracingEvent::Instant {
+                name,
+                ts,
+                scope,
+                cname,
+                ..
+            } => {
+                assert_eq!(name, "ButtonPressed");
+                assert_eq!(*ts, 3_000_000);
+                assert!(matches!(scope, InstantScope::Global));
+                assert!(matches!(cname, CName::Yellow));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_ready_task_counter() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        let lines = [
+            "0.1 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "0.1 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            "0.2 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "0.2 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            "0.3 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "0.3 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+        ];
+        let mut ready_counts = Vec::new();
+        for line in lines {
+            let log_line = LogLine::from_str(line).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+
+            for event in receiver.try_iter() {
+                if let TracingEvent::Counter {
+                    name, pid, args, ..
+                } = event
+                    && name == "Ready tasks"
+                {
+                    assert_eq!(pid, Some(1));
+                    ready_counts.push(args["value"]);
+                }
+            }
+        }
+
+        assert_eq!(ready_counts, vec![1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_drain_completed_monitors() {
+        let options = TracingOptions {
+            collect_monitors: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+
+        instance.update(&monitor_event(1.0, "parse", true));
+        instance.update(&monitor_event(1.0003, "parse", false));
+        instance.update(&monitor_event(2.0, "open", true));
+
+        let monitors = instance.drain_completed_monitors();
+        assert_eq!(
+            monitors,
+            vec![MonitorInterval {
+                name: "parse".to_string(),
+                core_id: 0,
+                task_id: None,
+                category: None,
+                start: EmbassyTime::from_secs_f64(1.0),
+                duration_us: 300,
+                recursion_depth: None,
+            }]
+        );
+        assert!(monitors[0].duration_us < 500);
+
+        // drained monitors are gone, open ones show up once they end
+        instance.update(&monitor_event(2.5, "open", false));
+        let monitors = instance.drain_completed_monitors();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].name, "open");
+    }
+
+    #[test]
+    fn test_monitor_start_with_executor_id() {
+        let options = TracingOptions {
+            collect_monitors: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+
+        // Task 10 of executor 1 gets preempted by executor 2 (task 20) right before the monitor
+        // start of task 10 arrives
+        let events = [
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=2,core_id=0)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=2,core_id=0,task_id=20)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=2,core_id=0,task_id=20)",
+            "@EVENT_MONITOR_START(function_name=a,executor_id=1,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=b,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=b,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=a,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let task_ids: HashMap<_, _> = instance
+            .drain_completed_monitors()
+            .into_iter()
+            .map(|monitor| (monitor.name, monitor.task_id))
+            .collect();
+        assert_eq!(task_ids["a"], Some(10));
+        assert_eq!(task_ids["b"], Some(20)); // fallback to the polling executor
+    }
+
+    #[test]
+    fn test_scope_location() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // Two scopes of the same name in different places
+        let events = [
+            "@EVENT_MONITOR_START(function_name=parse,location=src/uart.rs:12,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=parse,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=parse,location=src/spi.rs:40,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=parse,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [INFO ] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let locations: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Begin { name, args, .. } if name == "parse" => {
+                    args.get("location").cloned()
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(locations, vec!["src/uart.rs:12", "src/spi.rs:40"]);
+    }
+
+    #[test]
+    fn test_coalesced_monitor() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        for (ts, core_id) in [(0.1, 0), (0.2, 1)] {
+            let log_line = LogLine::from_str(&format!(
+                "{ts} [INFO ] @EVENT_MONITOR_AGGREGATE(function_name=hot,count=50,total_us=1000,window_us=100000,core_id={core_id})"
+            ))
+            .unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let values = last_counter_values(&receiver);
+        assert_eq!(values["hot calls/s"], 500.0);
+        assert_eq!(values["hot avg us"], 20.0);
+        assert_eq!(instance.get_monitor_aggregates()["hot"], (100, 2000));
+    }
+
+    #[test]
+    fn test_priority_inversion_heuristic() {
+        let options = TracingOptions {
+            inversion_threshold_us: Some(1000),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // low prio executor 1 polls task 10 which enters a monitored scope, then the
+        // high prio executor 2 preempts it for 2ms
+        let lines = [
+            "0.100 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "0.100 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "0.101 [INFO ] @EVENT_MONITOR_START(function_name=hold_bus,core_id=0)",
+            "0.102 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=2,core_id=0)",
+            "0.104 [TRACE] @EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=2,core_id=0)",
+        ];
+        for line in lines {
+            let log_line = LogLine::from_str(line).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let inversions: Vec<TracingEvent> = receiver
+            .try_iter()
+            .filter(|e| {
+                matches!(e, TracingEvent::Instant { cat: Some(cat), .. } if cat == "priority_inversion")
+            })
+            .collect();
+        assert_eq!(inversions.len(), 1);
+        if let TracingEvent::Instant { args, tid, .. } = &inversions[0] {
+            assert_eq!(*tid, Some(10));
+            assert_eq!(args["monitor"], "hold_bus");
+            assert_eq!(args["preempted_us"], "2000");
+        }
+    }
+
+    #[test]
+    fn test_ended_tasks_are_evicted() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // many short-lived tasks, task ids (pool slots) get reused
+        let mut ts = 0.0;
+        for i in 0..10_000u32 {
+            let task_id = 100 + i % 8;
+            for event in [
+                "EVENT_EMBASSY_TASK_NEW",
+                "EVENT_EMBASSY_TASK_READY_BEGIN",
+                "EVENT_EMBASSY_POLL_START",
+                "EVENT_EMBASSY_TASK_EXEC_BEGIN",
+                "EVENT_EMBASSY_TASK_END",
+                "EVENT_EMBASSY_TASK_EXEC_END",
+                "EVENT_EMBASSY_EXECUTOR_IDLE",
+            ] {
+                ts += 0.000001;
+                let log_line = LogLine::from_str(&format!(
+                    "{ts:.6} [TRACE] @{event}(executor_id=1,core_id=0,task_id={task_id})"
+                ))
+                .unwrap();
+                instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+            }
+            let _ = receiver.try_iter().count();
+        }
+
+        assert_eq!(instance.get_tracked_task_count(), 0);
+    }
+
+    #[test]
+    fn test_ended_executors_are_evicted() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        let events = [
+            (
+                1.0,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                1.0,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (1.0, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (
+                1.0,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                1.5,
+                "@EVENT_EMBASSY_TASK_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                1.5,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (1.5, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)"),
+            // Idle without tasks, but not for long enough yet
+            (5.0, "@EVENT_EMBASSY_POLL_START(executor_id=2,core_id=0)"),
+            (5.0, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=2,core_id=0)"),
+        ];
+        for (ts, event) in events {
+            let log_line = LogLine::from_str(&format!("{ts:.6} [TRACE] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        assert_eq!(instance.get_tracked_executor_count(), 2);
+        let _ = receiver.try_iter().count();
+
+        let log_line = LogLine::from_str(
+            "12.000000 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=2,core_id=0)",
+        )
+        .unwrap();
+        instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+
+        // The executor without tasks is closed and forgotten, its task statistics are kept
+        assert_eq!(instance.get_tracked_executor_count(), 1);
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TracingEvent::End {
+                ts: 12_000_000,
+                pid: 1,
+                tid: None,
+                ..
+            }
+        )));
+        let usage = instance.get_usage();
+        let key = ("Executor 0x1".to_string(), "Task 0xA".to_string());
+        assert_eq!(usage.task_running_us[&key], 500_000);
+        assert_eq!(usage.task_polls[&key], 1);
+    }
+
+    #[test]
+    fn test_out_of_order_events_are_rejected() {
+        let options = TracingOptions {
+            out_of_order_tolerance_us: Some(100),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let marker = |ts: f64| {
+            let log_line =
+                LogLine::from_str(&format!("{ts:.6} [TRACE] @EVENT_MARKER(name=M,core_id=0)"))
+                    .unwrap();
+            LogEvent::from_log_line(&log_line).unwrap()
+        };
+
+        instance.update(&marker(1.0));
+        instance.update(&marker(0.99995)); // jitter within tolerance
+        instance.update(&marker(0.5)); // desync
+        instance.update(&marker(1.1));
+        assert_eq!(instance.get_rejected_events(), 1);
+        let markers = receiver
+            .try_iter()
+            .filter(|event| matches!(event, TracingEvent::Instant { .. }))
+            .count();
+        assert_eq!(markers, 3);
+
+        // A persistent jump back is accepted as new timeline
+        for _ in 0..OUT_OF_ORDER_RESYNC_COUNT {
+            instance.update(&marker(0.1));
+        }
+        assert_eq!(
+            instance.get_rejected_events(),
+            OUT_OF_ORDER_RESYNC_COUNT as u64
+        );
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(
+            matches!(&events[0], TracingEvent::Instant { name, .. } if name == "Timestamps resynchronized")
+        );
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn test_out_of_order_resync_resets_tracked_state() {
+        let options = TracingOptions {
+            out_of_order_tolerance_us: Some(100),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+        ];
+        for event in events {
+            let log_line = LogLine::from_str(&format!("1.000000 [TRACE] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        assert_eq!(instance.get_tracked_task_count(), 1);
+        let _ = receiver.try_iter().count();
+
+        // Log lines count towards the resync like tracing events
+        for _ in 0..OUT_OF_ORDER_RESYNC_COUNT - 1 {
+            instance.add_log_line(&LogLine::from_str("0.100000 [INFO] rebooted").unwrap());
+        }
+        assert_eq!(receiver.try_iter().count(), 0);
+        instance.add_log_line(&LogLine::from_str("0.100000 [INFO] rebooted").unwrap());
+
+        // The running task of the old timeline is closed at its latest timestamp
+        assert_eq!(instance.get_tracked_task_count(), 0);
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TracingEvent::End {
+                ts: 1_000_000,
+                pid: 1,
+                tid: Some(10),
+                ..
+            }
+        )));
+        assert!(events.iter().any(
+            |event| matches!(event, TracingEvent::Instant { name, .. } if name == "Timestamps resynchronized")
+        ));
+        assert!(events.iter().any(
+            |event| matches!(event, TracingEvent::Instant { name, ts: 100_000, .. } if name == "rebooted")
+        ));
+    }
+
+    #[test]
+    fn test_untimed_log_lines_are_aligned() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        instance.add_untimed_log_line(LogLevel::Info, "booting".to_string());
+        instance.update(&metric_event(2.5, 1.0));
+        instance.add_untimed_log_line(LogLevel::Warn, "sensor timeout".to_string());
+
+        let logs: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Instant { name, ts, args, .. } => Some((name, ts, args)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(logs.len(), 2);
+        assert_eq!((logs[0].0.as_str(), logs[0].1), ("booting", 0));
+        assert_eq!(
+            (logs[1].0.as_str(), logs[1].1),
+            ("sensor timeout", 2_500_000)
+        );
+        assert!(logs[1].2["timing"].starts_with("approximate"));
+        assert_eq!(logs[1].2["level"], "WARN");
+    }
+
+    #[test]
+    fn test_host_markers() {
+        assert_eq!(parse_host_marker("   "), None);
+        assert_eq!(
+            parse_host_marker("start command sent\n"),
+            Some((None, "start command sent".to_string()))
+        );
+        assert_eq!(
+            parse_host_marker("1.5 step 2"),
+            Some((Some(EmbassyTime::from_secs_f64(1.5)), "step 2".to_string()))
+        );
+        assert_eq!(parse_host_marker("42"), Some((None, "42".to_string())));
+        assert_eq!(
+            parse_host_marker("1e30 far away"),
+            Some((None, "1e30 far away".to_string()))
+        );
+
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // No device time yet ==> dropped
+        instance.add_host_marker("too early", None);
+        instance.update(&metric_event(2.0, 1.0));
+        instance.add_host_marker("aligned", None);
+
+        let markers: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Instant { name, ts, .. } => Some((name, ts)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(markers, vec![("[host] aligned".to_string(), 2_000_000)]);
+    }
+
+    #[test]
+    fn test_wakeup_flows() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // Task 10 is readied by an interrupt, then readies task 11 while running
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=11)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let flows: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::FlowStart { id, pid, tid, .. } => Some(("s", id, pid, tid)),
+                TracingEvent::FlowEnd { id, pid, tid, .. } => Some(("f", id, pid, tid)),
+                TracingEvent::Complete { name, pid, tid, .. } if name == "Wake (ISR)" => {
+                    Some(("X", u64::MAX, pid, tid))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            flows,
+            vec![
+                ("X", u64::MAX, 0, 0), // interrupt anchor on the core overview
+                ("s", 0, 0, 0),
+                ("f", 0, 1, 10),
+                ("s", 1, 1, 10), // readied by the running task 10
+                ("f", 1, 1, 11),
+            ]
+        );
+        assert!(instance.pending_wakeup_flows.is_empty());
+    }
+
+    #[test]
+    fn test_timer_ready_marker() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // The first timer wakes the task 500µs late, the second one is woken 5ms early (e.g. by
+        // a select on another future)
+        let events = [
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                1_000,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (1_000, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (
+                1_000,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (2_000, "@EVENT_TIMER_SET(deadline_us=10000,core_id=0)"),
+            (
+                3_000,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                10_500,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                11_000,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (11_000, "@EVENT_TIMER_FIRED(deadline_us=10000,core_id=0)"),
+            (12_000, "@EVENT_TIMER_SET(deadline_us=20000,core_id=0)"),
+            (
+                13_000,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                15_000,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+        ];
+        for (ts_us, event) in events {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", ts_us as f64 / 1e6)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let markers: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Instant {
+                    name,
+                    ts,
+                    pid: Some(1),
+                    tid: Some(10),
+                    args,
+                    ..
+                } if name == "Timer ready" => Some((
+                    ts,
+                    args["deadline_us"].clone(),
+                    args["wakeup_latency_us"].clone(),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            markers,
+            vec![
+                (10_500, "10000".to_string(), "500".to_string()),
+                (15_000, "20000".to_string(), "-5000".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wakeup_flows_skip_filtered_tasks() {
+        let options = TracingOptions {
+            task_filter: NameFilter {
+                include: vec![],
+                exclude: vec!["Task 0xB".to_string()],
+            },
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // Filtered task 11 is readied by task 10 and then readies task 10 and 12 itself
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=12)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=12)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=12)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let flows: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::FlowStart { id, pid, tid, .. } => Some(("s", id, pid, tid)),
+                TracingEvent::FlowEnd { id, pid, tid, .. } => Some(("f", id, pid, tid)),
+                _ => None,
+            })
+            .collect();
+        // Only the interrupt waking task 10 remains, task 11 neither wakes nor is woken
+        assert_eq!(flows, vec![("s", 0, 0, 0), ("f", 0, 1, 10)]);
+        assert!(instance.pending_wakeup_flows.is_empty());
+    }
+
+    #[test]
+    fn test_async_task_view() {
+        let options = TracingOptions {
+            task_view: TaskView::Async,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // Two tasks with the same id on different executors
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=2,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let states: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::AsyncBegin { name, id, .. } => Some(("b", name, id)),
+                TracingEvent::AsyncEnd { name, id, .. } => Some(("e", name, id)),
+                TracingEvent::Begin {
+                    pid, tid: Some(_), ..
+                } => {
+                    panic!("unexpected state slice on executor {pid}")
+                }
+                _ => None,
+            })
+            .collect();
+        let state = |ph, name: &str, id: &str| (ph, name.to_string(), id.to_string());
+        assert_eq!(
+            states,
+            vec![
+                state("b", "Task 0xA: Spawned", "0x10000000A"),
+                state("b", "Task 0xA: Spawned", "0x20000000A"),
+                state("e", "Task 0xA: Spawned", "0x10000000A"),
+                state("b", "Task 0xA: Waiting", "0x10000000A"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exec_end_of_other_task() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // The exec begin of task 11 is lost ==> its exec end must not leave task 10 running
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let warning = events
+            .iter()
+            .find_map(|event| match event {
+                TracingEvent::Instant { name, ts, args, .. } if name == "Lost task events" => {
+                    Some((*ts, args.clone()))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(warning.0, 6000);
+        assert_eq!(warning.1["polling_task"], "0xA");
+        assert_eq!(warning.1["exec_end_task"], "0xB");
+
+        // Task 10 went idle at the exec end
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TracingEvent::Begin { name, tid: Some(10), ts: 6000, .. } if name == "Idle"
+        )));
+    }
+
+    #[test]
+    fn test_merge_short_idle_periods() {
+        let options = TracingOptions {
+            idle_merge_threshold_us: Some(10),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // Busy and idle alternate every 5µs
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.000005)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        instance.end_session();
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let slices = |pid: u32, tid: Option<u32>| {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    TracingEvent::Begin {
+                        name,
+                        pid: p,
+                        tid: t,
+                        ts,
+                        ..
+                    } if *p == pid && *t == tid => Some(("B", name.clone(), *ts, None)),
+                    TracingEvent::End {
+                        pid: p,
+                        tid: t,
+                        ts,
+                        args,
+                        ..
+                    } if *p == pid && *t == tid => {
+                        Some(("E", String::new(), *ts, args.get("merged_idle_us").cloned()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // One Scheduling slice on the executor track, the trailing idle period is kept
+        let merged = Some("10".to_string());
+        assert_eq!(
+            slices(1, None),
+            vec![
+                ("B", "Created".to_string(), 0, None),
+                ("E", String::new(), 5, None),
+                ("B", "Scheduling".to_string(), 5, None),
+                ("E", String::new(), 30, merged.clone()),
+                ("B", "Idle".to_string(), 30, None),
+            ]
+        );
+
+        // One executor slice on the core overview
+        let core_slices = slices(0, Some(0));
+        assert_eq!(core_slices.len(), 2);
+        assert_eq!(core_slices[1], ("E", String::new(), 30, merged));
+    }
+
+    #[test]
+    fn test_merge_idle_with_backwards_timestamp() {
+        let options = TracingOptions {
+            idle_merge_threshold_us: Some(10),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // The second poll is stamped before the idle period it ends
+        let events = [
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (5, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (10, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)"),
+            (8, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (15, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)"),
+        ];
+        for (ts_us, event) in events {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", ts_us as f64 / 1e6)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        instance.end_session();
+
+        // The backwards gap is merged as an idle period of 0µs instead of wrapping around
+        let events: Vec<_> = receiver.try_iter().collect();
+        let scheduling_slices = events
+            .iter()
+            .filter(|event| {
+                matches!(event, TracingEvent::Begin { name, pid: 1, .. } if name == "Scheduling")
+            })
+            .count();
+        assert_eq!(scheduling_slices, 1);
+        assert!(events.iter().all(|event| match event {
+            TracingEvent::End { args, .. } => !args.contains_key("merged_idle_us"),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn test_usage_with_backwards_timestamps() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let _receiver = instance.get_trace_event_receiver();
+
+        // The task and the executor end before they started
+        let events = [
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                0,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (100, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (
+                100,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                90,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (80, "@EVENT_EMBASSY_EXECUTOR_IDLE(executor_id=1,core_id=0)"),
+        ];
+        for (ts_us, event) in events {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", ts_us as f64 / 1e6)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        // Counted as no time instead of wrapping around
+        let usage = instance.get_usage();
+        assert_eq!(usage.core_busy_us[&0], 0);
+        assert_eq!(
+            usage.task_running_us.values().copied().collect::<Vec<_>>(),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_yield_reasons() {
+        let options = TracingOptions {
+            yield_reason_names: HashMap::from([(16, "modem".to_string())]),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let mut events = vec!["@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)"];
+        for yield_reason in [
+            "@EVENT_YIELD_REASON(code=1,core_id=0)",  // built-in timer
+            "@EVENT_YIELD_REASON(code=16,core_id=0)", // named on the command line
+            "@EVENT_YIELD_REASON(code=17,core_id=0)", // unnamed
+        ] {
+            events.extend([
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+                "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+                yield_reason,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ]);
+        }
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let reasons: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::End {
+                    tid: Some(10),
+                    args,
+                    ..
+                } => args.get("yield_reason").cloned(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons, vec!["timer", "modem", "code 17"]);
+    }
+
+    #[test]
+    fn test_warmup_excluded_from_statistics() {
+        let options = TracingOptions {
+            collect_monitors: true,
+            warmup_us: Some(1000),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // A slow first call during init, then the steady state
+        instance.update(&monitor_event(0.000100, "read", true));
+        instance.update(&monitor_event(0.000900, "read", false));
+        instance.update(&monitor_event(0.002000, "read", true));
+        instance.update(&monitor_event(0.002010, "read", false));
+
+        assert_eq!(instance.get_monitor_max_us()["read"], 10);
+        assert_eq!(instance.drain_completed_monitors().len(), 1);
+
+        // Both calls are still in the trace
+        let slices = receiver
+            .try_iter()
+            .filter(|event| matches!(event, TracingEvent::Begin { name, .. } if name == "read"))
+            .count();
+        assert_eq!(slices, 2);
+    }
+
+    #[test]
+    fn test_monitor_colors() {
+        let options = TracingOptions {
+            monitor_colors: MonitorColors {
+                monitors: HashMap::from([("read".to_string(), CName::Good)]),
+                categories: HashMap::from([("network".to_string(), CName::Yellow)]),
+            },
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        for event in [
+            "@EVENT_MONITOR_START(function_name=read,category=network,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=send,category=network,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=parse,core_id=0)",
+        ] {
+            let log_line = LogLine::from_str(&format!("1.0 [INFO ] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let colors: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Begin { name, cname, .. } => Some((name, cname)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            colors,
+            vec![
+                ("read".to_string(), Some(CName::Good)),
+                ("send".to_string(), Some(CName::Yellow)),
+                ("parse".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spawn_failed_marker() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        let log_line = LogLine::from_str(
+            "0.5 [INFO ] @EVENT_EMBASSY_TASK_SPAWN_FAILED(executor_id=1,core_id=0)",
+        )
+        .unwrap();
+        instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TracingEvent::Instant { name, pid: Some(1), ts: 500000, cname: CName::Terrible, .. }
+                if name == "Spawn failed"
+        )));
+    }
+
+    #[test]
+    fn test_resource_contention_slices() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // The task blocks on the lock across two polls, holds it and ends before releasing
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_RESOURCE_WAIT(name=bus,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_RESOURCE_ACQUIRED(name=bus,core_id=0)",
+            "@EVENT_RESOURCE_RELEASED(name=other,core_id=0)",
+            "@EVENT_EMBASSY_TASK_END(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let slices: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::AsyncBegin { name, ts, .. } => Some(("b", name, ts)),
+                TracingEvent::AsyncEnd { name, ts, .. } => Some(("e", name, ts)),
+                _ => None,
+            })
+            .collect();
+        let slice = |ph, name: &str, ts| (ph, name.to_string(), ts);
+        assert_eq!(
+            slices,
+            vec![
+                slice("b", "Blocked on bus", 4000),
+                slice("e", "Blocked on bus", 8000),
+                slice("b", "Holding bus", 8000),
+                // closed when the ended task is evicted
+                slice("e", "Holding bus", 11000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_abandoned_and_out_of_order_resource_slices() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // Waits that time out and a guard released while another wait is open
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_RESOURCE_WAIT(name=bus,core_id=0)",
+            "@EVENT_RESOURCE_ACQUIRED(name=bus,core_id=0)",
+            "@EVENT_RESOURCE_WAIT(name=uart,core_id=0)",
+            "@EVENT_RESOURCE_ABANDONED(name=uart,core_id=0)",
+            "@EVENT_RESOURCE_WAIT(name=dma,core_id=0)",
+            "@EVENT_RESOURCE_RELEASED(name=bus,core_id=0)",
+            "@EVENT_RESOURCE_ABANDONED(name=dma,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let slices: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::AsyncBegin { name, ts, .. } => Some(("b", name, ts)),
+                TracingEvent::AsyncEnd { name, ts, .. } => Some(("e", name, ts)),
+                _ => None,
+            })
+            .collect();
+        let slice = |ph, name: &str, ts| (ph, name.to_string(), ts);
+        assert_eq!(
+            slices,
+            vec![
+                slice("b", "Blocked on bus", 4000),
+                slice("e", "Blocked on bus", 5000),
+                slice("b", "Holding bus", 5000),
+                slice("b", "Blocked on uart", 6000),
+                slice("e", "Blocked on uart", 7000),
+                slice("b", "Blocked on dma", 8000),
+                // the inner wait is ended and begun again to keep the slices nested
+                slice("e", "Blocked on dma", 9000),
+                slice("e", "Holding bus", 9000),
+                slice("b", "Blocked on dma", 9000),
+                slice("e", "Blocked on dma", 10000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dma_transfer_slices() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // Two channels overlap, the end of the second transfer on channel 1 gets lost
+        let events = [
+            "@EVENT_DMA_BEGIN(channel=1,core_id=0)",
+            "@EVENT_DMA_BEGIN(channel=4,core_id=1)",
+            "@EVENT_DMA_END(channel=1,core_id=0)",
+            "@EVENT_DMA_BEGIN(channel=1,core_id=0)",
+            "@EVENT_DMA_END(channel=4,core_id=1)",
+            "@EVENT_DMA_BEGIN(channel=1,core_id=0)",
+            "@EVENT_DMA_END(channel=1,core_id=0)",
+            "@EVENT_DMA_END(channel=1,core_id=0)", // nothing open anymore
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [INFO ] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let thread_names = events
+            .iter()
+            .filter(|e| matches!(e, TracingEvent::Metadata { name, pid: DMA_PID, .. } if name == "thread_name"))
+            .count();
+        assert_eq!(thread_names, 2);
+
+        let slices: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                TracingEvent::Begin {
+                    pid: DMA_PID,
+                    tid: Some(tid),
+                    ts,
+                    ..
+                } => Some(("B", *tid, *ts, false)),
+                TracingEvent::End {
+                    pid: DMA_PID,
+                    tid: Some(tid),
+                    ts,
+                    args,
+                    ..
+                } => Some(("E", *tid, *ts, args.contains_key("warning"))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            slices,
+            vec![
+                ("B", 1, 0, false),
+                ("B", 4, 1000, false),
+                ("E", 1, 2000, false),
+                ("B", 1, 3000, false),
+                ("E", 4, 4000, false),
+                ("E", 1, 5000, true),
+                ("B", 1, 5000, false),
+                ("E", 1, 6000, false),
+            ]
+        );
+        // DMA events do not create core tracks
+        assert!(instance.cores.is_empty());
+    }
+
+    #[test]
+    fn test_name_filters() {
+        let options = TracingOptions {
+            collect_monitors: true,
+            monitor_filter: NameFilter {
+                include: vec!["sensor_*".to_string()],
+                exclude: vec!["sensor_debug".to_string()],
+            },
+            task_filter: NameFilter {
+                include: vec![],
+                exclude: vec!["Task 0xB".to_string()],
+            },
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let events = [
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            "@EVENT_MONITOR_START(function_name=sensor_read,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=sensor_debug,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=sensor_debug,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=sensor_read,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=uart_write,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=uart_write,core_id=0)",
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", i as f64 * 0.001)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let mut monitors = Vec::new();
+        let mut task_ids = Vec::new();
+        for event in receiver.try_iter() {
+            match event {
+                TracingEvent::Begin {
+                    name,
+                    pid: 0,
+                    tid: Some(0),
+                    ..
+                } => monitors.push(name),
+                TracingEvent::End {
+                    name: Some(name),
+                    pid: 0,
+                    ..
+                } => monitors.push(name),
+                TracingEvent::Metadata {
+                    tid: Some(tid),
+                    pid: 1,
+                    ..
+                } => task_ids.push(tid),
+                _ => {}
+            }
+        }
+        assert_eq!(monitors, vec!["sensor_read", "sensor_read"]);
+        assert_eq!(task_ids, vec![10]);
+
+        // Filtered monitors are still tracked
+        assert_eq!(instance.drain_completed_monitors().len(), 3);
+    }
+
+    #[test]
+    fn test_equal_timestamps_keep_nesting() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        // µs resolution: a whole burst of monitors shares one timestamp. The defmt stream keeps
+        // the order of a core, so arrival order is the order to nest by
+        let events = [
+            "@EVENT_MONITOR_START(function_name=outer,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=first,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=first,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=second,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=second,core_id=0)",
+            "@EVENT_MONITOR_END(function_name=outer,core_id=0)",
+            "@EVENT_MONITOR_START(function_name=next,core_id=0)",
+        ];
+        for event in events {
+            let log_line = LogLine::from_str(&format!("1.000000 [INFO ] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let slices: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                TracingEvent::Begin {
+                    name, pid: 0, ts, ..
+                } => Some(("B", name, ts)),
+                TracingEvent::End {
+                    name: Some(name),
+                    pid: 0,
+                    ts,
+                    ..
+                } => Some(("E", name, ts)),
+                _ => None,
+            })
+            .collect();
+        let slice = |ph, name: &str| (ph, name.to_string(), 1_000_000);
+        assert_eq!(
+            slices,
+            vec![
+                slice("B", "outer"),
+                slice("B", "first"),
+                slice("E", "first"),
+                slice("B", "second"),
+                slice("E", "second"),
+                slice("E", "outer"),
+                slice("B", "next"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_monitor_stack_stays_bounded() {
+        let options = TracingOptions {
+            max_monitor_depth: Some(8),
+            collect_monitors: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        let mut ts = 0.0;
+        let mut send = |instance: &mut TracingInstance, event: &str| {
+            ts += 0.000010;
+            let log_line = LogLine::from_str(&format!("{ts:.6} [INFO ] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        };
+        send(
+            &mut instance,
+            "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+        );
+        send(
+            &mut instance,
+            "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+        );
+        send(
+            &mut instance,
+            "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+        );
+        let task = Some((1, 10));
+
+        // Firmware under load: every `lossy` start loses its end, `ok` monitors stay intact
+        let mut rejected_starts = 0;
+        for _ in 0..100 {
+            let rejecting = instance.is_rejecting_monitors(task);
+            let depth = instance.get_monitor_depth();
+            send(
+                &mut instance,
+                "@EVENT_MONITOR_START(function_name=lossy,core_id=0)",
+            );
+            if rejecting {
+                // starts are refused until the stack drained (or the task gave up waiting)
+                assert!(instance.get_monitor_depth() <= depth);
+                rejected_starts += 1;
+            }
+            send(
+                &mut instance,
+                "@EVENT_MONITOR_START(function_name=ok,core_id=0)",
+            );
+            send(
+                &mut instance,
+                "@EVENT_MONITOR_END(function_name=ok,core_id=0)",
+            );
+            assert!(instance.get_monitor_depth() <= 8);
+        }
+        assert!(rejected_starts > 0);
+
+        // Only the oldest half was force-closed at the limit
+        let events: Vec<_> = receiver.try_iter().collect();
+        let first_overflow = events
+            .iter()
+            .position(
+                |e| matches!(e, TracingEvent::Instant { name, .. } if name == "Monitor stack overflow"),
+            )
+            .unwrap();
+        let force_closed = events[..first_overflow]
+            .iter()
+            .filter(|e| matches!(e, TracingEvent::End { args, .. } if args.contains_key("warning")))
+            .count();
+        assert_eq!(force_closed, 4);
+
+        // Durations of intact monitors stay sane, force-closed ones are not recorded
+        let completed = instance.drain_completed_monitors();
+        assert!(!completed.is_empty() && completed.len() < 100);
+        assert!(
+            completed
+                .iter()
+                .all(|m| m.name == "ok" && m.duration_us == 10)
+        );
+        assert_eq!(instance.get_monitor_max_us().get("lossy"), None);
+
+        // Every slice opened in the trace was closed again (except the still open ones)
+        let is_monitor = |name: &str| name == "lossy" || name == "ok";
+        let begins = events
+            .iter()
+            .filter(|e| matches!(e, TracingEvent::Begin { name, .. } if is_monitor(name)))
+            .count();
+        let ends = events
+            .iter()
+            .filter(|e| matches!(e, TracingEvent::End { name: Some(name), .. } if is_monitor(name)))
+            .count();
+        assert_eq!(begins - ends, instance.get_monitor_depth());
+    }
+
+    #[test]
+    fn test_monitor_end_from_other_core() {
+        let mut instance =
+            TracingInstance::new(FirmwareAddressMap::default(), TracingOptions::default());
+        let receiver = instance.get_trace_event_receiver();
+
+        let send = |instance: &mut TracingInstance, ts: f64, event: &str| {
+            let log_line = LogLine::from_str(&format!("{ts:.6} [INFO ] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        };
+        send(
+            &mut instance,
+            0.001,
+            "@EVENT_MONITOR_START(function_name=work,core_id=0)",
+        );
+        send(
+            &mut instance,
+            0.002,
+            "@EVENT_MONITOR_START(function_name=work,core_id=1)",
+        );
+        // Both cores have `work` open ==> every end closes the monitor of its own core
+        send(
+            &mut instance,
+            0.003,
+            "@EVENT_MONITOR_END(function_name=work,core_id=1)",
+        );
+        // Only open on core 0 ==> dropped with a warning, the monitor of core 0 stays open
+        send(
+            &mut instance,
+            0.004,
+            "@EVENT_MONITOR_END(function_name=work,core_id=1)",
+        );
+        assert_eq!(instance.get_monitor_depth(), 1);
+        send(
+            &mut instance,
+            0.005,
+            "@EVENT_MONITOR_END(function_name=work,core_id=0)",
+        );
+        // Nothing open anymore ==> ignored
+        send(
+            &mut instance,
+            0.006,
+            "@EVENT_MONITOR_END(function_name=work,core_id=0)",
+        );
+
+        assert_eq!(instance.get_monitor_depth(), 0);
+        assert_eq!(instance.get_mismatched_monitor_ends(), 1);
+        let events: Vec<_> = receiver.try_iter().collect();
+        let ends: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                TracingEvent::End { tid, ts, .. } => Some((*tid, *ts)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ends, vec![(Some(1), 3000), (Some(0), 5000)]);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TracingEvent::Instant { name, tid: Some(1), ts: 4000, args, .. }
+                if name == "Monitor end from other core" && args["open_on_core"] == "0"
+        )));
+    }
+
+    #[test]
+    fn test_collapse_recursion() {
+        let options = TracingOptions {
+            collapse_recursion: true,
+            collect_monitors: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // fib(3) calling fib(2) calling fib(1), then again with a sensor read in between
+        for (ts, name, start) in [
+            (0.0, "fib", true),
+            (0.1, "fib", true),
+            (0.2, "fib", true),
+            (0.3, "fib", false),
+            (0.4, "fib", false),
+            (0.5, "fib", false),
+            (1.0, "fib", true),
+            (1.1, "sensor_read", true),
+            (1.2, "fib", true),
+            (1.3, "fib", false),
+            (1.4, "sensor_read", false),
+            (1.5, "fib", false),
+        ] {
+            instance.update(&monitor_event(ts, name, start));
+        }
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let begins: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TracingEvent::Begin { name, ts, .. } => Some((name.as_str(), *ts)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            begins,
+            vec![
+                ("fib", 0),
+                ("fib", 1_000_000),
+                ("sensor_read", 1_100_000),
+                ("fib", 1_200_000)
+            ]
+        );
+        let ends: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TracingEvent::End { ts, args, .. } => {
+                    Some((*ts, args.get("recursion_depth").cloned()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ends,
+            vec![
+                (500_000, Some("3".to_string())),
+                (1_300_000, None),
+                (1_400_000, None),
+                (1_500_000, None)
+            ]
+        );
+
+        // Every call is still in the statistics
+        assert_eq!(instance.drain_completed_monitors().len(), 6);
+    }
+
+    #[test]
+    fn test_collapse_recursion_per_task_and_within_one_tick() {
+        let options = TracingOptions {
+            collapse_recursion: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // Task 10 recurses within one µs and suspends inside fib, then task 11 calls fib
+        let events = [
+            (0, "@EVENT_MONITOR_START(function_name=fib,core_id=0)"),
+            (0, "@EVENT_MONITOR_START(function_name=fib,core_id=0)"),
+            (1, "@EVENT_MONITOR_END(function_name=fib,core_id=0)"),
+            (1, "@EVENT_MONITOR_END(function_name=fib,core_id=0)"),
+            (
+                10,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                10,
+                "@EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=11)",
+            ),
+            (
+                10,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                10,
+                "@EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            ),
+            (10, "@EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)"),
+            (
+                11,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (12, "@EVENT_MONITOR_START(function_name=fib,core_id=0)"),
+            (
+                13,
+                "@EVENT_EMBASSY_TASK_EXEC_END(executor_id=1,core_id=0,task_id=10)",
+            ),
+            (
+                14,
+                "@EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=11)",
+            ),
+            (15, "@EVENT_MONITOR_START(function_name=fib,core_id=0)"),
+        ];
+        for (ts_us, event) in events {
+            let log_line =
+                LogLine::from_str(&format!("{:.6} [TRACE] {event}", ts_us as f64 / 1e6)).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let depths: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TracingEvent::End {
+                    name: Some(name),
+                    ts,
+                    args,
+                    ..
+                } if name == "fib" => Some((*ts, args.get("recursion_depth").cloned())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, vec![(1, Some("2".to_string()))]);
+
+        // The call of task 11 is not swallowed by the suspended call of task 10
+        let fib_begins: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TracingEvent::Begin { name, ts, .. } if name == "fib" => Some(*ts),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fib_begins, vec![0, 12, 15]);
+    }
+
+    #[test]
+    fn test_follow_target_reset() {
+        let options = TracingOptions {
+            follow_resets: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // The target resets while task 10 is running and boots again
+        let events = [
+            "2.000000 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "2.000100 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "2.000200 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "2.000300 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "0.000050 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "0.000100 [INFO ] @EVENT_MARKER(name=Booted,core_id=0)",
+        ];
+        for event in events {
+            let log_line = LogLine::from_str(event).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        assert_eq!(instance.get_target_resets(), 1);
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let reset_ts = events
+            .iter()
+            .find_map(|e| match e {
+                TracingEvent::Instant { name, ts, .. } if name == "Target reset" => Some(*ts),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(reset_ts, 2_000_300);
+
+        // Every slice of the previous run is closed at the reset
+        let open_slices = |until: u128| {
+            events.iter().fold(0i64, |open, e| match e {
+                TracingEvent::Begin { ts, .. } if *ts <= until => open + 1,
+                TracingEvent::End { ts, .. } if *ts <= until => open - 1,
+                _ => open,
+            })
+        };
+        assert_eq!(open_slices(reset_ts), 0);
+
+        // The new run continues after the reset
+        let marker_ts = events
+            .iter()
+            .find_map(|e| match e {
+                TracingEvent::Instant { name, ts, .. } if name == "Booted" => Some(*ts),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(marker_ts, 2_000_400);
+    }
+
+    #[test]
+    fn test_follow_soft_reset() {
+        let options = TracingOptions {
+            follow_resets: true,
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        // The target restarts while task 10 is running, but its clock keeps counting
+        let events = [
+            "1.000000 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "1.000100 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "1.000200 [TRACE] @EVENT_EMBASSY_POLL_START(executor_id=1,core_id=0)",
+            "1.000300 [TRACE] @EVENT_EMBASSY_TASK_EXEC_BEGIN(executor_id=1,core_id=0,task_id=10)",
+            "1.500000 [TRACE] @EVENT_EMBASSY_TASK_NEW(executor_id=1,core_id=0,task_id=10)",
+            "1.500100 [TRACE] @EVENT_EMBASSY_TASK_READY_BEGIN(executor_id=1,core_id=0,task_id=10)",
+        ];
+        for event in events {
+            let log_line = LogLine::from_str(event).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+        assert_eq!(instance.get_target_resets(), 1);
+        assert_eq!(instance.get_tracked_task_count(), 1);
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        let (reset_ts, reset_args) = events
+            .iter()
+            .find_map(|e| match e {
+                TracingEvent::Instant { name, ts, args, .. } if name == "Target reset" => {
+                    Some((*ts, args.clone()))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(reset_ts, 1_500_000);
+        assert_eq!(reset_args["reason"], "task spawned again");
+        assert_eq!(reset_args["time_base_us"], "0");
+
+        // The running slice of the previous run is closed at the reset
+        assert!(events.iter().any(|e| matches!(
+            e,
+            TracingEvent::End { ts, tid: Some(10), .. } if *ts == 1_500_000
+        )));
+    }
+
+    #[test]
+    fn test_metric_panels() {
+        let options = TracingOptions {
+            metric_panels: serde_json::from_str(
+                r#"{"panels": [{"name": "Motor", "metrics": ["motor.current", "motor.voltage"], "bands": true}]}"#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let mut instance = TracingInstance::new(FirmwareAddressMap::default(), options);
+        let receiver = instance.get_trace_event_receiver();
+
+        for event in [
+            "@EVENT_METRIC(name=motor.current,value=1.5,unit=A,core_id=0)",
+            "@EVENT_METRIC(name=temperature,value=21,core_id=0)",
+        ] {
+            let log_line = LogLine::from_str(&format!("1.0 [INFO ] {event}")).unwrap();
+            instance.update(&LogEvent::from_log_line(&log_line).unwrap());
+        }
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            TracingEvent::Metadata { name, pid: 1000, args, .. }
+                if name == "process_name" && args["name"] == "Motor"
+        )));
+        let counters: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                TracingEvent::Counter { name, pid, .. } => Some((name.as_str(), *pid)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                ("motor.current [A]", Some(1000)),
+                ("motor.current.min [A]", Some(1000)),
+                ("motor.current.max [A]", Some(1000)),
+                ("temperature", None),
+            ]
+        );
+        assert_eq!(
+            instance.get_unseen_panel_metrics(),
+            vec!["motor.voltage".to_string()]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Live dashboard in the terminal (`--tui`, behind the `tui` feature)
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use crossbeam::{channel::Receiver, select};
+use ratatui::{
+    Frame, Terminal,
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::{Hide, Show},
+        execute,
+        terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    widgets::{Block, Gauge, Paragraph, Row, Table},
+};
+
+use crate::perfetto_backend::trace_event::TracingEvent;
+
+/// Redraw interval of the dashboard
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+/// Capacity of the trace event sink of the dashboard
+pub const SINK_CAPACITY: usize = 100_000;
+/// Number of tasks listed as busiest
+const TOP_TASK_COUNT: usize = 5;
+
+/// Live statistics derived from the trace events
+#[derive(Default)]
+struct DashboardStats {
+    /// Latest device timestamp (µs)
+    latest_ts: u128,
+    /// Per core: (busy µs since the last refresh, start of the running executor slice)
+    cores: HashMap<u32, (u128, Option<u128>)>,
+    /// Device time (µs) of the last refresh
+    window_start_ts: Option<u128>,
+    /// Per (executor, task): (running µs in total, start of the current state if running)
+    tasks: HashMap<(u32, u32), (u128, Option<u128>)>,
+    /// Task names from the thread metadata
+    task_names: HashMap<(u32, u32), String>,
+    /// Latest value per metric track
+    metrics: HashMap<String, f64>,
+    /// Events received since the last refresh
+    event_count: u64,
+}
+
+impl DashboardStats {
+    fn update(&mut self, event: &TracingEvent) {
+        self.event_count += 1;
+        match event {
+            TracingEvent::Metadata {
+                name,
+                pid,
+                tid: Some(tid),
+                args,
+                ..
+            } if name == "thread_name" && *pid != 0 => {
+                if let Some(task_name) = args.get("name") {
+                    self.task_names.insert((*pid, *tid), task_name.clone());
+                }
+            }
+            // Running executor on the core overview track
+            TracingEvent::Begin {
+                cat,
+                pid: 0,
+                tid: Some(core),
+                ts,
+                ..
+            } if cat.as_deref() == Some("executor") => {
+                self.latest_ts = self.latest_ts.max(*ts);
+                self.cores.entry(*core).or_default().1 = Some(*ts);
+            }
+            TracingEvent::End {
+                cat,
+                pid: 0,
+                tid: Some(core),
+                ts,
+                args,
+                ..
+            } if cat.as_deref() == Some("executor") => {
+                self.latest_ts = self.latest_ts.max(*ts);
+                // short idle periods merged into the slice (--merge-idle-us) are not busy time
+                let merged_idle_us = args
+                    .get("merged_idle_us")
+                    .and_then(|value| value.parse::<u128>().ok())
+                    .unwrap_or(0);
+                let (busy_us, running_since) = self.cores.entry(*core).or_default();
+                if let Some(since) = running_since.take() {
+                    *busy_us += ts.saturating_sub(since).saturating_sub(merged_idle_us);
+                }
+            }
+            // Task states (slice view)
+            TracingEvent::Begin {
+                name,
+                pid,
+                tid: Some(tid),
+                ts,
+                ..
+            } if *pid != 0 => {
+                self.latest_ts = self.latest_ts.max(*ts);
+                self.tasks.entry((*pid, *tid)).or_default().1 = (name == "Running").then_some(*ts);
+            }
+            TracingEvent::End {
+                pid,
+                tid: Some(tid),
+                ts,
+                ..
+            } if *pid != 0 => {
+                self.latest_ts = self.latest_ts.max(*ts);
+                if let Some((running_us, running_since)) = self.tasks.get_mut(&(*pid, *tid))
+                    && let Some(since) = running_since.take()
+                {
+                    *running_us += ts.saturating_sub(since);
+                }
+            }
+            TracingEvent::Counter { name, ts, args, .. } => {
+                self.latest_ts = self.latest_ts.max(*ts);
+                if let Some(value) = args.get("value") {
+                    self.metrics.insert(name.clone(), *value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Utilization (0..=1) per core since the last refresh. Resets the window
+    fn take_core_utilization(&mut self) -> Vec<(u32, f64)> {
+        let window_start_ts = self.window_start_ts.replace(self.latest_ts);
+        let window_us = window_start_ts.map(|start| self.latest_ts.saturating_sub(start));
+
+        let mut utilization: Vec<(u32, f64)> = self
+            .cores
+            .iter_mut()
+            .map(|(core, (busy_us, running_since))| {
+                // Count the running slice up to now and continue it in the next window
+                if let Some(since) = running_since {
+                    *busy_us += self.latest_ts.saturating_sub(*since);
+                    *since = self.latest_ts;
+                }
+                let busy = std::mem::take(busy_us);
+                let ratio = match window_us {
+                    Some(window_us) if window_us > 0 => busy as f64 / window_us as f64,
+                    _ => 0.0,
+                };
+                (*core, ratio.min(1.0))
+            })
+            .collect();
+        utilization.sort_by_key(|(core, _)| *core);
+        utilization
+    }
+
+    /// Tasks with the most running time (name, µs)
+    fn top_tasks(&self) -> Vec<(String, u128)> {
+        let mut tasks: Vec<(String, u128)> = self
+            .tasks
+            .iter()
+            .map(|(key, (running_us, _))| {
+                let name = self
+                    .task_names
+                    .get(key)
+                    .cloned()
+                    .unwrap_or_else(|| format!("Task 0x{:X}", key.1));
+                (name, *running_us)
+            })
+            .collect();
+        tasks.sort_by_key(|(_, running_us)| std::cmp::Reverse(*running_us));
+        tasks.truncate(TOP_TASK_COUNT);
+        tasks
+    }
+}
+
+/// Keeps the dashboard on the alternate screen and hands the terminal back when dropped, also if
+/// the dashboard thread panics. Raw mode stays off, so Ctrl+C still ends the session
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> std::io::Result<Self> {
+        execute!(std::io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stdout(), LeaveAlternateScreen, Show);
+    }
+}
+
+/// Draw the dashboard on the terminal until the trace event sink closes or `stop` is dropped.
+/// `dropped_events` counts the events the tracing instance could not hand to the consumers
+pub fn spawn_tui(
+    trace_event_recver: Receiver<TracingEvent>,
+    dropped_events: Arc<AtomicU64>,
+    stop: Receiver<()>,
+) -> JoinHandle<anyhow::Result<()>> {
+    std::thread::spawn(move || {
+        let _terminal_guard = TerminalGuard::enter().context("Failed to set up the terminal")?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+        terminal.clear()?;
+
+        let mut stats = DashboardStats::default();
+        let mut last_refresh = Instant::now();
+        loop {
+            select! {
+                recv(trace_event_recver) -> event => match event {
+                    Ok(event) => stats.update(&event),
+                    Err(_) => break, // channel closed
+                },
+                recv(stop) -> _ => break,
+                default(REFRESH_INTERVAL) => {}
+            }
+
+            let elapsed = last_refresh.elapsed();
+            if elapsed >= REFRESH_INTERVAL {
+                last_refresh = Instant::now();
+                let view = DashboardView::take(
+                    &mut stats,
+                    elapsed,
+                    dropped_events.load(Ordering::Relaxed),
+                );
+                terminal.draw(|frame| view.render(frame))?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Numbers shown by one refresh of the dashboard
+struct DashboardView {
+    header: String,
+    core_utilization: Vec<(u32, f64)>,
+    top_tasks: Vec<(String, u128)>,
+    metrics: Vec<(String, f64)>,
+}
+
+impl DashboardView {
+    /// Take the numbers of the refresh window from the statistics
+    fn take(stats: &mut DashboardStats, elapsed: Duration, dropped_events: u64) -> Self {
+        let header = format!(
+            "rustmeter  |  device time {:.3} s  |  {:.0} events/s  |  {dropped_events} dropped",
+            stats.latest_ts as f64 / 1_000_000.0,
+            std::mem::take(&mut stats.event_count) as f64 / elapsed.as_secs_f64()
+        );
+        let mut metrics: Vec<(String, f64)> = stats
+            .metrics
+            .iter()
+            .map(|(name, value)| (name.clone(), *value))
+            .collect();
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            header,
+            core_utilization: stats.take_core_utilization(),
+            top_tasks: stats.top_tasks(),
+            metrics,
+        }
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [header_area, cores_area, tasks_area, metrics_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(self.core_utilization.len() as u16 + 2),
+            Constraint::Length(TOP_TASK_COUNT as u16 + 2),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(Paragraph::new(self.header.as_str()), header_area);
+
+        let cores_block = Block::bordered().title("Core utilization");
+        let core_rows = Layout::vertical(vec![Constraint::Length(1); self.core_utilization.len()])
+            .split(cores_block.inner(cores_area));
+        frame.render_widget(cores_block, cores_area);
+        for ((core, utilization), row) in self.core_utilization.iter().zip(core_rows.iter()) {
+            let gauge = Gauge::default()
+                .label(format!("CORE {core}  {:5.1}%", utilization * 100.0))
+                .ratio(*utilization);
+            frame.render_widget(gauge, *row);
+        }
+
+        let task_rows = self.top_tasks.iter().map(|(name, running_us)| {
+            Row::new([
+                name.clone(),
+                format!("{:>10.3} ms", *running_us as f64 / 1000.0),
+            ])
+        });
+        let tasks = Table::new(task_rows, [Constraint::Fill(1), Constraint::Length(14)])
+            .block(Block::bordered().title("Busiest tasks (running time)"));
+        frame.render_widget(tasks, tasks_area);
+
+        let metric_rows = self
+            .metrics
+            .iter()
+            .map(|(name, value)| Row::new([name.clone(), value.to_string()]));
+        let metrics = Table::new(metric_rows, [Constraint::Fill(1), Constraint::Length(20)])
+            .block(Block::bordered().title("Metrics"));
+        frame.render_widget(metrics, metrics_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dashboard_stats() {
+        let mut stats = DashboardStats::default();
+        let events = [
+            TracingEvent::Metadata {
+                name: "thread_name".to_string(),
+                cat: None,
+                pid: 1,
+                tid: Some(10),
+                args: HashMap::from([("name".to_string(), "blink".to_string())]),
+            },
+            TracingEvent::Begin {
+                name: "Running".to_string(),
+                cat: None,
+                ts: 100,
+                pid: 1,
+                tid: Some(10),
+                args: HashMap::new(),
+                cname: None,
+            },
+            TracingEvent::Begin {
+                name: "Executor".to_string(),
+                cat: Some("executor".to_string()),
+                ts: 100,
+                pid: 0,
+                tid: Some(0),
+                args: HashMap::new(),
+                cname: None,
+            },
+            TracingEvent::End {
+                name: None,
+                cat: None,
+                pid: 1,
+                tid: Some(10),
+                ts: 400,
+                args: HashMap::new(),
+            },
+            TracingEvent::End {
+                name: None,
+                cat: Some("executor".to_string()),
+                pid: 0,
+                tid: Some(0),
+                ts: 400,
+                args: HashMap::new(),
+            },
+            TracingEvent::Counter {
+                name: "temperature".to_string(),
+                cat: None,
+                pid: None,
+                ts: 1100,
+                args: HashMap::from([("value".to_string(), 21.5)]),
+            },
+        ];
+
+        stats.window_start_ts = Some(0);
+        for event in &events {
+            stats.update(event);
+        }
+
+        assert_eq!(stats.take_core_utilization(), vec![(0, 300.0 / 1100.0)]);
+        assert_eq!(stats.top_tasks(), vec![("blink".to_string(), 300)]);
+        assert_eq!(stats.metrics["temperature"], 21.5);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::perfetto_backend::trace_event::TracingEvent;
+
+/// Broken invariant of a trace and the event that breaks it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub message: String,
+    pub event: serde_json::Value,
+}
+
+impl Violation {
+    fn new(message: impl Into<String>, event: &serde_json::Value) -> Self {
+        Self {
+            message: message.into(),
+            event: event.clone(),
+        }
+    }
+}
+
+/// Timestamp, pid and tid of the events that sit on a slice track (metadata, counters and
+/// global instants have none)
+fn slice_track(event: &TracingEvent) -> Option<(u128, u32, Option<u32>)> {
+    match event {
+        TracingEvent::Complete { ts, pid, tid, .. } => Some((*ts, *pid, Some(*tid))),
+        TracingEvent::Begin { ts, pid, tid, .. } | TracingEvent::End { ts, pid, tid, .. } => {
+            Some((*ts, *pid, *tid))
+        }
+        TracingEvent::Instant {
+            ts,
+            pid: Some(pid),
+            tid,
+            ..
+        } => Some((*ts, *pid, *tid)),
+        _ => None,
+    }
+}
+
+/// Result of checking the events of a trace
+#[derive(Debug, Default)]
+pub struct TraceCheck<'a> {
+    pub violations: Vec<Violation>,
+    /// Begin events without End at the end of the trace. Not a violation: rustmeter leaves the
+    /// latest state of every task and executor open, and Perfetto draws it up to the trace end
+    pub open_slices: Vec<&'a serde_json::Value>,
+}
+
+/// Check the invariants of Perfetto trace events (in file order):
+/// - every End closes a Begin on its (pid, tid) track
+/// - timestamps do not decrease per slice track and per counter track
+/// - Complete durations are not negative
+/// - every referenced process and thread is named by a metadata event
+pub fn validate_trace_events(raw_events: &[serde_json::Value]) -> TraceCheck<'_> {
+    let mut violations = Vec::new();
+    let mut events = Vec::new();
+    for raw_event in raw_events {
+        // would be rejected by the u64 field with a less helpful message
+        if raw_event["ph"] == "X" && raw_event["dur"].as_i64().is_some_and(|dur| dur < 0) {
+            violations.push(Violation::new(
+                "Complete event with negative duration",
+                raw_event,
+            ));
+            continue;
+        }
+        match serde_json::from_value::<TracingEvent>(raw_event.clone()) {
+            Ok(event) => events.push((event, raw_event)),
+            Err(e) => violations.push(Violation::new(
+                format!("Invalid trace event: {e}"),
+                raw_event,
+            )),
+        }
+    }
+
+    let mut process_names = HashSet::new();
+    let mut thread_names = HashSet::new();
+    for (event, _) in &events {
+        if let TracingEvent::Metadata { name, pid, tid, .. } = event {
+            match name.as_str() {
+                "process_name" => {
+                    process_names.insert(*pid);
+                }
+                "thread_name" => {
+                    thread_names.insert((*pid, *tid));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut open_slices: HashMap<(u32, Option<u32>), Vec<&serde_json::Value>> = HashMap::new();
+    let mut slice_track_ts: HashMap<(u32, Option<u32>), u128> = HashMap::new();
+    let mut counter_track_ts: HashMap<(Option<u32>, &str), u128> = HashMap::new();
+    let mut unnamed_processes = HashSet::new();
+    let mut unnamed_threads = HashSet::new();
+    for (event, raw_event) in &events {
+        if let Some((ts, pid, tid)) = slice_track(event) {
+            let track = (pid, tid);
+            if let Some(last_ts) = slice_track_ts.insert(track, ts)
+                && ts < last_ts
+            {
+                violations.push(Violation::new(
+                    format!(
+                        "Timestamp goes back from {last_ts} to {ts} on track pid {pid} tid {tid:?}"
+                    ),
+                    raw_event,
+                ));
+            }
+
+            match event {
+                TracingEvent::Begin { .. } => open_slices.entry(track).or_default().push(raw_event),
+                TracingEvent::End { .. }
+                    if open_slices.entry(track).or_default().pop().is_none() =>
+                {
+                    violations.push(Violation::new(
+                        format!("End without Begin on track pid {pid} tid {tid:?}"),
+                        raw_event,
+                    ));
+                }
+                _ => {}
+            }
+
+            if let Some(tid) = tid
+                && !thread_names.contains(&(pid, Some(tid)))
+                && unnamed_threads.insert((pid, tid))
+            {
+                violations.push(Violation::new(
+                    format!("No thread_name metadata for pid {pid} tid {tid}"),
+                    raw_event,
+                ));
+            }
+        }
+
+        if let TracingEvent::Counter { name, pid, ts, .. } = event
+            && let Some(last_ts) = counter_track_ts.insert((*pid, name.as_str()), *ts)
+            && *ts < last_ts
+        {
+            violations.push(Violation::new(
+                format!("Timestamp goes back from {last_ts} to {ts} on counter '{name}'"),
+                raw_event,
+            ));
+        }
+
+        let pid = match event {
+            TracingEvent::Metadata { .. } => None,
+            TracingEvent::Complete { pid, .. }
+            | TracingEvent::Begin { pid, .. }
+            | TracingEvent::End { pid, .. }
+            | TracingEvent::AsyncBegin { pid, .. }
+            | TracingEvent::AsyncEnd { pid, .. }
+            | TracingEvent::FlowStart { pid, .. }
+            | TracingEvent::FlowEnd { pid, .. } => Some(*pid),
+            TracingEvent::Instant { pid, .. } | TracingEvent::Counter { pid, .. } => *pid,
+        };
+        if let Some(pid) = pid
+            && !process_names.contains(&pid)
+            && unnamed_processes.insert(pid)
+        {
+            violations.push(Violation::new(
+                format!("No process_name metadata for pid {pid}"),
+                raw_event,
+            ));
+        }
+    }
+
+    let mut open_slices: Vec<_> = open_slices.into_values().flatten().collect();
+    open_slices.sort_by_key(|raw_event| raw_event["ts"].as_u64());
+    TraceCheck {
+        violations,
+        open_slices,
+    }
+}
+
+/// Load a written Perfetto trace and check its invariants (`--validate`). Fails if any is
+/// broken, so the check can gate a CI job
+pub fn run_validate(trace_filename: &Path) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(trace_filename)
+        .with_context(|| format!("Failed to read trace {}", trace_filename.display()))?;
+    let json: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Trace {} is not valid JSON", trace_filename.display()))?;
+    let raw_events = json["traceEvents"].as_array().ok_or(anyhow::anyhow!(
+        "Trace {} has no traceEvents",
+        trace_filename.display()
+    ))?;
+
+    let check = validate_trace_events(raw_events);
+    for violation in &check.violations {
+        println!("{}", violation.message);
+        println!("    {}", violation.event);
+    }
+    if !check.open_slices.is_empty() {
+        println!(
+            "{} slice(s) still open at the end of the trace (drawn up to the trace end)",
+            check.open_slices.len()
+        );
+    }
+
+    if !check.violations.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} violation(s) in {} trace events",
+            check.violations.len(),
+            raw_events.len()
+        ));
+    }
+    println!("{} trace events ok", raw_events.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn messages(raw_events: &[serde_json::Value]) -> Vec<String> {
+        validate_trace_events(raw_events)
+            .violations
+            .into_iter()
+            .map(|violation| violation.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_trace() {
+        let raw_events = [
+            json!({"ph": "M", "name": "process_name", "pid": 1, "args": {"name": "Executor"}}),
+            json!({"ph": "M", "name": "thread_name", "pid": 1, "tid": 10, "args": {"name": "Task"}}),
+            json!({"ph": "B", "name": "Running", "pid": 1, "tid": 10, "ts": 100}),
+            json!({"ph": "X", "name": "Wake (ISR)", "pid": 1, "tid": 10, "ts": 150, "dur": 0}),
+            json!({"ph": "E", "pid": 1, "tid": 10, "ts": 200}),
+            json!({"ph": "C", "name": "temperature", "ts": 50, "args": {"value": 21.5}}),
+        ];
+        let check = validate_trace_events(&raw_events);
+        assert!(check.violations.is_empty());
+        assert!(check.open_slices.is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let raw_events = [
+            json!({"ph": "M", "name": "process_name", "pid": 1, "args": {"name": "Executor"}}),
+            json!({"ph": "B", "name": "Running", "pid": 1, "ts": 100}),
+            json!({"ph": "E", "pid": 1, "ts": 200}),
+            json!({"ph": "E", "pid": 1, "ts": 300}),
+            json!({"ph": "B", "name": "Waiting", "pid": 1, "ts": 250}),
+            json!({"ph": "X", "name": "Wake (ISR)", "pid": 1, "tid": 10, "ts": 400, "dur": -5}),
+            json!({"ph": "B", "name": "Running", "pid": 2, "tid": 7, "ts": 500}),
+            json!({"ph": "C", "name": "temperature", "ts": 50, "args": {"value": 1.0}}),
+            json!({"ph": "C", "name": "temperature", "ts": 40, "args": {"value": 2.0}}),
+            json!({"ph": "Q", "name": "unknown", "pid": 1, "ts": 600}),
+        ];
+        let messages = messages(&raw_events);
+        assert_eq!(messages.len(), 7, "{messages:#?}");
+        assert_eq!(messages[0], "Complete event with negative duration");
+        assert!(messages[1].starts_with("Invalid trace event"));
+        assert_eq!(messages[2], "End without Begin on track pid 1 tid None");
+        assert_eq!(
+            messages[3],
+            "Timestamp goes back from 300 to 250 on track pid 1 tid None"
+        );
+        assert_eq!(messages[4], "No thread_name metadata for pid 2 tid 7");
+        assert_eq!(messages[5], "No process_name metadata for pid 2");
+        assert_eq!(
+            messages[6],
+            "Timestamp goes back from 50 to 40 on counter 'temperature'"
+        );
+        assert_eq!(validate_trace_events(&raw_events).open_slices.len(), 2);
+    }
+
+    #[test]
+    fn test_selftest_trace_is_valid() {
+        let filename =
+            std::env::temp_dir().join(format!("rustmeter-validate-{}.json", std::process::id()));
+        crate::selftest::run_selftest(&filename).unwrap();
+        let result = run_validate(&filename);
+        std::fs::remove_file(&filename).unwrap();
+        result.unwrap();
+    }
+}